@@ -1,7 +1,14 @@
-use std::io;
+use std::io::{self, BufRead, BufReader};
+use std::ops::Range;
+use std::path::Path;
+use std::time::SystemTime;
 use thiserror::Error;
+use unicode_width::UnicodeWidthChar;
 use log::{debug, error, info, warn};
 
+use crate::syntax::IndentStyle;
+use crate::undo::Splice;
+
 #[derive(Error, Debug)]
 pub enum BufferError {
     #[error("File not found: {0}")]
@@ -12,36 +19,424 @@ pub enum BufferError {
     InvalidLineIndex(usize),
     #[error("Invalid column index: {0} in line {1}")]
     InvalidColumnIndex(usize, usize),
+    #[error("Cannot write a scratch buffer")]
+    ScratchBuffer,
+    #[error("File changed on disk")]
+    ChangedOnDisk,
+    #[error("File is not valid UTF-8")]
+    InvalidUtf8,
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Vim's word classes: runs of keyword chars and runs of punctuation are
+/// separate words, whitespace separates them.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum CharClass {
+    Blank,
+    Punctuation,
+    Word,
+}
+
+fn char_class(c: char) -> CharClass {
+    if c.is_whitespace() {
+        CharClass::Blank
+    } else if is_word_char(c) {
+        CharClass::Word
+    } else {
+        CharClass::Punctuation
+    }
+}
+
+/// Terminal cells `c` takes up: two for wide (CJK, fullwidth) characters.
+/// Zero-width and control characters still get a cell of their own so every
+/// char stays visible and addressable.
+pub fn char_width(c: char) -> usize {
+    c.width().unwrap_or(1).max(1)
+}
+
+/// Cells taken up by `text` when drawn without tab expansion.
+pub fn display_width(text: &str) -> usize {
+    text.chars().map(char_width).sum()
+}
+
+/// Screen column of char `char_col` in `line` once tabs are expanded to
+/// stops every `tab_width` columns and wide characters take two cells.
+pub fn visual_col(line: &str, char_col: usize, tab_width: usize) -> usize {
+    let tab_width = tab_width.max(1);
+    line.chars().take(char_col).fold(0, |col, c| {
+        if c == '\t' { (col / tab_width + 1) * tab_width } else { col + char_width(c) }
+    })
+}
+
+/// `visual_col(line, i, tab_width)` for every `i` from 0 to the line length,
+/// in a single pass.
+pub fn visual_cols(line: &str, tab_width: usize) -> Vec<usize> {
+    let tab_width = tab_width.max(1);
+    let mut cols = vec![0];
+    let mut col = 0;
+    for c in line.chars() {
+        col = if c == '\t' { (col / tab_width + 1) * tab_width } else { col + char_width(c) };
+        cols.push(col);
+    }
+    cols
+}
+
+/// The char drawn at screen column `visual` of `line`, the inverse of
+/// [`visual_col`]. A column inside a tab or a wide character gives that
+/// char; one past the end gives the line length.
+pub fn char_col(line: &str, visual: usize, tab_width: usize) -> usize {
+    let tab_width = tab_width.max(1);
+    let mut col = 0;
+    for (index, c) in line.chars().enumerate() {
+        col = if c == '\t' { (col / tab_width + 1) * tab_width } else { col + char_width(c) };
+        if col > visual {
+            return index;
+        }
+    }
+    line.chars().count()
+}
+
+/// The chars at which each display row of `line` starts when it is wrapped
+/// at `width` screen columns; the first is always 0. A char that would cross
+/// the right edge, such as a wide one or a tab, starts the next row instead.
+/// Tabs still expand against the whole line, so char `i` in the row starting
+/// at char `s` is drawn `visual_col(line, i) - visual_col(line, s)` columns
+/// into that row.
+pub fn wrap_starts(line: &str, width: usize, tab_width: usize) -> Vec<usize> {
+    let (width, tab_width) = (width.max(1), tab_width.max(1));
+    let mut starts = vec![0];
+    let (mut row_col, mut col) = (0, 0);
+    for (index, c) in line.chars().enumerate() {
+        let next = if c == '\t' { (col / tab_width + 1) * tab_width } else { col + char_width(c) };
+        // a char too wide for even an empty row stays where it is
+        if next - row_col > width && col > row_col {
+            starts.push(index);
+            row_col = col;
+        }
+        col = next;
+    }
+    starts
+}
+
+/// Leading whitespace `width` columns wide: spaces when `style` expands
+/// tabs, otherwise as many tabs as fit and spaces for the rest.
+pub fn indent_string(width: usize, style: IndentStyle) -> String {
+    let tab_width = style.tab_width.max(1);
+    if style.expand_tab {
+        " ".repeat(width)
+    } else {
+        format!("{}{}", "\t".repeat(width / tab_width), " ".repeat(width % tab_width))
+    }
+}
+
+/// Char columns at which `word` occurs in `line` as a whole word.
+fn whole_word_matches(line: &str, word: &[char]) -> Vec<usize> {
+    let chars: Vec<char> = line.chars().collect();
+    if word.is_empty() || chars.len() < word.len() {
+        return Vec::new();
+    }
+    (0..=chars.len() - word.len())
+        .filter(|&start| {
+            let end = start + word.len();
+            chars[start..end] == *word
+                && (start == 0 || !is_word_char(chars[start - 1]))
+                && (end == chars.len() || !is_word_char(chars[end]))
+        })
+        .collect()
+}
+
+/// Finds the text a substitution replaces. Plain strings match literally;
+/// a regex engine can implement this to give `:s` patterns.
+pub trait Matcher {
+    /// Byte range of the first match in `text` starting at or after byte `from`.
+    fn find_at(&self, text: &str, from: usize) -> Option<Range<usize>>;
+}
+
+impl Matcher for str {
+    fn find_at(&self, text: &str, from: usize) -> Option<Range<usize>> {
+        if self.is_empty() {
+            return None;
+        }
+        text[from..].find(self).map(|start| from + start..from + start + self.len())
+    }
+}
+
+/// One step of a line diff, describing the buffer relative to another text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiffOp {
+    Unchanged(String),
+    /// Present in the buffer but not in the other text.
+    Added(String),
+    /// Present in the other text but not in the buffer.
+    Removed(String),
+}
+
+/// How lines are terminated in the file a buffer was read from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    Crlf,
+}
+
+impl LineEnding {
+    fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::Crlf => "\r\n",
+        }
+    }
+
+    /// Whichever ending most lines of `text` use; LF when there is a tie.
+    fn detect(text: &str) -> Self {
+        let crlf = text.matches("\r\n").count();
+        let lf = text.matches('\n').count() - crlf;
+        if crlf > lf { LineEnding::Crlf } else { LineEnding::Lf }
+    }
+}
+
+/// Splits file contents into lines, noting the line ending and whether the
+/// last line was terminated. There is always at least one line.
+fn parse_text(text: &str) -> (Vec<String>, LineEnding, bool) {
+    let mut lines: Vec<String> = text.lines().map(|s| s.to_string()).collect();
+    if lines.is_empty() {
+        lines.push(String::new());
+    }
+    (lines, LineEnding::detect(text), text.ends_with('\n'))
+}
+
+/// Text read from another file or a command's output, as lines to put
+/// into a buffer. Unlike a whole file, empty text gives no lines at all.
+pub fn text_lines(bytes: &[u8]) -> Vec<String> {
+    let (text, _, _) = decode(bytes);
+    text.lines().map(str::to_string).collect()
+}
+
+const BOM: &str = "\u{feff}";
+
+/// Most cells of the table `diff_against` fills in, as for 2000 changed
+/// lines against 2000; bigger changes are not worth the memory.
+const DIFF_CELL_LIMIT: usize = 4_000_000;
+
+/// Line diff of `ours` against `other` from their longest common
+/// subsequence of lines.
+fn lcs_diff(other: &[String], ours: &[String]) -> Vec<DiffOp> {
+    let (n, m) = (other.len(), ours.len());
+    // lcs[i][j] = LCS length of other[i..] and ours[j..]
+    let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if other[i] == ours[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut ops = Vec::with_capacity(n.max(m));
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if other[i] == ours[j] {
+            ops.push(DiffOp::Unchanged(ours[j].clone()));
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            ops.push(DiffOp::Removed(other[i].clone()));
+            i += 1;
+        } else {
+            ops.push(DiffOp::Added(ours[j].clone()));
+            j += 1;
+        }
+    }
+    ops.extend(other[i..].iter().cloned().map(DiffOp::Removed));
+    ops.extend(ours[j..].iter().cloned().map(DiffOp::Added));
+    ops
+}
+
+/// File bytes as text: a leading UTF-8 byte-order mark is taken off and
+/// invalid sequences become U+FFFD. Also says whether there was a BOM and
+/// whether anything had to be replaced.
+fn decode(bytes: &[u8]) -> (String, bool, bool) {
+    let (bytes, bom) = match bytes.strip_prefix(BOM.as_bytes()) {
+        Some(rest) => (rest, true),
+        None => (bytes, false),
+    };
+    match String::from_utf8_lossy(bytes) {
+        std::borrow::Cow::Borrowed(text) => (text.to_string(), bom, false),
+        std::borrow::Cow::Owned(text) => (text, bom, true),
+    }
 }
 
+/// What happened to a buffer's file behind its back.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiskChange {
+    Modified,
+    Deleted,
+}
+
+fn file_mtime(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Where the cursor is in a buffer and how big it is, as `g Ctrl-g` reports
+/// it. Positions count from 1, and line endings count as chars.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BufferStats {
+    pub line: usize,
+    pub lines: usize,
+    pub col: usize,
+    pub char: usize,
+    pub chars: usize,
+    pub words: usize,
+}
+
+/// An intra-line search: `f` and `F` land on `target`, `t` and `T` stop
+/// just before it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CharFind {
+    pub target: char,
+    pub forward: bool,
+    pub till: bool,
+}
+
+/// Called with the written path after every successful save.
+pub type PostSaveHook = Box<dyn Fn(&Path)>;
+
+#[derive(Default)]
 pub struct Buffer {
     pub file: Option<String>,
     pub lines: Vec<String>,
     pub modified: bool,
+    /// Scratch buffers hold throwaway text: they are never written to disk
+    /// and never count as having unsaved changes.
+    pub scratch: bool,
+    pub post_save_hook: Option<PostSaveHook>,
+    /// A crash recovery file newer than `file`, waiting for the user to
+    /// restore or discard it.
+    pub recovery: Option<String>,
+    /// Written between lines on save, as found in the file.
+    pub line_ending: LineEnding,
+    /// Whether the last line is followed by a line ending on save.
+    pub trailing_newline: bool,
+    /// The file started with a UTF-8 byte-order mark, written back on save.
+    pub bom: bool,
+    /// The file wasn't valid UTF-8. The bad bytes were replaced by U+FFFD,
+    /// so saving would not write back what was read.
+    pub invalid_utf8: bool,
+    /// The file's modification time when it was last read or saved; `None`
+    /// until it exists on disk.
+    pub mtime: Option<SystemTime>,
+    /// The edits made to `lines` since the undo history last took them.
+    pub edits: Vec<Splice>,
+}
+
+/// Whether two paths name the same file, as written or once resolved.
+pub fn same_file(a: &str, b: &str) -> bool {
+    a == b || std::fs::canonicalize(a).ok().is_some_and(|a| std::fs::canonicalize(b).ok() == Some(a))
+}
+
+/// `path` with a leading `~` or `~/` replaced by the home directory. Other
+/// paths, including `~user`, are returned as they are.
+pub fn expand_tilde(path: &str) -> String {
+    let rest = match path.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => rest,
+        _ => return path.to_string(),
+    };
+    match dirs::home_dir() {
+        Some(home) => format!("{}{}", home.display(), rest),
+        None => path.to_string(),
+    }
+}
+
+/// Makes the directories a file is about to be written into.
+fn create_parent_dirs(file_path: &str) -> Result<(), BufferError> {
+    if Path::new(file_path).exists() {
+        debug!("File exists, overwriting");
+        return Ok(());
+    }
+    let parent = Path::new(file_path).parent().ok_or_else(|| {
+        warn!("Invalid path provided for save");
+        BufferError::FileNotFound("Invalid path".to_string())
+    })?;
+    debug!("Creating directory structure: {:?}", parent);
+    std::fs::create_dir_all(parent)?;
+    Ok(())
+}
+
+fn recovery_path(file: &str) -> String {
+    format!("{}.recovery", file)
+}
+
+/// The recovery file left behind for `file`, if it is newer than the file.
+fn newer_recovery(file: &str) -> Option<String> {
+    let path = recovery_path(file);
+    let recovered = std::fs::metadata(&path).and_then(|m| m.modified()).ok()?;
+    let original = std::fs::metadata(file).and_then(|m| m.modified()).ok()?;
+    (recovered > original).then_some(path)
 }
 
 impl Buffer {
     pub fn from_file(file: Option<String>) -> Result<Self, BufferError> {
-        let lines = match &file {
-            Some(file_path) => {
-                info!("Opening file: {}", file_path);
-                if !std::path::Path::new(file_path).exists() {
-                    warn!("File not found: {}", file_path);
-                    return Err(BufferError::FileNotFound(file_path.clone()));
-                }
-                let content: Vec<String> = std::fs::read_to_string(file_path)?
-                    .lines()
-                    .map(|s| s.to_string())
-                    .collect();
-                debug!("Read {} lines from file", content.len());
-                content
-            }
-            None => {
-                info!("Creating new empty buffer");
-                vec![String::new()]
-            }
+        let Some(file_path) = file else {
+            info!("Creating new empty buffer");
+            return Ok(Self { lines: vec![String::new()], ..Default::default() });
         };
-        Ok(Self { file, lines, modified: false })
+        info!("Opening file: {}", file_path);
+        if !std::path::Path::new(&file_path).exists() {
+            warn!("File not found: {}", file_path);
+            return Err(BufferError::FileNotFound(file_path));
+        }
+        let reader = BufReader::new(std::fs::File::open(&file_path)?);
+        let mut buffer = Self::from_reader(reader, Some(file_path.clone()))?;
+        buffer.recovery = newer_recovery(&file_path);
+        if let Some(path) = &buffer.recovery {
+            warn!("Found recovery file {}", path);
+        }
+        buffer.mtime = file_mtime(&file_path);
+        Ok(buffer)
+    }
+
+    /// Reads a buffer from `reader`, such as stdin, giving it `name` as the
+    /// file it will be saved to. Nothing about that file is looked at.
+    pub fn from_reader(mut reader: impl BufRead, name: Option<String>) -> Result<Self, BufferError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        let (text, bom, invalid_utf8) = decode(&bytes);
+        let display = name.as_deref().unwrap_or("input");
+        if invalid_utf8 {
+            warn!("{} is not valid UTF-8, replaced the invalid bytes", display);
+        }
+        let (lines, line_ending, trailing_newline) = parse_text(&text);
+        debug!("Read {} lines ({:?} endings) from {}", lines.len(), line_ending, display);
+        Ok(Self { file: name, lines, line_ending, trailing_newline, bom, invalid_utf8, ..Default::default() })
+    }
+
+    /// A buffer for a file that doesn't exist yet; saving creates it.
+    pub fn new_file(file: String) -> Self {
+        info!("Starting new file: {}", file);
+        Self { file: Some(file), lines: vec![String::new()], ..Default::default() }
+    }
+
+    pub fn scratch() -> Self {
+        info!("Creating scratch buffer");
+        Self { lines: vec![String::new()], scratch: true, ..Default::default() }
+    }
+
+    /// Whether the file on disk can't be written, going by its permissions.
+    pub fn is_write_protected(&self) -> bool {
+        self.file
+            .as_ref()
+            .and_then(|file| std::fs::metadata(file).ok())
+            .is_some_and(|metadata| metadata.permissions().readonly())
+    }
+
+    /// Whether quitting now would lose work the user might care about.
+    pub fn has_unsaved_changes(&self) -> bool {
+        self.modified && !self.scratch
     }
 
     pub fn len(&self) -> usize {
@@ -53,42 +448,214 @@ impl Buffer {
             .ok_or(BufferError::InvalidLineIndex(index))
     }
 
-    pub fn get_line_mut(&mut self, index: usize) -> Result<&mut String, BufferError> {
-        self.lines.get_mut(index)
-            .ok_or(BufferError::InvalidLineIndex(index))
+    /// Notes that `removed` lines from `start` are about to give way to
+    /// `inserted` others, keeping what they hold so undo can put it back.
+    fn record_edit(&mut self, start: usize, removed: usize, inserted: usize) {
+        let removed = self.lines[start..start + removed].to_vec();
+        self.edits.push(Splice { start, removed, inserted });
+        self.modified = true;
     }
 
-    pub fn insert_char(&mut self, line: usize, col: usize, c: char) -> Result<(), BufferError> {
-        {
-            let line_content = self.get_line_mut(line)?;
-            if col > line_content.len() {
-                return Err(BufferError::InvalidColumnIndex(col, line));
-            }
-            line_content.insert(col, c);
+    /// The edits made to `lines` since this was last called, oldest first.
+    pub fn take_edits(&mut self) -> Vec<Splice> {
+        std::mem::take(&mut self.edits)
+    }
+
+    /// Columns are char positions; this finds the matching byte offset,
+    /// allowing the position just past the last char.
+    fn byte_offset(&self, line: usize, col: usize) -> Result<usize, BufferError> {
+        let line_content = self.get_line(line)?;
+        match line_content.char_indices().nth(col) {
+            Some((offset, _)) => Ok(offset),
+            None if col == line_content.chars().count() => Ok(line_content.len()),
+            None => Err(BufferError::InvalidColumnIndex(col, line)),
         }
-        self.modified = true;
+    }
+
+    pub fn insert_char(&mut self, line: usize, col: usize, c: char) -> Result<(), BufferError> {
+        let offset = self.byte_offset(line, col)?;
+        self.record_edit(line, 1, 1);
+        self.lines[line].insert(offset, c);
         Ok(())
     }
 
     pub fn remove_char(&mut self, line: usize, col: usize) -> Result<char, BufferError> {
-        let removed = {
-            let line_content = self.get_line_mut(line)?;
-            if col >= line_content.len() {
-                return Err(BufferError::InvalidColumnIndex(col, line));
-            }
-            line_content.remove(col)
+        let offset = self.byte_offset(line, col)?;
+        if offset >= self.lines[line].len() {
+            return Err(BufferError::InvalidColumnIndex(col, line));
+        }
+        self.record_edit(line, 1, 1);
+        Ok(self.lines[line].remove(offset))
+    }
+
+    /// Removes everything before `col` on `line`, returning it. Nothing
+    /// changes at column 0.
+    pub fn delete_to_line_start(&mut self, line: usize, col: usize) -> Result<String, BufferError> {
+        let offset = self.byte_offset(line, col)?;
+        if offset == 0 {
+            return Ok(String::new());
+        }
+        self.record_edit(line, 1, 1);
+        Ok(self.lines[line].drain(..offset).collect())
+    }
+
+    /// Removes everything from `col` to the end of `line`, returning it.
+    pub fn delete_to_line_end(&mut self, line: usize, col: usize) -> Result<String, BufferError> {
+        let offset = self.byte_offset(line, col)?;
+        if offset == self.lines[line].len() {
+            return Ok(String::new());
+        }
+        self.record_edit(line, 1, 1);
+        Ok(self.lines[line].split_off(offset))
+    }
+
+    /// Overwrites the char at `col`, returning the one it replaced.
+    pub fn replace_char(&mut self, line: usize, col: usize, c: char) -> Result<char, BufferError> {
+        let offset = self.byte_offset(line, col)?;
+        let old = self.lines[line][offset..]
+            .chars()
+            .next()
+            .ok_or(BufferError::InvalidColumnIndex(col, line))?;
+        self.record_edit(line, 1, 1);
+        self.lines[line].replace_range(offset..offset + old.len_utf8(), c.encode_utf8(&mut [0; 4]));
+        Ok(old)
+    }
+
+    /// Breaks a line in two at `col`, moving the rest of it onto a new line below.
+    pub fn split_line(&mut self, line: usize, col: usize) -> Result<(), BufferError> {
+        let offset = self.byte_offset(line, col)?;
+        self.record_edit(line, 1, 2);
+        let tail = self.lines[line].split_off(offset);
+        self.lines.insert(line + 1, tail);
+        Ok(())
+    }
+
+    /// Inserts `text` (one entry per line) at `col`, splitting the line when
+    /// the text spans several. Returns the position just past the inserted text.
+    pub fn insert_text(&mut self, line: usize, col: usize, text: &[String]) -> Result<(usize, usize), BufferError> {
+        let offset = self.byte_offset(line, col)?;
+        let Some((last, rest)) = text.split_last() else {
+            return Ok((line, col));
         };
-        self.modified = true;
-        Ok(removed)
+        self.record_edit(line, 1, text.len());
+        let tail = self.lines[line].split_off(offset);
+        let end_line = line + rest.len();
+        let end_col = if rest.is_empty() { col } else { 0 } + last.chars().count();
+        let mut pieces = text.iter();
+        if let Some(first) = pieces.next() {
+            self.lines[line].push_str(first);
+        }
+        self.lines.splice(line + 1..line + 1, pieces.cloned());
+        self.lines[end_line].push_str(&tail);
+        Ok((end_line, end_col))
+    }
+
+    /// Inserts `text` at `col`, where each `\n` starts a new line. Returns
+    /// the position just past the inserted text.
+    pub fn insert_str(&mut self, line: usize, col: usize, text: &str) -> Result<(usize, usize), BufferError> {
+        let lines: Vec<String> = text.split('\n').map(str::to_string).collect();
+        self.insert_text(line, col, &lines)
+    }
+
+    /// Inserts whole lines so the first of them becomes line `index`.
+    pub fn insert_lines(&mut self, index: usize, lines: &[String]) -> Result<(), BufferError> {
+        if index > self.lines.len() {
+            return Err(BufferError::InvalidLineIndex(index));
+        }
+        self.record_edit(index, 0, lines.len());
+        self.lines.splice(index..index, lines.iter().cloned());
+        Ok(())
     }
 
+    /// Length of a line in chars, the unit cursor columns are counted in.
     pub fn line_length(&self, index: usize) -> Result<usize, BufferError> {
-        self.get_line(index).map(|line| line.len())
+        self.get_line(index).map(|line| line.chars().count())
+    }
+
+    /// Column of the first non-whitespace character, or the line length if
+    /// the line is blank.
+    pub fn first_non_blank(&self, index: usize) -> usize {
+        self.lines
+            .get(index)
+            .map(|line| line.chars().take_while(|c| c.is_whitespace()).count())
+            .unwrap_or(0)
+    }
+
+    /// The leading whitespace of a line.
+    pub fn indentation(&self, index: usize) -> String {
+        self.lines
+            .get(index)
+            .map(|line| line.chars().take_while(|c| c.is_whitespace()).collect())
+            .unwrap_or_default()
+    }
+
+    /// Replaces the leading whitespace of a line with `indent`, returning the
+    /// column where the text after it starts.
+    pub fn reindent(&mut self, index: usize, indent: &str) -> Result<usize, BufferError> {
+        let line = format!("{}{}", indent, self.get_line(index)?.trim_start());
+        self.record_edit(index, 1, 1);
+        self.lines[index] = line;
+        Ok(indent.chars().count())
+    }
+
+    /// Adds `shift_width` columns to a line's indentation, rebuilding it in
+    /// `style`. Empty lines are left alone.
+    pub fn indent_line(&mut self, index: usize, shift_width: usize, style: IndentStyle) -> Result<(), BufferError> {
+        if self.get_line(index)?.is_empty() {
+            return Ok(());
+        }
+        let width = self.indent_width(index, style.tab_width);
+        self.reindent(index, &indent_string(width + shift_width, style)).map(|_| ())
+    }
+
+    /// Takes `shift_width` columns off a line's indentation, or all of it
+    /// when there is less, rebuilding what remains in `style`.
+    pub fn dedent_line(&mut self, index: usize, shift_width: usize, style: IndentStyle) -> Result<(), BufferError> {
+        let width = self.indent_width(index, style.tab_width);
+        if width == 0 {
+            return Ok(());
+        }
+        self.reindent(index, &indent_string(width.saturating_sub(shift_width), style)).map(|_| ())
+    }
+
+    /// Screen columns taken by a line's indentation.
+    fn indent_width(&self, index: usize, tab_width: usize) -> usize {
+        let line = self.lines.get(index).map(String::as_str).unwrap_or("");
+        visual_col(line, self.first_non_blank(index), tab_width)
+    }
+
+    /// Clamps a cursor position into the buffer. With `past_end`, as in
+    /// Insert mode, the cursor may sit just past the last character;
+    /// otherwise it must be on one.
+    pub fn clamp_position(&self, line: usize, col: usize, past_end: bool) -> (usize, usize) {
+        let line = line.min(self.lines.len().saturating_sub(1));
+        let len = self.line_length(line).unwrap_or(0);
+        let max_col = if past_end { len } else { len.saturating_sub(1) };
+        (line, col.min(max_col))
+    }
+
+    /// Counts for the cursor at `line` and `col`. Words are runs of
+    /// non-whitespace.
+    pub fn stats(&self, line: usize, col: usize) -> BufferStats {
+        let ending = self.line_ending.as_str().len();
+        let line_chars = |text: &String| text.chars().count();
+        let before: usize = self.lines[..line].iter().map(|text| line_chars(text) + ending).sum();
+        let text: usize = self.lines.iter().map(line_chars).sum();
+        let endings = (self.lines.len() - 1 + usize::from(self.trailing_newline)) * ending;
+        BufferStats {
+            line: line + 1,
+            lines: self.lines.len(),
+            col: col + 1,
+            char: before + col + 1,
+            chars: text + endings,
+            words: self.lines.iter().map(|text| text.split_whitespace().count()).sum(),
+        }
     }
 
     pub fn display_name(&self) -> String {
         match &self.file {
             Some(path) => path.clone(),
+            None if self.scratch => "[Scratch]".to_string(),
             None => "[No Name]".to_string(),
         }
     }
@@ -98,87 +665,591 @@ impl Buffer {
             return Err(BufferError::InvalidLineIndex(line_index));
         }
 
+        self.get_line(line_index)?;
+        self.record_edit(line_index - 1, 2, 1);
         let current_line = self.lines.remove(line_index);
-        let previous_length = {
-            let previous_line = self.get_line_mut(line_index - 1)?;
-            let len = previous_line.len();
-            previous_line.push_str(&current_line);
-            len
-        };
-        self.modified = true;
+        let previous_line = &mut self.lines[line_index - 1];
+        let previous_length = previous_line.chars().count();
+        previous_line.push_str(&current_line);
         Ok(previous_length)
     }
 
-    pub fn delete_line(&mut self, index: usize) -> Result<(), BufferError> {
-        if self.lines.is_empty() {
-            return Err(BufferError::InvalidLineIndex(index));
+    /// Joins the line after `line_index` onto it, as `J` does: the joined
+    /// line loses its leading whitespace and a single space separates the
+    /// two unless either side is empty. Returns the column of the join.
+    pub fn join_next_line(&mut self, line_index: usize) -> Result<usize, BufferError> {
+        if line_index + 1 >= self.lines.len() {
+            return Err(BufferError::InvalidLineIndex(line_index + 1));
         }
-        if self.lines.len() == 1 {
-            // keep a single empty line
-            self.lines[0].clear();
-            self.modified = true;
-            return Ok(());
+        self.record_edit(line_index, 2, 1);
+        let next_line = self.lines.remove(line_index + 1);
+        let next_line = next_line.trim_start();
+        let line = &mut self.lines[line_index];
+        let col = line.chars().count();
+        if !line.is_empty() && !next_line.is_empty() {
+            line.push(' ');
         }
-        if index >= self.lines.len() {
-            return Err(BufferError::InvalidLineIndex(index));
+        line.push_str(next_line);
+        Ok(col)
+    }
+
+    /// Byte bounds of the chars from `start` to `end` (both inclusive) that
+    /// fall on `line`, for a range spanning `start.0..=end.0`.
+    fn range_on_line(&self, line: usize, start: (usize, usize), end: (usize, usize)) -> Result<(usize, usize), BufferError> {
+        let len = self.line_length(line)?;
+        let from = if line == start.0 { start.1.min(len) } else { 0 };
+        let to = if line == end.0 { (end.1 + 1).min(len) } else { len };
+        Ok((self.byte_offset(line, from)?, self.byte_offset(line, to.max(from))?))
+    }
+
+    /// The text from `start` to `end` inclusive, one entry per line touched.
+    pub fn text_range(&self, start: (usize, usize), end: (usize, usize)) -> Vec<String> {
+        (start.0..=end.0)
+            .filter_map(|line| {
+                let (from, to) = self.range_on_line(line, start, end).ok()?;
+                Some(self.lines[line][from..to].to_string())
+            })
+            .collect()
+    }
+
+    /// Removes the text from `start` to `end` inclusive, joining what is left
+    /// of the first and last lines. Returns the removed text.
+    pub fn delete_range(&mut self, start: (usize, usize), end: (usize, usize)) -> Result<Vec<String>, BufferError> {
+        if end.0 >= self.lines.len() {
+            return Err(BufferError::InvalidLineIndex(end.0));
+        }
+        let removed = self.text_range(start, end);
+        let (head, _) = self.range_on_line(start.0, start, end)?;
+        let (_, tail) = self.range_on_line(end.0, start, end)?;
+        let joined = format!("{}{}", &self.lines[start.0][..head], &self.lines[end.0][tail..]);
+        self.record_edit(start.0, end.0 + 1 - start.0, 1);
+        self.lines.splice(start.0..=end.0, [joined]);
+        Ok(removed)
+    }
+
+    /// Removes lines `start` to `end` inclusive, returning them. Removing
+    /// every line leaves a single empty one.
+    pub fn delete_lines(&mut self, start: usize, end: usize) -> Result<Vec<String>, BufferError> {
+        if start > end || end >= self.lines.len() {
+            return Err(BufferError::InvalidLineIndex(end));
+        }
+        let count = end + 1 - start;
+        let emptied = count == self.lines.len();
+        self.record_edit(start, count, usize::from(emptied));
+        let removed: Vec<String> = self.lines.drain(start..=end).collect();
+        if emptied {
+            self.lines.push(String::new());
+        }
+        Ok(removed)
+    }
+
+    /// Removes trailing spaces and tabs from a line, returning whether
+    /// anything was removed.
+    pub fn trim_line_trailing(&mut self, index: usize) -> Result<bool, BufferError> {
+        let line = self.get_line(index)?;
+        let trimmed_len = line.trim_end_matches([' ', '\t']).len();
+        if trimmed_len == line.len() {
+            return Ok(false);
+        }
+        self.record_edit(index, 1, 1);
+        self.lines[index].truncate(trimmed_len);
+        Ok(true)
+    }
+
+    /// Trims trailing whitespace from every line and returns how many lines changed.
+    pub fn strip_trailing_whitespace(&mut self) -> usize {
+        let mut changed = 0;
+        for index in 0..self.lines.len() {
+            if let Ok(true) = self.trim_line_trailing(index) {
+                changed += 1;
+            }
+        }
+        debug!("Stripped trailing whitespace from {} lines", changed);
+        changed
+    }
+
+    /// Replaces the first match on each of `lines`, or every match when
+    /// `global` is set. Returns the number of replacements and the lines
+    /// they were made on.
+    pub fn substitute(
+        &mut self,
+        lines: Range<usize>,
+        matcher: &(impl Matcher + ?Sized),
+        replacement: &str,
+        global: bool,
+    ) -> (usize, Vec<usize>) {
+        let mut count = 0;
+        let mut changed = Vec::new();
+        for index in lines.start..lines.end.min(self.lines.len()) {
+            let line = &self.lines[index];
+            let mut result = String::with_capacity(line.len());
+            let (mut from, mut replaced) = (0, false);
+            while let Some(found) = matcher.find_at(line, from) {
+                result.push_str(&line[from..found.start]);
+                result.push_str(replacement);
+                count += 1;
+                replaced = true;
+                from = found.end;
+                if found.is_empty() {
+                    // an empty match has to step over a char to move on
+                    let Some(c) = line[from..].chars().next() else { break };
+                    result.push(c);
+                    from += c.len_utf8();
+                }
+                if !global {
+                    break;
+                }
+            }
+            if !replaced {
+                continue;
+            }
+            result.push_str(&line[from..]);
+            self.record_edit(index, 1, 1);
+            self.lines[index] = result;
+            changed.push(index);
+        }
+        debug!("Made {} substitutions", count);
+        (count, changed)
+    }
+
+    pub fn line_chars(&self, line: usize) -> Vec<char> {
+        self.lines.get(line).map(|l| l.chars().collect()).unwrap_or_default()
+    }
+
+    /// Last cursor position in the buffer, used when a motion runs off the end.
+    fn last_position(&self) -> (usize, usize) {
+        let line = self.lines.len().saturating_sub(1);
+        (line, self.line_length(line).unwrap_or(0).saturating_sub(1))
+    }
+
+    /// Start of the next word (`w`). Empty lines count as words.
+    pub fn next_word_start(&self, line: usize, col: usize) -> (usize, usize) {
+        let (mut line, mut col) = (line, col);
+        let chars = self.line_chars(line);
+        if let Some(&c) = chars.get(col) {
+            let class = char_class(c);
+            if class != CharClass::Blank {
+                while col < chars.len() && char_class(chars[col]) == class {
+                    col += 1;
+                }
+            }
+        }
+        loop {
+            let chars = self.line_chars(line);
+            while col < chars.len() && char_class(chars[col]) == CharClass::Blank {
+                col += 1;
+            }
+            if col < chars.len() {
+                return (line, col);
+            }
+            if line + 1 >= self.lines.len() {
+                return self.last_position();
+            }
+            line += 1;
+            col = 0;
+            if self.lines[line].is_empty() {
+                return (line, 0);
+            }
+        }
+    }
+
+    /// Start of the current or previous word (`b`). Empty lines count as words.
+    pub fn prev_word_start(&self, line: usize, col: usize) -> (usize, usize) {
+        let (mut line, mut col) = (line, col);
+        let mut chars = self.line_chars(line);
+        loop {
+            if col == 0 {
+                if line == 0 {
+                    return (0, 0);
+                }
+                line -= 1;
+                chars = self.line_chars(line);
+                col = chars.len();
+                if chars.is_empty() {
+                    return (line, 0);
+                }
+            }
+            col -= 1;
+            if char_class(chars[col]) != CharClass::Blank {
+                break;
+            }
+        }
+        let class = char_class(chars[col]);
+        while col > 0 && char_class(chars[col - 1]) == class {
+            col -= 1;
+        }
+        (line, col)
+    }
+
+    /// End of the current or next word (`e`).
+    pub fn word_end(&self, line: usize, col: usize) -> (usize, usize) {
+        let (mut line, mut col) = (line, col + 1);
+        loop {
+            let chars = self.line_chars(line);
+            while col < chars.len() && char_class(chars[col]) == CharClass::Blank {
+                col += 1;
+            }
+            if col < chars.len() {
+                let class = char_class(chars[col]);
+                while col + 1 < chars.len() && char_class(chars[col + 1]) == class {
+                    col += 1;
+                }
+                return (line, col);
+            }
+            if line + 1 >= self.lines.len() {
+                return self.last_position();
+            }
+            line += 1;
+            col = 0;
+        }
+    }
+
+    /// Where the text removed by `dw` from `col` ends (exclusive): the next
+    /// word start when it is on the same line, otherwise the end of the line.
+    pub fn word_delete_end(&self, line: usize, col: usize) -> usize {
+        let chars = self.line_chars(line);
+        let (next_line, next_col) = self.next_word_start(line, col);
+        // at the end of the buffer `w` stops on the last char, which is not
+        // necessarily the start of a word
+        let starts_word = next_col > 0
+            && chars.get(next_col).is_some_and(|&c| {
+                char_class(c) != CharClass::Blank && char_class(c) != char_class(chars[next_col - 1])
+            });
+        if next_line == line && next_col > col && ((next_line, next_col) != self.last_position() || starts_word) {
+            next_col
+        } else {
+            chars.len()
+        }
+    }
+
+    /// Where `f`, `F`, `t` or `T` lands from `col`: the `count`th `target`
+    /// after (or before) it on the line, or beside it for `till`. `None` when
+    /// there are fewer than `count` of them.
+    pub fn find_char(&self, line: usize, col: usize, find: CharFind, count: usize) -> Option<usize> {
+        let chars: Vec<char> = self.lines.get(line)?.chars().collect();
+        let matches = |i: &usize| chars[*i] == find.target;
+        if find.forward {
+            let found = (col + 1..chars.len()).filter(matches).nth(count.saturating_sub(1))?;
+            Some(if find.till { found - 1 } else { found })
+        } else {
+            let found = (0..col.min(chars.len())).rev().filter(matches).nth(count.saturating_sub(1))?;
+            Some(if find.till { found + 1 } else { found })
         }
-        self.lines.remove(index);
-        self.modified = true;
-        Ok(())
     }
 
-    pub fn save(&self) -> Result<(), BufferError> {
+    /// Where Insert-mode `Ctrl-w` deletes back to from `col`: the start of
+    /// the word before the cursor, taking any blanks after it too. Only
+    /// blanks before the cursor go back to the start of the line.
+    pub fn word_delete_start(&self, line: usize, col: usize) -> usize {
+        match self.prev_word_start(line, col) {
+            (start_line, start) if start_line == line => start,
+            _ => 0,
+        }
+    }
+
+    /// The `[start, end)` columns of the inner word (`iw`) at `col`: the run
+    /// of word chars, punctuation or blanks the cursor is on.
+    pub fn inner_word(&self, line: usize, col: usize) -> Option<(usize, usize)> {
+        let chars = self.line_chars(line);
+        let class = char_class(*chars.get(col)?);
+        let start = chars[..col].iter().rposition(|&c| char_class(c) != class).map_or(0, |i| i + 1);
+        let end = chars[col..].iter().position(|&c| char_class(c) != class).map_or(chars.len(), |i| col + i);
+        Some((start, end))
+    }
+
+    /// Returns the keyword under or after the cursor on `line`, as used by `*` and `#`.
+    pub fn word_at(&self, line: usize, col: usize) -> Option<String> {
+        let chars: Vec<char> = self.get_line(line).ok()?.chars().collect();
+        let mut start = (col..chars.len()).find(|&i| is_word_char(chars[i]))?;
+        if start == col {
+            while start > 0 && is_word_char(chars[start - 1]) {
+                start -= 1;
+            }
+        }
+        let end = (start..chars.len())
+            .find(|&i| !is_word_char(chars[i]))
+            .unwrap_or(chars.len());
+        Some(chars[start..end].iter().collect())
+    }
+
+    /// Columns at which `word` occurs on a line as a whole word.
+    pub fn whole_word_columns(&self, line: usize, word: &str) -> Vec<usize> {
+        let word: Vec<char> = word.chars().collect();
+        self.lines.get(line).map_or_else(Vec::new, |text| whole_word_matches(text, &word))
+    }
+
+    /// Finds the next whole-word occurrence of `word` after (or, searching
+    /// backward, before) the given position, wrapping around the buffer.
+    /// Returns the `(line, col)` of the match and whether the search wrapped.
+    pub fn find_whole_word(
+        &self,
+        word: &str,
+        line: usize,
+        col: usize,
+        forward: bool,
+    ) -> Option<((usize, usize), bool)> {
+        let word: Vec<char> = word.chars().collect();
+        let total = self.lines.len();
+        for step in 0..=total {
+            let index = if forward {
+                (line + step) % total
+            } else {
+                (line + total * 2 - step) % total
+            };
+            let matches = whole_word_matches(&self.lines[index], &word);
+            let found = if forward {
+                matches.into_iter().find(|&c| match step {
+                    0 => c > col,
+                    s if s == total => c <= col,
+                    _ => true,
+                })
+            } else {
+                matches.into_iter().rev().find(|&c| match step {
+                    0 => c < col,
+                    s if s == total => c >= col,
+                    _ => true,
+                })
+            };
+            if let Some(c) = found {
+                let wrapped = step == total || if forward { index < line } else { index > line };
+                return Some(((index, c), wrapped));
+            }
+        }
+        None
+    }
+
+    /// The bracket matching the first of `()[]{}` at or after `col` on
+    /// `line`, as used by `%`. Nested pairs of the same kind are skipped and
+    /// the search crosses lines; `None` when there is no bracket to start
+    /// from or it is unbalanced.
+    pub fn find_matching_bracket(&self, line: usize, col: usize) -> Option<(usize, usize)> {
+        const PAIRS: [(char, char); 3] = [('(', ')'), ('[', ']'), ('{', '}')];
+        let chars = self.line_chars(line);
+        let (start, bracket) = chars.iter().copied().enumerate().skip(col).find(|&(_, c)| "()[]{}".contains(c))?;
+        let (forward, other) = PAIRS.iter().find_map(|&(open, close)| {
+            if bracket == open {
+                Some((true, close))
+            } else if bracket == close {
+                Some((false, open))
+            } else {
+                None
+            }
+        })?;
+        let mut depth = 0usize;
+        let mut index = line;
+        let mut chars = chars;
+        let mut pos = start;
+        loop {
+            let c = chars[pos];
+            if c == bracket {
+                depth += 1;
+            } else if c == other {
+                depth -= 1;
+                if depth == 0 {
+                    return Some((index, pos));
+                }
+            }
+            // step to the next char in the search direction, skipping empty lines
+            loop {
+                if forward && pos + 1 < chars.len() {
+                    pos += 1;
+                } else if !forward && pos > 0 {
+                    pos -= 1;
+                } else {
+                    index = if forward { index + 1 } else { index.checked_sub(1)? };
+                    chars = self.lines.get(index)?.chars().collect();
+                    if chars.is_empty() {
+                        continue;
+                    }
+                    pos = if forward { 0 } else { chars.len() - 1 };
+                }
+                break;
+            }
+        }
+    }
+
+    /// Line diff of the buffer against `other` (e.g. the file on disk). The
+    /// lines both share at the start and end are matched up first, and only
+    /// what lies between goes through the longest common subsequence; past
+    /// `DIFF_CELL_LIMIT` that part is just shown as replaced.
+    pub fn diff_against(&self, other: &[String]) -> Vec<DiffOp> {
+        let ours = &self.lines;
+        let head = other.iter().zip(ours).take_while(|(a, b)| a == b).count();
+        let tail = other[head..].iter().rev().zip(ours[head..].iter().rev()).take_while(|(a, b)| a == b).count();
+        let (other_mid, ours_mid) = (&other[head..other.len() - tail], &ours[head..ours.len() - tail]);
+
+        let mut ops = Vec::with_capacity(other.len().max(ours.len()));
+        ops.extend(ours[..head].iter().cloned().map(DiffOp::Unchanged));
+        let (n, m) = (other_mid.len(), ours_mid.len());
+        if (n + 1).saturating_mul(m + 1) > DIFF_CELL_LIMIT {
+            debug!("Diff of {} against {} lines is too large, showing it as replaced", m, n);
+            ops.extend(other_mid.iter().cloned().map(DiffOp::Removed));
+            ops.extend(ours_mid.iter().cloned().map(DiffOp::Added));
+        } else {
+            ops.extend(lcs_diff(other_mid, ours_mid));
+        }
+        ops.extend(ours[ours.len() - tail..].iter().cloned().map(DiffOp::Unchanged));
+        ops
+    }
+
+    /// The text written to disk: the lines joined with the buffer's line
+    /// ending, plus a final one if the file had it.
+    fn contents(&self) -> String {
+        let ending = self.line_ending.as_str();
+        let mut content = if self.bom { BOM.to_string() } else { String::new() };
+        content.push_str(&self.lines.join(ending));
+        if self.trailing_newline {
+            content.push_str(ending);
+        }
+        content
+    }
+
+    /// What has happened to the file on disk since it was last read or
+    /// saved, going by its modification time.
+    pub fn disk_change(&self) -> Option<DiskChange> {
+        let recorded = self.mtime?;
+        match self.file.as_deref().and_then(file_mtime) {
+            None => Some(DiskChange::Deleted),
+            Some(mtime) if mtime != recorded => Some(DiskChange::Modified),
+            Some(_) => None,
+        }
+    }
+
+    /// Writes the buffer to its file and returns the number of bytes written.
+    /// Refuses if something else changed the file since it was read or saved.
+    pub fn save(&mut self) -> Result<usize, BufferError> {
+        if self.disk_change() == Some(DiskChange::Modified) {
+            warn!("File changed on disk, not saving");
+            return Err(BufferError::ChangedOnDisk);
+        }
+        // writing the replacement chars back would lose the original bytes
+        if self.invalid_utf8 {
+            warn!("File is not valid UTF-8, not saving");
+            return Err(BufferError::InvalidUtf8);
+        }
+        self.force_save()
+    }
+
+    /// Like `save`, but overwrites the file even if it changed on disk or
+    /// was not valid UTF-8.
+    pub fn force_save(&mut self) -> Result<usize, BufferError> {
+        if self.scratch {
+            return Err(BufferError::ScratchBuffer);
+        }
         let file_path = self.file.as_ref()
             .ok_or_else(|| BufferError::FileNotFound("No file path set".to_string()))?;
         
-        let content = self.lines.join("\n");
+        let content = self.contents();
         std::fs::write(file_path, &content)?;
         debug!("Successfully saved {} bytes to {}", content.len(), file_path);
-        Ok(())
+        self.run_post_save_hook(Path::new(file_path));
+        self.mtime = file_mtime(file_path);
+        self.modified = false;
+        self.invalid_utf8 = false;
+        Ok(content.len())
     }
 
-    pub fn save_as(&mut self, file_path: String) -> Result<(), BufferError> {
+    pub fn save_as(&mut self, file_path: String) -> Result<usize, BufferError> {
+        if self.scratch {
+            return Err(BufferError::ScratchBuffer);
+        }
         info!("Saving as: {}", file_path);
-        if std::path::Path::new(&file_path).exists() {
-            debug!("File exists, overwriting");
-            let content = self.lines.join("\n");
-            std::fs::write(&file_path, &content)?;
-            debug!("Successfully saved {} bytes", content.len());
-            self.file = Some(file_path);
-            self.modified = false;
-            Ok(())
-        } else {
-            let parent = std::path::Path::new(&file_path)
-                .parent()
-                .ok_or_else(|| {
-                    warn!("Invalid path provided for save_as");
-                    BufferError::FileNotFound("Invalid path".to_string())
-                })?;
-            
-            debug!("Creating directory structure: {:?}", parent);
-            std::fs::create_dir_all(parent)?;
-            let content = self.lines.join("\n");
-            std::fs::write(&file_path, &content)?;
-            debug!("Successfully saved {} bytes", content.len());
-            self.file = Some(file_path);
-            self.modified = false;
-            Ok(())
+        if self.invalid_utf8 && self.file.as_deref().is_some_and(|file| same_file(file, &file_path)) {
+            warn!("File is not valid UTF-8, not saving over it");
+            return Err(BufferError::InvalidUtf8);
+        }
+        create_parent_dirs(&file_path)?;
+        let content = self.contents();
+        std::fs::write(&file_path, &content)?;
+        debug!("Successfully saved {} bytes", content.len());
+        self.run_post_save_hook(Path::new(&file_path));
+        self.mtime = file_mtime(&file_path);
+        self.file = Some(file_path);
+        self.modified = false;
+        self.invalid_utf8 = false;
+        Ok(content.len())
+    }
+
+    /// Writes a copy of the buffer to `file_path`, leaving its own file and
+    /// modified flag alone. Returns the number of bytes written.
+    pub fn write_to(&self, file_path: &str) -> Result<usize, BufferError> {
+        info!("Writing a copy to: {}", file_path);
+        create_parent_dirs(file_path)?;
+        let content = self.contents();
+        std::fs::write(file_path, &content)?;
+        debug!("Successfully wrote {} bytes", content.len());
+        Ok(content.len())
+    }
+
+    /// Re-reads the buffer's file from disk, discarding in-memory changes.
+    pub fn reload(&mut self) -> Result<(), BufferError> {
+        let file_path = self.file.clone()
+            .ok_or_else(|| BufferError::FileNotFound("No file path set".to_string()))?;
+        let (text, bom, invalid_utf8) = decode(&std::fs::read(&file_path)?);
+        let (lines, line_ending, trailing_newline) = parse_text(&text);
+        debug!("Reloaded {} lines from {}", lines.len(), file_path);
+        self.record_edit(0, self.lines.len(), lines.len());
+        self.lines = lines;
+        self.line_ending = line_ending;
+        self.trailing_newline = trailing_newline;
+        self.bom = bom;
+        self.invalid_utf8 = invalid_utf8;
+        self.mtime = file_mtime(&file_path);
+        self.modified = false;
+        Ok(())
+    }
+
+    /// The lines of the buffer's file as it is on disk, read the way opening
+    /// it would read them.
+    pub fn disk_lines(&self) -> Result<Vec<String>, BufferError> {
+        let file_path = self.file.as_ref()
+            .ok_or_else(|| BufferError::FileNotFound("No file path set".to_string()))?;
+        Ok(parse_text(&decode(&std::fs::read(file_path)?).0).0)
+    }
+
+    /// Replaces the contents with the pending recovery file. The result is
+    /// unsaved until written; the recovery file stays until then.
+    pub fn restore_recovery(&mut self) -> Result<(), BufferError> {
+        let Some(path) = self.recovery.take() else {
+            return Ok(());
+        };
+        let (lines, _, _) = parse_text(&decode(&std::fs::read(&path)?).0);
+        info!("Restored {} lines from {}", lines.len(), path);
+        self.record_edit(0, self.lines.len(), lines.len());
+        self.lines = lines;
+        Ok(())
+    }
+
+    /// Deletes the pending recovery file, keeping the contents read from disk.
+    pub fn discard_recovery(&mut self) {
+        if let Some(path) = self.recovery.take() {
+            match std::fs::remove_file(&path) {
+                Ok(()) => info!("Discarded recovery file {}", path),
+                Err(e) => warn!("Failed to remove recovery file {}: {}", path, e),
+            }
+        }
+    }
+
+    fn run_post_save_hook(&self, path: &Path) {
+        if let Some(hook) = &self.post_save_hook {
+            debug!("Running post-save hook for {:?}", path);
+            hook(path);
         }
     }
 
     /// Attempts to save any modified changes to a recovery file during a panic
     pub fn try_save_recovery(&self) {
-        if !self.modified {
-            debug!("Buffer not modified, skipping recovery save");
+        if !self.has_unsaved_changes() {
+            debug!("Buffer has no unsaved changes, skipping recovery save");
             return;
         }
 
         let recovery_path = match &self.file {
-            Some(path) => format!("{}.recovery", path),
+            Some(path) => recovery_path(path),
             None => ".unnamed.recovery".to_string(),
         };
 
-        let content = self.lines.join("\n");
+        let content = self.contents();
         if let Err(e) = std::fs::write(&recovery_path, &content) {
             error!("Failed to save recovery file: {}", e);
         } else {
@@ -186,3 +1257,806 @@ impl Buffer {
         }
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer_with(lines: &[&str]) -> Buffer {
+        Buffer {
+            lines: lines.iter().map(|s| s.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_trim_line_trailing() {
+        let mut buffer = buffer_with(&["foo  \t", "bar"]);
+        assert!(buffer.trim_line_trailing(0).unwrap());
+        assert!(!buffer.trim_line_trailing(1).unwrap());
+        assert_eq!(buffer.lines, vec!["foo", "bar"]);
+        assert!(buffer.modified);
+        assert!(buffer.trim_line_trailing(2).is_err());
+    }
+
+    #[test]
+    fn test_substitute_first_or_every_match() {
+        let mut buffer = buffer_with(&["a-a-a", "b", "a"]);
+        assert_eq!(buffer.substitute(0..1, "a", "xy", false), (1, vec![0]));
+        assert_eq!(buffer.lines, lines(&["xy-a-a", "b", "a"]));
+        assert_eq!(buffer.substitute(0..3, "a", "A", true), (3, vec![0, 2]));
+        assert_eq!(buffer.lines, lines(&["xy-A-A", "b", "A"]));
+        assert!(buffer.modified);
+
+        let mut clean = buffer_with(&["aaa"]);
+        assert_eq!(clean.substitute(0..1, "aa", "a", true), (1, vec![0]));
+        assert_eq!(clean.lines, lines(&["aa"]));
+        clean.modified = false;
+        assert_eq!(clean.substitute(0..1, "z", "y", true), (0, vec![]));
+        assert_eq!(clean.substitute(0..1, "", "y", true), (0, vec![]));
+        assert!(!clean.modified);
+    }
+
+    #[test]
+    fn test_strip_trailing_whitespace_counts_changed_lines() {
+        let mut buffer = buffer_with(&["a ", "b", "  ", "", "\tc\t"]);
+        assert_eq!(buffer.strip_trailing_whitespace(), 3);
+        assert_eq!(buffer.lines, vec!["a", "b", "", "", "\tc"]);
+
+        let mut blank = buffer_with(&[" \t ", "\t"]);
+        assert_eq!(blank.strip_trailing_whitespace(), 2);
+        assert_eq!(blank.lines, vec!["", ""]);
+        assert!(blank.modified);
+
+        let mut clean = buffer_with(&["a", "b"]);
+        assert_eq!(clean.strip_trailing_whitespace(), 0);
+        assert!(!clean.modified);
+    }
+
+    #[test]
+    fn test_multibyte_insert_and_remove() {
+        let mut buffer = buffer_with(&[""]);
+        for (col, c) in "café".chars().enumerate() {
+            buffer.insert_char(0, col, c).unwrap();
+        }
+        buffer.insert_char(0, 4, '!').unwrap();
+        assert_eq!(buffer.lines[0], "café!");
+        assert_eq!(buffer.line_length(0).unwrap(), 5);
+
+        assert_eq!(buffer.remove_char(0, 3).unwrap(), 'é');
+        assert_eq!(buffer.lines[0], "caf!");
+        assert!(buffer.insert_char(0, 6, 'x').is_err());
+    }
+
+    #[test]
+    fn test_split_and_join_multibyte_line() {
+        let mut buffer = buffer_with(&["naïve text"]);
+        buffer.split_line(0, 5).unwrap();
+        assert_eq!(buffer.lines, vec!["naïve", " text"]);
+        assert_eq!(buffer.join_with_previous_line(1).unwrap(), 5);
+        assert_eq!(buffer.lines, vec!["naïve text"]);
+    }
+
+    #[test]
+    fn test_indentation_and_reindent() {
+        let mut buffer = buffer_with(&["\t  code", "   ", "plain"]);
+        assert_eq!(buffer.indentation(0), "\t  ");
+        assert_eq!(buffer.indentation(1), "   ");
+        assert_eq!(buffer.indentation(2), "");
+        assert_eq!(buffer.reindent(2, "  ").unwrap(), 2);
+        assert_eq!(buffer.reindent(0, "").unwrap(), 0);
+        assert_eq!(buffer.lines, lines(&["code", "   ", "  plain"]));
+        assert!(buffer.reindent(3, " ").is_err());
+    }
+
+    #[test]
+    fn test_join_next_line() {
+        let mut buffer = buffer_with(&["fn main() {", "    body", "", "}"]);
+        assert_eq!(buffer.join_next_line(0).unwrap(), 11);
+        assert_eq!(buffer.lines, vec!["fn main() { body", "", "}"]);
+        assert_eq!(buffer.join_next_line(1).unwrap(), 0);
+        assert_eq!(buffer.lines, vec!["fn main() { body", "}"]);
+        assert!(buffer.join_next_line(1).is_err());
+        assert_eq!(buffer.lines.len(), 2);
+    }
+
+    #[test]
+    fn test_remove_char_at_end_of_line() {
+        let mut buffer = buffer_with(&["abc"]);
+        assert_eq!(buffer.remove_char(0, 2).unwrap(), 'c');
+        assert_eq!(buffer.lines[0], "ab");
+        assert!(matches!(buffer.remove_char(0, 2), Err(BufferError::InvalidColumnIndex(2, 0))));
+    }
+
+    #[test]
+    fn test_remove_char_on_empty_line() {
+        let mut buffer = buffer_with(&[""]);
+        assert!(buffer.remove_char(0, 0).is_err());
+        assert_eq!(buffer.lines[0], "");
+        assert!(!buffer.modified);
+    }
+
+    #[test]
+    fn test_clamp_position_on_shorter_line() {
+        let buffer = buffer_with(&["long line", "ab", ""]);
+        assert_eq!(buffer.clamp_position(1, 7, false), (1, 1));
+        assert_eq!(buffer.clamp_position(1, 7, true), (1, 2));
+        assert_eq!(buffer.clamp_position(2, 3, false), (2, 0));
+        assert_eq!(buffer.clamp_position(2, 3, true), (2, 0));
+        assert_eq!(buffer.clamp_position(0, 4, false), (0, 4));
+    }
+
+    #[test]
+    fn test_clamp_position_beyond_last_line() {
+        let buffer = buffer_with(&["one", "two three"]);
+        assert_eq!(buffer.clamp_position(10, 20, false), (1, 8));
+        assert_eq!(buffer.clamp_position(10, 20, true), (1, 9));
+    }
+
+    #[test]
+    fn test_visual_col_with_mixed_tabs_and_spaces() {
+        let line = "a\tb  \tc";
+        assert_eq!(visual_col(line, 0, 4), 0);
+        assert_eq!(visual_col(line, 1, 4), 1);
+        assert_eq!(visual_col(line, 2, 4), 4);
+        assert_eq!(visual_col(line, 5, 4), 7);
+        assert_eq!(visual_col(line, 6, 4), 8);
+        assert_eq!(visual_col("\t\tx", 2, 8), 16);
+        assert_eq!(visual_col("  \tx", 3, 2), 4);
+    }
+
+    #[test]
+    fn test_columns_with_wide_characters() {
+        let line = "a日本b";
+        assert_eq!(visual_col(line, 1, 4), 1);
+        assert_eq!(visual_col(line, 2, 4), 3);
+        assert_eq!(visual_col(line, 3, 4), 5);
+        assert_eq!(visual_col("日\tx", 2, 4), 4);
+        assert_eq!(char_col(line, 2, 4), 1);
+        assert_eq!(char_col(line, 3, 4), 2);
+        assert_eq!(char_col(line, 5, 4), 3);
+        assert_eq!(display_width("日本 ok"), 7);
+        assert_eq!(visual_cols("a日\tb", 4), vec![0, 1, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_find_char_forward_backward_and_till() {
+        let buffer = buffer_with(&["a,b,c,d"]);
+        let find = |target, forward, till| CharFind { target, forward, till };
+        assert_eq!(buffer.find_char(0, 0, find(',', true, false), 1), Some(1));
+        assert_eq!(buffer.find_char(0, 1, find(',', true, false), 1), Some(3));
+        assert_eq!(buffer.find_char(0, 0, find(',', true, false), 3), Some(5));
+        assert_eq!(buffer.find_char(0, 0, find('c', true, true), 1), Some(3));
+        assert_eq!(buffer.find_char(0, 6, find(',', false, false), 1), Some(5));
+        assert_eq!(buffer.find_char(0, 6, find(',', false, false), 2), Some(3));
+        assert_eq!(buffer.find_char(0, 6, find('b', false, true), 1), Some(3));
+    }
+
+    #[test]
+    fn test_find_char_not_found_and_multibyte() {
+        let buffer = buffer_with(&["naïve café", ""]);
+        let find = |target, forward, till| CharFind { target, forward, till };
+        assert_eq!(buffer.find_char(0, 0, find('é', true, false), 1), Some(9));
+        assert_eq!(buffer.find_char(0, 9, find('ï', false, false), 1), Some(2));
+        assert_eq!(buffer.find_char(0, 0, find('ï', true, true), 1), Some(1));
+        assert_eq!(buffer.find_char(0, 0, find('z', true, false), 1), None);
+        assert_eq!(buffer.find_char(0, 0, find('n', false, false), 1), None);
+        assert_eq!(buffer.find_char(0, 0, find('a', true, false), 2), Some(7));
+        assert_eq!(buffer.find_char(0, 0, find('a', true, false), 3), None);
+        assert_eq!(buffer.find_char(1, 0, find('a', true, false), 1), None);
+        assert_eq!(buffer.find_char(2, 0, find('a', true, false), 1), None);
+    }
+
+    #[test]
+    fn test_find_matching_bracket_respects_nesting() {
+        let buffer = Buffer { lines: vec!["f(a(b), [c])".to_string()], ..Default::default() };
+        assert_eq!(buffer.find_matching_bracket(0, 1), Some((0, 11)));
+        assert_eq!(buffer.find_matching_bracket(0, 11), Some((0, 1)));
+        assert_eq!(buffer.find_matching_bracket(0, 3), Some((0, 5)));
+        assert_eq!(buffer.find_matching_bracket(0, 4), Some((0, 3)));
+        assert_eq!(buffer.find_matching_bracket(0, 0), Some((0, 11)));
+        assert_eq!(buffer.find_matching_bracket(0, 8), Some((0, 10)));
+    }
+
+    #[test]
+    fn test_find_matching_bracket_across_lines() {
+        let lines = ["if x {", "", "    y = [1,", "    2];", "}", "{ ]"];
+        let buffer = Buffer { lines: lines.iter().map(|l| l.to_string()).collect(), ..Default::default() };
+        assert_eq!(buffer.find_matching_bracket(0, 0), Some((4, 0)));
+        assert_eq!(buffer.find_matching_bracket(4, 0), Some((0, 5)));
+        assert_eq!(buffer.find_matching_bracket(3, 4), Some((2, 8)));
+        assert_eq!(buffer.find_matching_bracket(5, 0), None);
+        assert_eq!(buffer.find_matching_bracket(5, 2), None);
+        assert_eq!(buffer.find_matching_bracket(1, 0), None);
+    }
+
+    #[test]
+    fn test_wrap_starts() {
+        assert_eq!(wrap_starts("", 4, 4), vec![0]);
+        assert_eq!(wrap_starts("abcd", 4, 4), vec![0]);
+        assert_eq!(wrap_starts("abcdefghij", 4, 4), vec![0, 4, 8]);
+        // the wide char doesn't fit in the last cell and moves down
+        assert_eq!(wrap_starts("abc日本", 4, 4), vec![0, 3]);
+        assert_eq!(wrap_starts("ab\tcd", 5, 4), vec![0, 4]);
+        assert_eq!(wrap_starts("abcd\tx", 6, 4), vec![0, 4]);
+        assert_eq!(wrap_starts("日本", 1, 4), vec![0, 1]);
+    }
+
+    #[test]
+    fn test_char_col_inverts_visual_col() {
+        let line = "a\tb";
+        assert_eq!(char_col(line, 0, 4), 0);
+        assert_eq!(char_col(line, 1, 4), 1);
+        assert_eq!(char_col(line, 3, 4), 1);
+        assert_eq!(char_col(line, 4, 4), 2);
+        assert_eq!(char_col(line, 9, 4), 3);
+    }
+
+    #[test]
+    fn test_replace_char() {
+        let mut buffer = buffer_with(&["héllo"]);
+        assert_eq!(buffer.replace_char(0, 1, 'ä').unwrap(), 'é');
+        assert_eq!(buffer.replace_char(0, 4, 'x').unwrap(), 'o');
+        assert_eq!(buffer.lines, lines(&["hällx"]));
+        assert!(buffer.replace_char(0, 5, 'y').is_err());
+    }
+
+    #[test]
+    fn test_insert_text_single_and_multi_line() {
+        let mut buffer = buffer_with(&["héllo"]);
+        assert_eq!(buffer.insert_text(0, 2, &lines(&["XY"])).unwrap(), (0, 4));
+        assert_eq!(buffer.lines, lines(&["héXYllo"]));
+
+        let mut buffer = buffer_with(&["abcd"]);
+        assert_eq!(buffer.insert_text(0, 2, &lines(&["1", "", "23"])).unwrap(), (2, 2));
+        assert_eq!(buffer.lines, lines(&["ab1", "", "23cd"]));
+    }
+
+    #[test]
+    fn test_insert_str_splits_on_newlines() {
+        let mut buffer = buffer_with(&["xyz"]);
+        assert_eq!(buffer.insert_str(0, 1, "a\nb").unwrap(), (1, 1));
+        assert_eq!(buffer.lines, lines(&["xa", "byz"]));
+        assert!(buffer.modified);
+
+        let mut buffer = buffer_with(&["日本語"]);
+        assert_eq!(buffer.insert_str(0, 2, "é\n").unwrap(), (1, 0));
+        assert_eq!(buffer.lines, lines(&["日本é", "語"]));
+        assert_eq!(buffer.insert_str(1, 1, "").unwrap(), (1, 1));
+        assert!(buffer.insert_str(1, 2, "a").is_err());
+    }
+
+    #[test]
+    fn test_insert_lines() {
+        let mut buffer = buffer_with(&["a", "b"]);
+        buffer.insert_lines(2, &lines(&["c", "d"])).unwrap();
+        buffer.insert_lines(0, &lines(&["z"])).unwrap();
+        assert_eq!(buffer.lines, lines(&["z", "a", "b", "c", "d"]));
+        assert!(buffer.insert_lines(9, &lines(&["x"])).is_err());
+    }
+
+    #[test]
+    fn test_delete_range_within_line() {
+        let mut buffer = buffer_with(&["héllo world"]);
+        assert_eq!(buffer.delete_range((0, 1), (0, 5)).unwrap(), vec!["éllo "]);
+        assert_eq!(buffer.lines, lines(&["hworld"]));
+        assert!(buffer.has_unsaved_changes());
+    }
+
+    #[test]
+    fn test_delete_range_across_lines() {
+        let mut buffer = buffer_with(&["one", "two", "", "three"]);
+        assert_eq!(buffer.text_range((0, 1), (3, 1)), vec!["ne", "two", "", "th"]);
+        buffer.delete_range((0, 1), (3, 1)).unwrap();
+        assert_eq!(buffer.lines, lines(&["oree"]));
+    }
+
+    #[test]
+    fn test_first_non_blank() {
+        let buffer = buffer_with(&["\t  foo", "bar", "   "]);
+        assert_eq!(buffer.first_non_blank(0), 3);
+        assert_eq!(buffer.first_non_blank(1), 0);
+        assert_eq!(buffer.first_non_blank(2), 3);
+    }
+
+    #[test]
+    fn test_next_word_start() {
+        let buffer = buffer_with(&["foo.bar  baz", "", "  qux"]);
+        assert_eq!(buffer.next_word_start(0, 0), (0, 3));
+        assert_eq!(buffer.next_word_start(0, 3), (0, 4));
+        assert_eq!(buffer.next_word_start(0, 4), (0, 9));
+        assert_eq!(buffer.next_word_start(0, 9), (1, 0));
+        assert_eq!(buffer.next_word_start(1, 0), (2, 2));
+        assert_eq!(buffer.next_word_start(2, 2), (2, 4));
+    }
+
+    #[test]
+    fn test_inner_word() {
+        let buffer = buffer_with(&["foo.bar  baz", ""]);
+        assert_eq!(buffer.inner_word(0, 1), Some((0, 3)));
+        assert_eq!(buffer.inner_word(0, 3), Some((3, 4)));
+        assert_eq!(buffer.inner_word(0, 8), Some((7, 9)));
+        assert_eq!(buffer.inner_word(0, 11), Some((9, 12)));
+        assert_eq!(buffer.inner_word(1, 0), None);
+    }
+
+    #[test]
+    fn test_word_delete_end_stays_on_the_line() {
+        let buffer = buffer_with(&["foo.bar  baz", "  qux", "ab c"]);
+        assert_eq!(buffer.word_delete_end(0, 0), 3);
+        assert_eq!(buffer.word_delete_end(0, 4), 9);
+        assert_eq!(buffer.word_delete_end(0, 9), 12);
+        assert_eq!(buffer.word_delete_end(1, 2), 5);
+        assert_eq!(buffer.word_delete_end(2, 0), 3);
+        assert_eq!(buffer.word_delete_end(2, 3), 4);
+    }
+
+    #[test]
+    fn test_word_delete_start_stays_on_the_line() {
+        let buffer = buffer_with(&["x", "hello world", "  foo.bar  ", "   "]);
+        assert_eq!(buffer.word_delete_start(1, 11), 6);
+        assert_eq!(buffer.word_delete_start(1, 6), 0);
+        assert_eq!(buffer.word_delete_start(1, 8), 6);
+        assert_eq!(buffer.word_delete_start(2, 11), 6);
+        assert_eq!(buffer.word_delete_start(2, 6), 5);
+        assert_eq!(buffer.word_delete_start(2, 5), 2);
+        assert_eq!(buffer.word_delete_start(3, 3), 0);
+        assert_eq!(buffer.word_delete_start(1, 0), 0);
+    }
+
+    #[test]
+    fn test_delete_to_line_start() {
+        let mut buffer = buffer_with(&["  héllo world"]);
+        assert_eq!(buffer.delete_to_line_start(0, 8).unwrap(), "  héllo ");
+        assert_eq!(buffer.lines, vec!["world"]);
+        assert!(buffer.modified);
+
+        let mut buffer = buffer_with(&["abc"]);
+        assert_eq!(buffer.delete_to_line_start(0, 0).unwrap(), "");
+        assert!(!buffer.modified);
+        assert!(buffer.delete_to_line_start(0, 4).is_err());
+        assert!(buffer.delete_to_line_start(1, 0).is_err());
+    }
+
+    #[test]
+    fn test_delete_to_line_end() {
+        let mut buffer = buffer_with(&["héllo world"]);
+        assert_eq!(buffer.delete_to_line_end(0, 2).unwrap(), "llo world");
+        assert_eq!(buffer.lines, vec!["hé"]);
+        assert!(buffer.modified);
+
+        let mut buffer = buffer_with(&["abc"]);
+        assert_eq!(buffer.delete_to_line_end(0, 3).unwrap(), "");
+        assert!(!buffer.modified);
+        assert!(buffer.delete_to_line_end(0, 4).is_err());
+    }
+
+    #[test]
+    fn test_indent_and_dedent_line() {
+        let spaces = IndentStyle { tab_width: 4, expand_tab: true };
+        let tabs = IndentStyle { tab_width: 4, expand_tab: false };
+        let mut buffer = buffer_with(&["x", "\ty", "", "  z"]);
+        buffer.indent_line(0, 4, spaces).unwrap();
+        buffer.indent_line(1, 4, spaces).unwrap();
+        buffer.indent_line(2, 4, spaces).unwrap();
+        buffer.indent_line(3, 2, tabs).unwrap();
+        assert_eq!(buffer.lines, vec!["    x", "        y", "", "\tz"]);
+
+        buffer.dedent_line(0, 2, tabs).unwrap();
+        buffer.dedent_line(1, 4, tabs).unwrap();
+        buffer.dedent_line(3, 8, spaces).unwrap();
+        assert_eq!(buffer.lines, vec!["  x", "\ty", "", "z"]);
+
+        let mut flush = buffer_with(&["text"]);
+        flush.dedent_line(0, 4, spaces).unwrap();
+        assert_eq!(flush.lines, vec!["text"]);
+        assert!(!flush.modified);
+        assert!(flush.indent_line(1, 4, spaces).is_err());
+    }
+
+    #[test]
+    fn test_delete_lines() {
+        let mut buffer = buffer_with(&["a", "b", "c"]);
+        assert_eq!(buffer.delete_lines(1, 2).unwrap(), lines(&["b", "c"]));
+        assert_eq!(buffer.lines, lines(&["a"]));
+        assert!(buffer.delete_lines(0, 1).is_err());
+        assert_eq!(buffer.delete_lines(0, 0).unwrap(), lines(&["a"]));
+        assert_eq!(buffer.lines, lines(&[""]));
+    }
+
+    #[test]
+    fn test_prev_word_start() {
+        let buffer = buffer_with(&["foo.bar  baz", "", "  qux"]);
+        assert_eq!(buffer.prev_word_start(2, 2), (1, 0));
+        assert_eq!(buffer.prev_word_start(1, 0), (0, 9));
+        assert_eq!(buffer.prev_word_start(0, 10), (0, 9));
+        assert_eq!(buffer.prev_word_start(0, 9), (0, 4));
+        assert_eq!(buffer.prev_word_start(0, 4), (0, 3));
+        assert_eq!(buffer.prev_word_start(0, 3), (0, 0));
+        assert_eq!(buffer.prev_word_start(0, 0), (0, 0));
+    }
+
+    #[test]
+    fn test_word_end() {
+        let buffer = buffer_with(&["foo.bar  baz", "", "  qux"]);
+        assert_eq!(buffer.word_end(0, 0), (0, 2));
+        assert_eq!(buffer.word_end(0, 2), (0, 3));
+        assert_eq!(buffer.word_end(0, 3), (0, 6));
+        assert_eq!(buffer.word_end(0, 6), (0, 11));
+        assert_eq!(buffer.word_end(0, 11), (2, 4));
+        assert_eq!(buffer.word_end(2, 4), (2, 4));
+    }
+
+    #[test]
+    fn test_word_at() {
+        let buffer = buffer_with(&["let foo_bar = 1;"]);
+        assert_eq!(buffer.word_at(0, 5).as_deref(), Some("foo_bar"));
+        assert_eq!(buffer.word_at(0, 3).as_deref(), Some("foo_bar"));
+        assert_eq!(buffer.word_at(0, 14).as_deref(), Some("1"));
+        assert_eq!(buffer.word_at(0, 15).as_deref(), None);
+    }
+
+    #[test]
+    fn test_find_whole_word_skips_substrings() {
+        let buffer = buffer_with(&["foo foobar", "barfoo foo_x", "x foo"]);
+        assert_eq!(buffer.find_whole_word("foo", 0, 0, true), Some(((2, 2), false)));
+        assert_eq!(buffer.find_whole_word("foo", 2, 2, true), Some(((0, 0), true)));
+        assert_eq!(buffer.find_whole_word("foo", 2, 2, false), Some(((0, 0), false)));
+        assert_eq!(buffer.find_whole_word("foo", 0, 0, false), Some(((2, 2), true)));
+        assert_eq!(buffer.find_whole_word("baz", 0, 0, true), None);
+    }
+
+    fn lines(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_diff_against_insertion() {
+        let buffer = buffer_with(&["a", "new", "b"]);
+        assert_eq!(
+            buffer.diff_against(&lines(&["a", "b"])),
+            vec![
+                DiffOp::Unchanged("a".into()),
+                DiffOp::Added("new".into()),
+                DiffOp::Unchanged("b".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff_against_deletion() {
+        let buffer = buffer_with(&["a", "c"]);
+        assert_eq!(
+            buffer.diff_against(&lines(&["a", "b", "c"])),
+            vec![
+                DiffOp::Unchanged("a".into()),
+                DiffOp::Removed("b".into()),
+                DiffOp::Unchanged("c".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff_against_large_file_with_one_change() {
+        let text: Vec<String> = (0..50_000).map(|i| format!("line {}", i)).collect();
+        let mut buffer = buffer_with(&[]);
+        buffer.lines = text.clone();
+        buffer.lines[25_000] = "changed".to_string();
+        buffer.lines.remove(25_001);
+        let ops = buffer.diff_against(&text);
+        assert_eq!(ops.len(), 50_001);
+        assert_eq!(ops[24_999], DiffOp::Unchanged("line 24999".into()));
+        assert_eq!(
+            ops[25_000..25_003],
+            [
+                DiffOp::Removed("line 25000".into()),
+                DiffOp::Removed("line 25001".into()),
+                DiffOp::Added("changed".into()),
+            ]
+        );
+        assert_eq!(ops[25_003], DiffOp::Unchanged("line 25002".into()));
+    }
+
+    #[test]
+    fn test_diff_against_modification() {
+        let buffer = buffer_with(&["a", "B", "c", "d"]);
+        assert_eq!(
+            buffer.diff_against(&lines(&["a", "b", "c"])),
+            vec![
+                DiffOp::Unchanged("a".into()),
+                DiffOp::Removed("b".into()),
+                DiffOp::Added("B".into()),
+                DiffOp::Unchanged("c".into()),
+                DiffOp::Added("d".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_save_returns_bytes_written() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.txt");
+        let mut buffer = buffer_with(&["hello", "world"]);
+        assert_eq!(buffer.save_as(path.to_string_lossy().into_owned()).unwrap(), 11);
+        buffer.lines.push("!".to_string());
+        assert_eq!(buffer.save().unwrap(), 13);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "hello\nworld\n!");
+    }
+
+    #[test]
+    fn test_write_to_leaves_the_buffer_file_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let copy = dir.path().join("backup").join("copy.txt");
+        let mut buffer = buffer_with(&["hello"]);
+        buffer.file = Some("original.txt".to_string());
+        buffer.modified = true;
+        assert_eq!(buffer.write_to(&copy.to_string_lossy()).unwrap(), 5);
+        assert_eq!(std::fs::read_to_string(&copy).unwrap(), "hello");
+        assert_eq!(buffer.file.as_deref(), Some("original.txt"));
+        assert!(buffer.modified);
+    }
+
+    #[test]
+    fn test_crlf_file_round_trips() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dos.txt");
+        let name = Some(path.to_string_lossy().into_owned());
+        let mut buffer = Buffer::from_reader(io::Cursor::new("one\r\ntwo\r\n"), name).unwrap();
+        assert_eq!(buffer.lines, lines(&["one", "two"]));
+        assert_eq!(buffer.line_ending, LineEnding::Crlf);
+        assert!(buffer.trailing_newline);
+
+        buffer.lines.push("three".to_string());
+        buffer.save().unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "one\r\ntwo\r\nthree\r\n");
+    }
+
+    #[test]
+    fn test_bom_is_stripped_and_written_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("bom.txt");
+        let name = Some(path.to_string_lossy().into_owned());
+        let mut buffer = Buffer::from_reader(io::Cursor::new("\u{feff}one\ntwo\n"), name).unwrap();
+        assert_eq!(buffer.lines, lines(&["one", "two"]));
+        assert!(buffer.bom);
+        assert!(!buffer.invalid_utf8);
+
+        buffer.lines[1].push('!');
+        assert_eq!(buffer.save().unwrap(), 12);
+        assert_eq!(std::fs::read(&path).unwrap(), b"\xef\xbb\xbfone\ntwo!\n");
+    }
+
+    #[test]
+    fn test_invalid_utf8_is_replaced() {
+        let buffer = Buffer::from_reader(io::Cursor::new(b"caf\xe9\nok\n"), None).unwrap();
+        assert_eq!(buffer.lines, lines(&["caf\u{fffd}", "ok"]));
+        assert!(buffer.invalid_utf8);
+        assert!(!buffer.bom);
+        assert_eq!(decode(b"plain"), ("plain".to_string(), false, false));
+    }
+
+    #[test]
+    fn test_from_reader_notes_the_final_newline() {
+        let buffer = Buffer::from_reader(io::Cursor::new("one\ntwo\n"), Some("notes.txt".to_string())).unwrap();
+        assert_eq!(buffer.lines, lines(&["one", "two"]));
+        assert!(buffer.trailing_newline);
+        assert_eq!(buffer.file.as_deref(), Some("notes.txt"));
+        assert!(!buffer.modified);
+
+        let buffer = Buffer::from_reader(io::Cursor::new("one\r\ntwo"), None).unwrap();
+        assert_eq!(buffer.lines, lines(&["one", "two"]));
+        assert!(!buffer.trailing_newline);
+        assert_eq!(buffer.line_ending, LineEnding::Crlf);
+        assert_eq!(buffer.file, None);
+
+        assert_eq!(Buffer::from_reader(io::Cursor::new(""), None).unwrap().lines, lines(&[""]));
+    }
+
+    #[test]
+    fn test_save_refuses_invalid_utf8_unless_forced() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("latin1.txt");
+        std::fs::write(&path, b"caf\xe9\n").unwrap();
+        let mut buffer = Buffer::from_file(Some(path.to_string_lossy().into_owned())).unwrap();
+        assert!(matches!(buffer.save(), Err(BufferError::InvalidUtf8)));
+        assert_eq!(std::fs::read(&path).unwrap(), b"caf\xe9\n");
+
+        buffer.force_save().unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "caf\u{fffd}\n");
+        assert!(!buffer.invalid_utf8);
+    }
+
+    #[test]
+    fn test_save_as_refuses_only_the_invalid_utf8_file_itself() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("latin1.txt");
+        std::fs::write(&path, b"caf\xe9\n").unwrap();
+        let mut buffer = Buffer::from_file(Some(path.to_string_lossy().into_owned())).unwrap();
+        assert!(matches!(buffer.save_as(path.to_string_lossy().into_owned()), Err(BufferError::InvalidUtf8)));
+        assert_eq!(std::fs::read(&path).unwrap(), b"caf\xe9\n");
+
+        let copy = dir.path().join("utf8.txt");
+        buffer.save_as(copy.to_string_lossy().into_owned()).unwrap();
+        assert_eq!(std::fs::read_to_string(&copy).unwrap(), "caf\u{fffd}\n");
+        assert!(!buffer.invalid_utf8);
+        buffer.save().unwrap();
+    }
+
+    #[test]
+    fn test_missing_final_newline_is_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("bare.txt");
+        let name = Some(path.to_string_lossy().into_owned());
+        let mut buffer = Buffer::from_reader(io::Cursor::new("one\ntwo"), name).unwrap();
+        assert_eq!(buffer.line_ending, LineEnding::Lf);
+        assert!(!buffer.trailing_newline);
+
+        buffer.lines[1].push('!');
+        buffer.save().unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "one\ntwo!");
+    }
+
+    #[test]
+    fn test_stats_count_line_endings() {
+        let mut buffer = buffer_with(&["one two", "", "  three  four five"]);
+        assert_eq!(
+            buffer.stats(2, 3),
+            BufferStats { line: 3, lines: 3, col: 4, char: 13, chars: 27, words: 5 }
+        );
+        buffer.line_ending = LineEnding::Crlf;
+        buffer.trailing_newline = true;
+        let stats = buffer.stats(2, 3);
+        assert_eq!((stats.char, stats.chars), (15, 31));
+        assert_eq!(buffer.stats(0, 0).char, 1);
+    }
+
+    #[test]
+    fn test_line_ending_detection_follows_the_majority() {
+        assert_eq!(LineEnding::detect("a\r\nb\r\nc\n"), LineEnding::Crlf);
+        assert_eq!(LineEnding::detect("a\r\nb\nc\n"), LineEnding::Lf);
+        assert_eq!(LineEnding::detect(""), LineEnding::Lf);
+        assert_eq!(parse_text("").0, lines(&[""]));
+    }
+
+    #[test]
+    fn test_save_refuses_when_file_changed_on_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("shared.txt");
+        std::fs::write(&path, "one\n").unwrap();
+        let mut buffer = Buffer::from_file(Some(path.to_string_lossy().into_owned())).unwrap();
+        assert_eq!(buffer.disk_change(), None);
+
+        let touched = buffer.mtime.unwrap() + std::time::Duration::from_secs(5);
+        std::fs::File::options().write(true).open(&path).unwrap().set_modified(touched).unwrap();
+        assert_eq!(buffer.disk_change(), Some(DiskChange::Modified));
+        assert!(matches!(buffer.save(), Err(BufferError::ChangedOnDisk)));
+
+        buffer.force_save().unwrap();
+        assert_eq!(buffer.disk_change(), None);
+        buffer.save().unwrap();
+
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(buffer.disk_change(), Some(DiskChange::Deleted));
+        assert_eq!(buffer_with(&["unnamed"]).disk_change(), None);
+    }
+
+    #[test]
+    fn test_is_write_protected_follows_permissions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("locked.txt");
+        std::fs::write(&path, "text").unwrap();
+        let buffer = Buffer::from_file(Some(path.to_string_lossy().into_owned())).unwrap();
+        assert!(!buffer.is_write_protected());
+
+        let mut permissions = std::fs::metadata(&path).unwrap().permissions();
+        permissions.set_readonly(true);
+        std::fs::set_permissions(&path, permissions).unwrap();
+        assert!(buffer.is_write_protected());
+        assert!(!buffer_with(&["unnamed"]).is_write_protected());
+    }
+
+    #[test]
+    fn test_post_save_hook_receives_saved_path() {
+        use std::cell::RefCell;
+        use std::path::PathBuf;
+        use std::rc::Rc;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nested").join("hooked.txt");
+        let seen: Rc<RefCell<Vec<PathBuf>>> = Rc::default();
+        let mut buffer = buffer_with(&["text"]);
+        let sink = Rc::clone(&seen);
+        buffer.post_save_hook = Some(Box::new(move |p| sink.borrow_mut().push(p.to_path_buf())));
+
+        buffer.save_as(path.to_string_lossy().into_owned()).unwrap();
+        buffer.save().unwrap();
+        assert_eq!(*seen.borrow(), vec![path.clone(), path]);
+    }
+
+    #[test]
+    fn test_post_save_hook_not_run_on_failure() {
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        let called = Rc::new(Cell::new(false));
+        let mut buffer = buffer_with(&["text"]);
+        let flag = Rc::clone(&called);
+        buffer.post_save_hook = Some(Box::new(move |_| flag.set(true)));
+        assert!(buffer.save().is_err());
+        assert!(!called.get());
+    }
+
+    #[test]
+    fn test_scratch_buffer_can_be_abandoned() {
+        let mut buffer = Buffer::scratch();
+        buffer.insert_char(0, 0, 'x').unwrap();
+        assert!(buffer.modified);
+        assert!(!buffer.has_unsaved_changes());
+        assert_eq!(buffer.display_name(), "[Scratch]");
+
+        let mut regular = buffer_with(&[""]);
+        regular.insert_char(0, 0, 'x').unwrap();
+        assert!(regular.has_unsaved_changes());
+    }
+
+    #[test]
+    fn test_scratch_buffer_refuses_to_save() {
+        let mut buffer = Buffer::scratch();
+        assert!(matches!(buffer.save(), Err(BufferError::ScratchBuffer)));
+        assert!(matches!(
+            buffer.save_as("scratch.txt".to_string()),
+            Err(BufferError::ScratchBuffer)
+        ));
+        assert!(buffer.file.is_none());
+    }
+
+    /// Writes `file` plus a recovery file, backdating `file` so the recovery
+    /// copy is the newer of the two.
+    fn file_with_recovery(dir: &tempfile::TempDir, content: &str, recovered: &str) -> String {
+        let path = dir.path().join("notes.txt");
+        std::fs::write(&path, content).unwrap();
+        std::fs::write(dir.path().join("notes.txt.recovery"), recovered).unwrap();
+        let an_hour_ago = std::time::SystemTime::now() - std::time::Duration::from_secs(3600);
+        std::fs::File::options().write(true).open(&path).unwrap().set_modified(an_hour_ago).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn test_restore_recovery_loads_its_contents() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = file_with_recovery(&dir, "saved", "unsaved\nwork");
+        let mut buffer = Buffer::from_file(Some(path.clone())).unwrap();
+        assert_eq!(buffer.recovery, Some(format!("{}.recovery", path)));
+        assert_eq!(buffer.lines, lines(&["saved"]));
+
+        buffer.restore_recovery().unwrap();
+        assert_eq!(buffer.lines, lines(&["unsaved", "work"]));
+        assert!(buffer.has_unsaved_changes());
+        assert!(buffer.recovery.is_none());
+    }
+
+    #[test]
+    fn test_stale_recovery_is_ignored_and_discard_removes_it() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = file_with_recovery(&dir, "saved", "old");
+        std::fs::write(&path, "newer").unwrap();
+        assert!(Buffer::from_file(Some(path.clone())).unwrap().recovery.is_none());
+
+        let path = file_with_recovery(&dir, "saved", "unsaved");
+        let mut buffer = Buffer::from_file(Some(path.clone())).unwrap();
+        buffer.discard_recovery();
+        assert!(!std::path::Path::new(&format!("{}.recovery", path)).exists());
+        assert_eq!(buffer.lines, lines(&["saved"]));
+        assert!(!buffer.has_unsaved_changes());
+    }
+
+    #[test]
+    fn test_expand_tilde() {
+        let home = dirs::home_dir().unwrap().display().to_string();
+        assert_eq!(expand_tilde("~"), home);
+        assert_eq!(expand_tilde("~/notes.txt"), format!("{}/notes.txt", home));
+        assert_eq!(expand_tilde("~user/notes.txt"), "~user/notes.txt");
+        assert_eq!(expand_tilde("notes/~/a.txt"), "notes/~/a.txt");
+    }
+}
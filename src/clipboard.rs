@@ -0,0 +1,79 @@
+use log::warn;
+
+/// Access to a clipboard shared with other programs.
+pub trait Clipboard {
+    fn get_text(&mut self) -> Option<String>;
+    /// Returns whether the text was stored.
+    fn set_text(&mut self, text: &str) -> bool;
+}
+
+#[cfg(feature = "clipboard")]
+struct SystemClipboard(arboard::Clipboard);
+
+#[cfg(feature = "clipboard")]
+impl Clipboard for SystemClipboard {
+    fn get_text(&mut self) -> Option<String> {
+        self.0
+            .get_text()
+            .map_err(|e| warn!("Could not read the system clipboard: {}", e))
+            .ok()
+    }
+
+    fn set_text(&mut self, text: &str) -> bool {
+        self.0
+            .set_text(text)
+            .map_err(|e| warn!("Could not write the system clipboard: {}", e))
+            .is_ok()
+    }
+}
+
+/// The OS clipboard, or `None` when there isn't one (a headless session, or
+/// a build without the `clipboard` feature).
+#[cfg(feature = "clipboard")]
+pub fn system() -> Option<Box<dyn Clipboard>> {
+    match arboard::Clipboard::new() {
+        Ok(clipboard) => Some(Box::new(SystemClipboard(clipboard))),
+        Err(e) => {
+            warn!("System clipboard unavailable, using the internal register: {}", e);
+            None
+        }
+    }
+}
+
+#[cfg(not(feature = "clipboard"))]
+pub fn system() -> Option<Box<dyn Clipboard>> {
+    warn!("Built without the clipboard feature, using the internal register");
+    None
+}
+
+/// Clipboard text for register contents; whole lines end with a newline.
+pub fn to_text(lines: &[String], linewise: bool) -> String {
+    let mut text = lines.join("\n");
+    if linewise {
+        text.push('\n');
+    }
+    text
+}
+
+/// Register contents for clipboard text, and whether they are whole lines.
+pub fn from_text(text: &str) -> (Vec<String>, bool) {
+    let text = text.replace("\r\n", "\n");
+    match text.strip_suffix('\n') {
+        Some(lines) => (lines.split('\n').map(str::to_string).collect(), true),
+        None => (text.split('\n').map(str::to_string).collect(), false),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_round_trip() {
+        let lines = vec!["one".to_string(), "two".to_string()];
+        assert_eq!(to_text(&lines, true), "one\ntwo\n");
+        assert_eq!(from_text("one\ntwo\n"), (lines.clone(), true));
+        assert_eq!(from_text("one\r\ntwo"), (lines, false));
+        assert_eq!(from_text("word"), (vec!["word".to_string()], false));
+    }
+}
@@ -0,0 +1,100 @@
+//! Tab completion for the `:` command line.
+
+use std::path::Path;
+
+/// What a command accepts after its name, as far as completion cares.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Argument {
+    None,
+    /// A file name, completed from the file system.
+    Path,
+    /// Anything else, such as an option or a pattern.
+    Text,
+}
+
+/// Every way the command line can be completed, as whole command lines: the
+/// names in `commands` that start with what has been typed, or once a name
+/// and a space are in, the paths its argument could be.
+pub fn complete(line: &str, commands: &[(&str, Argument)]) -> Vec<String> {
+    match line.split_once(' ') {
+        None => {
+            let mut names: Vec<String> = commands
+                .iter()
+                .filter(|(name, _)| name.starts_with(line))
+                .map(|(name, _)| name.to_string())
+                .collect();
+            names.sort();
+            names.dedup();
+            names
+        }
+        Some((name, partial)) if commands.contains(&(name, Argument::Path)) => complete_path(partial)
+            .into_iter()
+            .map(|path| format!("{} {}", name, path))
+            .collect(),
+        Some(_) => Vec::new(),
+    }
+}
+
+/// The files and directories whose path starts with `partial`, sorted, with
+/// a `/` after directories. Hidden ones are left out unless `partial` names
+/// them with a leading dot.
+pub fn complete_path(partial: &str) -> Vec<String> {
+    let (dir, prefix) = match partial.rfind('/') {
+        Some(slash) => partial.split_at(slash + 1),
+        None => ("", partial),
+    };
+    let Ok(entries) = std::fs::read_dir(if dir.is_empty() { Path::new(".") } else { Path::new(dir) }) else {
+        return Vec::new();
+    };
+    let mut paths: Vec<String> = entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+            let slash = if entry.path().is_dir() { "/" } else { "" };
+            Some(format!("{}{}{}", dir, name, slash))
+        })
+        .collect();
+    paths.sort();
+    paths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COMMANDS: &[(&str, Argument)] = &[
+        ("w", Argument::Path),
+        ("wq", Argument::None),
+        ("set", Argument::Text),
+        ("stripws", Argument::None),
+    ];
+
+    #[test]
+    fn test_complete_command_names() {
+        assert_eq!(complete("s", COMMANDS), vec!["set", "stripws"]);
+        assert_eq!(complete("w", COMMANDS), vec!["w", "wq"]);
+        assert!(complete("z", COMMANDS).is_empty());
+        assert!(complete("set n", COMMANDS).is_empty());
+    }
+
+    #[test]
+    fn test_complete_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("notes.txt"), "").unwrap();
+        std::fs::write(dir.path().join(".hidden"), "").unwrap();
+        std::fs::create_dir(dir.path().join("nested")).unwrap();
+        let base = format!("{}/", dir.path().display());
+
+        assert_eq!(
+            complete_path(&format!("{}n", base)),
+            vec![format!("{}nested/", base), format!("{}notes.txt", base)]
+        );
+        assert_eq!(complete_path(&format!("{}.", base)), vec![format!("{}.hidden", base)]);
+        assert_eq!(complete(&format!("w {}no", base), COMMANDS), vec![format!("w {}notes.txt", base)]);
+        assert!(complete(&format!("wq {}no", base), COMMANDS).is_empty());
+        assert!(complete_path(&format!("{}missing/", base)).is_empty());
+    }
+}
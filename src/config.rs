@@ -0,0 +1,297 @@
+use std::path::Path;
+
+use log::{debug, error, info, warn};
+
+use crate::editor::{unknown_statusline_tokens, Editor};
+
+/// Settings read from `~/.vix/config.toml`. Anything left out keeps the
+/// editor's built-in default.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Config {
+    /// `[keys]`: action name to key, e.g. `move_left = "h"`.
+    pub keys: Vec<(String, String)>,
+    /// `[editor]` options.
+    pub tab_width: Option<usize>,
+    pub expand_tab: Option<bool>,
+    pub shift_width: Option<usize>,
+    pub show_line_numbers: Option<bool>,
+    pub syntax_highlighting: Option<bool>,
+    pub auto_indent: Option<bool>,
+    pub auto_pairs: Option<bool>,
+    pub hlsearch: Option<bool>,
+    pub insert_emacs_keys: Option<bool>,
+    pub wrap: Option<bool>,
+    pub trim_trailing_whitespace: Option<bool>,
+    pub mouse: Option<bool>,
+    /// Let `:r !cmd` run shell commands.
+    pub allow_shell: Option<bool>,
+    /// The status bar template, with `%` tokens such as `%f` and `%l`.
+    pub statusline: Option<String>,
+    /// Keep a `.<name>.swp` file next to open files.
+    pub swap_file: Option<bool>,
+    /// Seconds a status message stays; 0 keeps messages until replaced.
+    pub status_timeout: Option<u64>,
+    /// Seconds between autosaves of unsaved changes; 0 turns autosave off.
+    pub autosave: Option<u64>,
+    /// Yank to and paste from the system clipboard without `"+`.
+    pub clipboard: Option<bool>,
+}
+
+impl Config {
+    /// Reads the config file. A missing file gives the defaults; an unreadable
+    /// or malformed one is logged and also gives the defaults.
+    pub fn load(path: &Path) -> Self {
+        if !path.exists() {
+            debug!("No config file at {:?}", path);
+            return Self::default();
+        }
+        let parsed = std::fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| Self::parse(&text));
+        match parsed {
+            Ok(config) => {
+                info!("Loaded config from {:?}", path);
+                config
+            }
+            Err(e) => {
+                error!("Ignoring config file {:?}: {}", path, e);
+                Self::default()
+            }
+        }
+    }
+
+    /// Parses config text. Values of the wrong type are logged and skipped so
+    /// one typo doesn't throw away the rest of the file.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let table: toml::Table = text.parse().map_err(|e: toml::de::Error| e.to_string())?;
+        let mut config = Self::default();
+        if let Some(keys) = table.get("keys").and_then(toml::Value::as_table) {
+            for (name, key) in keys {
+                match key.as_str() {
+                    Some(key) => config.keys.push((name.clone(), key.to_string())),
+                    None => warn!("Config key for {} is not a string", name),
+                }
+            }
+        }
+        if let Some(editor) = table.get("editor").and_then(toml::Value::as_table) {
+            for (name, value) in editor {
+                match (name.as_str(), value) {
+                    ("tab_width", toml::Value::Integer(width)) if *width > 0 => {
+                        config.tab_width = Some(*width as usize);
+                    }
+                    ("expand_tab", toml::Value::Boolean(on)) => config.expand_tab = Some(*on),
+                    ("shift_width", toml::Value::Integer(width)) if *width >= 0 => {
+                        config.shift_width = Some(*width as usize);
+                    }
+                    ("show_line_numbers", toml::Value::Boolean(show)) => {
+                        config.show_line_numbers = Some(*show);
+                    }
+                    ("syntax_highlighting", toml::Value::Boolean(on)) => {
+                        config.syntax_highlighting = Some(*on);
+                    }
+                    ("auto_indent", toml::Value::Boolean(on)) => config.auto_indent = Some(*on),
+                    ("auto_pairs", toml::Value::Boolean(on)) => config.auto_pairs = Some(*on),
+                    ("hlsearch", toml::Value::Boolean(on)) => config.hlsearch = Some(*on),
+                    ("insert_emacs_keys", toml::Value::Boolean(on)) => config.insert_emacs_keys = Some(*on),
+                    ("wrap", toml::Value::Boolean(on)) => config.wrap = Some(*on),
+                    ("trim_trailing_whitespace", toml::Value::Boolean(on)) => {
+                        config.trim_trailing_whitespace = Some(*on);
+                    }
+                    ("mouse", toml::Value::Boolean(on)) => config.mouse = Some(*on),
+                    ("allow_shell", toml::Value::Boolean(on)) => config.allow_shell = Some(*on),
+                    ("statusline", toml::Value::String(template)) => config.statusline = Some(template.clone()),
+                    ("swap_file", toml::Value::Boolean(on)) => config.swap_file = Some(*on),
+                    ("status_timeout", toml::Value::Integer(secs)) if *secs >= 0 => {
+                        config.status_timeout = Some(*secs as u64);
+                    }
+                    ("autosave", toml::Value::Integer(secs)) if *secs >= 0 => {
+                        config.autosave = Some(*secs as u64);
+                    }
+                    ("clipboard", toml::Value::Boolean(on)) => config.clipboard = Some(*on),
+                    _ => warn!("Ignoring editor option {} = {}", name, value),
+                }
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn apply(&self, editor: &mut Editor) {
+        if let Some(width) = self.tab_width {
+            (editor.tab_width, editor.tab_width_set) = (width, true);
+        }
+        if let Some(on) = self.expand_tab {
+            (editor.expand_tab, editor.expand_tab_set) = (on, true);
+        }
+        if let Some(width) = self.shift_width {
+            editor.shift_width = width;
+        }
+        if let Some(show) = self.show_line_numbers {
+            editor.show_line_numbers = show;
+        }
+        if let Some(on) = self.syntax_highlighting {
+            editor.syntax_highlighting = on;
+        }
+        if let Some(on) = self.auto_indent {
+            editor.auto_indent = on;
+        }
+        if let Some(on) = self.auto_pairs {
+            editor.auto_pairs = on;
+        }
+        if let Some(on) = self.hlsearch {
+            editor.hlsearch = on;
+        }
+        if let Some(on) = self.insert_emacs_keys {
+            editor.insert_emacs_keys = on;
+        }
+        if let Some(on) = self.wrap {
+            editor.wrap = on;
+        }
+        if let Some(on) = self.trim_trailing_whitespace {
+            editor.trim_trailing_whitespace = on;
+        }
+        if let Some(on) = self.mouse {
+            editor.mouse = on;
+        }
+        if let Some(on) = self.allow_shell {
+            editor.allow_shell = on;
+        }
+        if let Some(template) = &self.statusline {
+            for token in unknown_statusline_tokens(template) {
+                warn!("Unknown statusline token %{}", token);
+            }
+            editor.statusline = template.clone();
+        }
+        if let Some(on) = self.swap_file {
+            editor.swap_file = on;
+        }
+        if let Some(secs) = self.status_timeout {
+            editor.status_timeout = (secs > 0).then(|| std::time::Duration::from_secs(secs));
+        }
+        if let Some(secs) = self.autosave {
+            editor.autosave_interval = (secs > 0).then(|| std::time::Duration::from_secs(secs));
+        }
+        if let Some(on) = self.clipboard {
+            editor.use_system_clipboard = on;
+        }
+        for (name, key) in &self.keys {
+            if let Err(e) = editor.keymap.bind(name, key) {
+                warn!("{}", e);
+            }
+        }
+    }
+}
+
+/// Reads an initial-commands file such as `~/.vix/.vixrc`. A missing or
+/// unreadable file gives no commands.
+pub fn load_rc(path: &Path) -> Vec<String> {
+    match std::fs::read_to_string(path) {
+        Ok(text) => {
+            info!("Loaded startup commands from {:?}", path);
+            parse_rc(&text)
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            debug!("No rc file at {:?}", path);
+            Vec::new()
+        }
+        Err(e) => {
+            error!("Ignoring rc file {:?}: {}", path, e);
+            Vec::new()
+        }
+    }
+}
+
+/// The commands in rc file text: one per line, as typed after `:` (the `:`
+/// itself is optional). Blank lines and lines starting with `"` are skipped.
+pub fn parse_rc(text: &str) -> Vec<String> {
+    text.lines()
+        .map(|line| line.trim().trim_start_matches(':').trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('"'))
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_keys_and_editor_options() {
+        let config = Config::parse(
+            "[keys]\nmove_left = \"<C-h>\"\n\n[editor]\ntab_width = 8\nshow_line_numbers = false\nsyntax_highlighting = false\n",
+        )
+        .unwrap();
+        assert_eq!(config.keys, vec![("move_left".to_string(), "<C-h>".to_string())]);
+        assert_eq!(config.tab_width, Some(8));
+        assert_eq!(config.show_line_numbers, Some(false));
+        assert_eq!(config.syntax_highlighting, Some(false));
+    }
+
+    #[test]
+    fn test_bad_values_are_skipped_and_bad_toml_is_an_error() {
+        let config = Config::parse("[editor]\ntab_width = \"wide\"\nshow_line_numbers = true\n").unwrap();
+        assert_eq!(config.tab_width, None);
+        assert_eq!(config.show_line_numbers, Some(true));
+        assert!(Config::parse("[editor\n").is_err());
+    }
+
+    #[test]
+    fn test_load_falls_back_to_defaults() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(Config::load(&dir.path().join("missing.toml")), Config::default());
+
+        let broken = dir.path().join("config.toml");
+        std::fs::write(&broken, "tab_width = ").unwrap();
+        assert_eq!(Config::load(&broken), Config::default());
+    }
+
+    #[test]
+    fn test_rc_file_commands_set_options() {
+        let text = "\" startup\nset nonumber\n\n:set tabwidth=2\nset wrapscan\n  set ai  \nset ro\n";
+        let commands = parse_rc(text);
+        assert_eq!(commands, vec!["set nonumber", "set tabwidth=2", "set wrapscan", "set ai", "set ro"]);
+
+        let mut editor = Editor::new();
+        editor.auto_indent = false;
+        editor.run_startup_commands(&commands);
+        assert!(!editor.show_line_numbers);
+        assert_eq!(editor.tab_width, 2);
+        assert!(editor.auto_indent);
+        assert!(editor.read_only);
+
+        let dir = tempfile::tempdir().unwrap();
+        assert!(load_rc(&dir.path().join(".vixrc")).is_empty());
+        std::fs::write(dir.path().join(".vixrc"), text).unwrap();
+        assert_eq!(load_rc(&dir.path().join(".vixrc")), commands);
+    }
+
+    #[test]
+    fn test_apply_sets_options_and_keys() {
+        let mut editor = Editor::new();
+        let config = Config::parse(
+            "[keys]\nmove_down = \"n\"\nbogus = \"z\"\n[editor]\ntab_width = 2\nexpand_tab = true\nshift_width = 3\nclipboard = true\nauto_indent = false\nauto_pairs = true\nhlsearch = false\ninsert_emacs_keys = true\nswap_file = true\nwrap = true\ntrim_trailing_whitespace = true\nstatus_timeout = 0\nautosave = 60\nallow_shell = true\nstatusline = \"%f%=%l\"\n",
+        )
+        .unwrap();
+        config.apply(&mut editor);
+        assert_eq!(editor.tab_width, 2);
+        assert!(editor.expand_tab);
+        assert_eq!(editor.shift_width, 3);
+        assert!(editor.use_system_clipboard);
+        assert!(!editor.auto_indent);
+        assert!(editor.auto_pairs);
+        assert!(!editor.hlsearch);
+        assert!(editor.insert_emacs_keys);
+        assert!(editor.swap_file);
+        assert!(editor.allow_shell);
+        assert!(editor.wrap);
+        assert!(editor.trim_trailing_whitespace);
+        assert_eq!(editor.status_timeout, None);
+        assert_eq!(editor.autosave_interval, Some(std::time::Duration::from_secs(60)));
+        assert!(editor.show_line_numbers);
+        assert_eq!(editor.statusline, "%f%=%l");
+        let key = crossterm::event::KeyEvent::new(
+            crossterm::event::KeyCode::Char('n'),
+            crossterm::event::KeyModifiers::NONE,
+        );
+        assert_eq!(editor.keymap.lookup(key), Some(crate::editor::Actions::MoveDown));
+    }
+}
@@ -1,12 +1,19 @@
 use anyhow::Result;
-use crossterm::event::{Event, KeyCode};
+use crossterm::event::{Event, KeyCode, MouseButton, MouseEvent, MouseEventKind};
 use crossterm::style::Color;
-use crossterm::{terminal, cursor::MoveTo, style::{Print, SetForegroundColor, SetBackgroundColor, ResetColor}};
+use crossterm::{terminal, cursor::MoveTo};
 use crossterm::QueueableCommand;
 use log::{debug, info, warn};
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::io::Write;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Actions {
     MoveUp,
     MoveDown,
@@ -14,46 +21,458 @@ pub enum Actions {
     MoveRight,
     EnterMode(Mode),
     PrintChar(char),
+    /// Inserts text as it is, such as a paste; `\n` starts a new line.
+    InsertText(String),
     Backspace,
     NewLine,
     Save,
     SaveAs(String),
+    /// `:e`: the file to open in place of the current one, or `None` to
+    /// reload it, and whether unsaved changes may be thrown away.
+    EditFile(Option<String>, bool),
+    /// `:w path`: writes a copy of the buffer without renaming it.
+    WriteCopy(String),
+    /// `:r file`: the file's lines go in below the cursor. Without a name,
+    /// the buffer's own file is read.
+    ReadFile(Option<String>),
+    /// `:r !cmd`: what the command prints goes in below the cursor.
+    ReadCommand(String),
     DeleteLine,
+    CommandChar(char),
+    CompleteCommand,
+    CommandBackspace,
+    ExecuteCommand,
+    StripWhitespace,
+    InsertNormalOnce,
+    SearchWordForward,
+    SearchWordBackward,
+    /// `n`, or `N` to search the other way, for the last searched word.
+    SearchNext(bool),
+    ClearHighlight,
+    DiffSummary,
+    FileInfo,
+    ShowStats,
+    CheckTime,
+    Quit,
+    Undo,
+    Redo,
+    /// `:earlier` and `:later`: undo or redo by a count or a length of time.
+    Earlier(UndoSpan),
+    Later(UndoSpan),
+    DeleteChar,
+    ForceQuit,
+    WriteQuit,
+    MoveWordForward,
+    MoveWordBackward,
+    MoveWordEnd,
+    MoveLineStart,
+    MoveLineEnd,
+    MoveFirstNonBlank,
+    PendingG,
+    CancelPending,
+    GotoFirstLine,
+    GotoLastLine,
+    DeleteSelection,
+    YankSelection,
+    PendingOperator(char),
+    YankLine,
+    Paste,
+    PasteBefore,
+    RestoreRecovery(bool),
+    AnswerSwap(SwapChoice),
+    PromptSaveAs,
+    QuitKey,
+    OpenLineBelow,
+    OpenLineAbove,
+    Append,
+    AppendLineEnd,
+    InsertLineStart,
+    CountDigit(usize),
+    SetSyntax(bool),
+    AwaitRegister,
+    SelectRegister(char),
+    /// `Q` or `@`, waiting for the register to record into or play. `Q`
+    /// while recording stops instead.
+    PendingMacro(char),
+    RecordMacro(char),
+    PlayMacro(char),
+    AwaitReplace,
+    /// `f`, `F`, `t` or `T`, waiting for the char to find.
+    AwaitFind { forward: bool, till: bool },
+    /// `f`, `F`, `t` or `T` after an operator, waiting for the char to find.
+    AwaitOperatorFind { operator: char, forward: bool, till: bool },
+    FindChar(CharFind),
+    /// `;`, or `,` to search the other way.
+    RepeatFind(bool),
+    ReplaceChar(char),
+    JoinLine,
+    DeleteMotion(Motion),
+    GotoLine(usize),
+    NextBuffer,
+    PrevBuffer,
+    ListBuffers,
+    ShowHelp,
+    CloseHelp,
+    /// Scrolls the help screen a line, down when true.
+    ScrollHelp(bool),
+    RepeatChange,
+    Substitute(Substitution),
+    MouseClick(u16, u16),
+    ScrollBy(isize),
+    ToggleCase,
+    AwaitTextObject(char),
+    ChangeMotion(Motion),
+    ForceSave,
+    PendingZ,
+    ScrollCursor(ScrollPosition),
+    MatchBracket,
+    JumpBack,
+    JumpForward,
+    Set(Setting),
+    MoveDisplayLineDown,
+    MoveDisplayLineUp,
+    DeleteWordBack,
+    DeleteToLineStart,
+    DeleteToLineEnd,
+    InsertTab,
+    ShiftLines(bool),
+    ShiftSelection(bool),
+}
+
+impl Actions {
+    /// Actions that may change the buffer text and so go through undo history.
+    fn is_edit(&self) -> bool {
+        matches!(
+            self,
+            Actions::PrintChar(_)
+                | Actions::InsertText(_)
+                | Actions::Backspace
+                | Actions::DeleteWordBack
+                | Actions::DeleteToLineStart
+                | Actions::DeleteToLineEnd
+                | Actions::InsertTab
+                | Actions::ShiftLines(_)
+                | Actions::ShiftSelection(_)
+                | Actions::NewLine
+                | Actions::DeleteLine
+                | Actions::DeleteChar
+                | Actions::StripWhitespace
+                | Actions::Save
+                | Actions::ForceSave
+                | Actions::SaveAs(_)
+                | Actions::WriteQuit
+                | Actions::DeleteSelection
+                | Actions::Paste
+                | Actions::PasteBefore
+                | Actions::RestoreRecovery(_)
+                | Actions::OpenLineBelow
+                | Actions::OpenLineAbove
+                | Actions::ReplaceChar(_)
+                | Actions::JoinLine
+                | Actions::DeleteMotion(_)
+                | Actions::Substitute(_)
+                | Actions::ToggleCase
+                | Actions::ChangeMotion(_)
+                | Actions::ReadFile(_)
+                | Actions::ReadCommand(_)
+        )
+    }
+
+    /// Edits that `.` can repeat. Writing the file and `:` commands are
+    /// left out, as is deleting a visual selection that is gone by then.
+    fn is_repeatable(&self) -> bool {
+        self.is_edit()
+            && !matches!(
+                self,
+                Actions::Save
+                    | Actions::ForceSave
+                    | Actions::SaveAs(_)
+                    | Actions::WriteQuit
+                    | Actions::RestoreRecovery(_)
+                    | Actions::StripWhitespace
+                    | Actions::Substitute(_)
+                    | Actions::DeleteSelection
+                    | Actions::ShiftSelection(_)
+                    | Actions::ReadFile(_)
+                    | Actions::ReadCommand(_)
+            )
+    }
+
+    /// Normal-mode actions that begin a change for `.`: a repeatable edit,
+    /// or a command that starts an Insert or Replace session.
+    fn starts_change(&self) -> bool {
+        self.is_repeatable()
+            || matches!(
+                self,
+                Actions::EnterMode(Mode::Insert | Mode::Replace)
+                    | Actions::Append
+                    | Actions::AppendLineEnd
+                    | Actions::InsertLineStart
+            )
+    }
+
+    /// Actions refused while the editor is read-only: changing or writing the
+    /// text, and starting to type. Answering the recovery question and `:w!`
+    /// still work.
+    fn needs_write_access(&self) -> bool {
+        (self.is_edit() || self.starts_change())
+            && !matches!(self, Actions::RestoreRecovery(_) | Actions::ForceSave)
+    }
+
+    /// Actions that a count prefix repeats, as in `5j` or `3dd`.
+    fn takes_count(&self) -> bool {
+        matches!(
+            self,
+            Actions::MoveUp
+                | Actions::MoveDown
+                | Actions::MoveLeft
+                | Actions::MoveRight
+                | Actions::ToggleCase
+                | Actions::MoveDisplayLineDown
+                | Actions::MoveDisplayLineUp
+                | Actions::JumpBack
+                | Actions::JumpForward
+                | Actions::PlayMacro(_)
+        )
+    }
+
+    /// Moves far enough that the place they leave goes on the jump list.
+    fn is_jump(&self) -> bool {
+        matches!(
+            self,
+            Actions::GotoFirstLine
+                | Actions::GotoLastLine
+                | Actions::GotoLine(_)
+                | Actions::SearchWordForward
+                | Actions::SearchWordBackward
+                | Actions::SearchNext(_)
+                | Actions::MatchBracket
+        )
+    }
+
+    /// Actions that only move the cursor, and so also extend a visual selection.
+    fn is_motion(&self) -> bool {
+        matches!(
+            self,
+            Actions::MoveUp
+                | Actions::MoveDown
+                | Actions::MoveLeft
+                | Actions::MoveRight
+                | Actions::MoveWordForward
+                | Actions::MoveWordBackward
+                | Actions::MoveWordEnd
+                | Actions::MoveLineStart
+                | Actions::MoveLineEnd
+                | Actions::MoveFirstNonBlank
+                | Actions::PendingG
+                | Actions::PendingZ
+                | Actions::MoveDisplayLineDown
+                | Actions::MoveDisplayLineUp
+                | Actions::GotoLastLine
+                | Actions::MatchBracket
+                | Actions::AwaitFind { .. }
+                | Actions::FindChar(_)
+                | Actions::RepeatFind(_)
+        )
+    }
+}
+
+/// An option changed with `:set`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Setting {
+    Number(bool),
+    Wrap(bool),
+    List(bool),
+    AutoIndent(bool),
+    AutoPairs(bool),
+    HlSearch(bool),
+    ReadOnly(bool),
+    TabWidth(usize),
+    ExpandTab(bool),
+    ShiftWidth(usize),
+}
+
+/// Parses what follows `earlier` or `later`: a count of changes, 1 if
+/// left out, or a length of time such as `10s`, `5m` or `1h`.
+fn parse_undo_span(span: &str) -> Option<UndoSpan> {
+    if span.is_empty() {
+        return Some(UndoSpan::Changes(1));
+    }
+    let (digits, unit) = span.split_at(span.find(|c: char| !c.is_ascii_digit()).unwrap_or(span.len()));
+    let count: u64 = digits.parse().ok()?;
+    let seconds = match unit {
+        "" => return Some(UndoSpan::Changes(count.try_into().ok()?)),
+        "s" => count,
+        "m" => count.checked_mul(60)?,
+        "h" => count.checked_mul(3600)?,
+        _ => return None,
+    };
+    Some(UndoSpan::Time(Duration::from_secs(seconds)))
+}
+
+/// Parses what follows `set`: `number` or `nonumber` for a flag (short
+/// vim names work too), `tabwidth=N` for a width.
+fn parse_set(option: &str) -> Option<Setting> {
+    if let Some((name, value)) = option.split_once('=') {
+        let width = value.trim().parse().ok()?;
+        return match name.trim() {
+            "tabwidth" | "tabstop" | "ts" if width > 0 => Some(Setting::TabWidth(width)),
+            "shiftwidth" | "sw" => Some(Setting::ShiftWidth(width)),
+            _ => None,
+        };
+    }
+    let (name, on) = match option.strip_prefix("no") {
+        Some(name) => (name, false),
+        None => (option, true),
+    };
+    match name {
+        "number" | "nu" => Some(Setting::Number(on)),
+        "wrap" => Some(Setting::Wrap(on)),
+        "list" => Some(Setting::List(on)),
+        "autoindent" | "ai" => Some(Setting::AutoIndent(on)),
+        "autopairs" => Some(Setting::AutoPairs(on)),
+        "hlsearch" | "hls" => Some(Setting::HlSearch(on)),
+        "readonly" | "ro" => Some(Setting::ReadOnly(on)),
+        "expandtab" | "et" => Some(Setting::ExpandTab(on)),
+        _ => None,
+    }
+}
+
+/// A parsed `:s/pattern/replacement/flags` command.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Substitution {
+    pub pattern: String,
+    pub replacement: String,
+    /// `g`: every match on a line rather than only the first.
+    pub global: bool,
+    /// `%s`: every line rather than only the cursor line.
+    pub whole_buffer: bool,
+}
+
+/// Parses what follows `s` in `s/old/new/`, or `%s` in `%s/old/new/g`. Any
+/// punctuation can stand in for `/`, and a backslash before it makes it part
+/// of the text.
+fn parse_substitute(rest: &str, whole_buffer: bool) -> Option<Substitution> {
+    let mut chars = rest.chars();
+    let delimiter = chars.next().filter(|c| c.is_ascii_punctuation() && *c != '\\')?;
+    let mut fields = vec![String::new()];
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.clone().next() == Some(delimiter) => {
+                fields.last_mut()?.push(delimiter);
+                chars.next();
+            }
+            c if c == delimiter => fields.push(String::new()),
+            c => fields.last_mut()?.push(c),
+        }
+    }
+    let mut fields = fields.into_iter();
+    let pattern = fields.next().filter(|p| !p.is_empty())?;
+    let replacement = fields.next().unwrap_or_default();
+    let global = match fields.next().as_deref() {
+        None | Some("") => false,
+        Some("g") => true,
+        Some(_) => return None,
+    };
+    if fields.next().is_some() {
+        return None;
+    }
+    Some(Substitution { pattern, replacement, global, whole_buffer })
+}
+
+/// What an operator such as `d` acts on: a motion from the cursor, or a
+/// text object around it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Motion {
+    /// `w`: up to the next word start, stopping at the end of the line.
+    WordForward,
+    /// `$`: to the end of the line.
+    LineEnd,
+    /// `G`: every line down to the end of the buffer.
+    LastLine,
+    /// `iw`: the word, or run of blanks, under the cursor.
+    InnerWord,
+    /// `f`, `t`, `F` or `T`: to the char found on the line, taking it
+    /// along for `f` and `F`.
+    Find(CharFind),
+}
+
+/// Where `zt`, `zz` and `zb` put the cursor line on screen.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScrollPosition {
+    Top,
+    Middle,
+    Bottom,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Mode {
     Normal,
     Insert,
+    Command,
+    Visual,
+    Replace,
 }
 
-pub fn handle_normal_event(ev: Event) -> Option<Actions> {
+pub fn handle_normal_event(keymap: &Keymap, ev: Event) -> Option<Actions> {
     match ev {
-        Event::Key(key) => {
-            use crossterm::event::KeyModifiers;
-            match (key.code, key.modifiers) {
-                (KeyCode::Char('h'), KeyModifiers::NONE) => Some(Actions::MoveLeft),
-                (KeyCode::Char('j'), KeyModifiers::NONE) => Some(Actions::MoveDown),
-                (KeyCode::Char('k'), KeyModifiers::NONE) => Some(Actions::MoveUp),
-                (KeyCode::Char('l'), KeyModifiers::NONE) => Some(Actions::MoveRight),
-                (KeyCode::Char('i'), KeyModifiers::NONE) => Some(Actions::EnterMode(Mode::Insert)),
-                (KeyCode::Char('s'), KeyModifiers::CONTROL) => Some(Actions::Save),
-                (KeyCode::Char('S'), KeyModifiers::CONTROL) => {
-                    // For now, just save to a hardcoded path. We'll add proper UI for this later.
-                    Some(Actions::SaveAs("new_file.txt".to_string()))
-                },
-                (KeyCode::Char('d'), KeyModifiers::CONTROL) => Some(Actions::DeleteLine),
-                _ => None,
-            }
+        Event::Key(key) => keymap.lookup(key),
+        _ => None,
+    }
+}
+
+/// Readline's movement keys and `Ctrl-k`, for Insert mode with
+/// `insert_emacs_keys` on.
+fn emacs_insert_key(code: KeyCode) -> Option<Actions> {
+    match code {
+        KeyCode::Char('a') => Some(Actions::MoveLineStart),
+        KeyCode::Char('e') => Some(Actions::MoveLineEnd),
+        KeyCode::Char('b') => Some(Actions::MoveLeft),
+        KeyCode::Char('f') => Some(Actions::MoveRight),
+        KeyCode::Char('k') => Some(Actions::DeleteToLineEnd),
+        _ => None,
+    }
+}
+
+pub fn handle_insert_event(ev: Event, emacs_keys: bool) -> Option<Actions> {
+    use crossterm::event::KeyModifiers;
+    match ev {
+        Event::Key(key) if emacs_keys
+            && key.modifiers == KeyModifiers::CONTROL
+            && let Some(action) = emacs_insert_key(key.code) =>
+        {
+            Some(action)
+        }
+        Event::Key(key) if key.code == KeyCode::Char('o') && key.modifiers == KeyModifiers::CONTROL => {
+            Some(Actions::InsertNormalOnce)
+        }
+        Event::Key(key) if key.code == KeyCode::Char('w') && key.modifiers == KeyModifiers::CONTROL => {
+            Some(Actions::DeleteWordBack)
+        }
+        Event::Key(key) if key.code == KeyCode::Char('u') && key.modifiers == KeyModifiers::CONTROL => {
+            Some(Actions::DeleteToLineStart)
+        }
+        Event::Key(key) => match key.code {
+            KeyCode::Esc => Some(Actions::EnterMode(Mode::Normal)),
+            // an unbound Ctrl key types nothing rather than its letter
+            KeyCode::Char(_) if key.modifiers.contains(KeyModifiers::CONTROL) => None,
+            KeyCode::Char(c) => Some(Actions::PrintChar(c)),
+            KeyCode::Tab => Some(Actions::InsertTab),
+            KeyCode::Backspace => Some(Actions::Backspace),
+            KeyCode::Enter => Some(Actions::NewLine),
+            _ => None,
         },
         _ => None,
     }
 }
 
-pub fn handle_insert_event(ev: Event) -> Option<Actions> {
+/// Like Insert mode, except that typed characters overwrite the text.
+pub fn handle_replace_event(ev: Event) -> Option<Actions> {
+    use crossterm::event::KeyModifiers;
     match ev {
         Event::Key(key) => match key.code {
             KeyCode::Esc => Some(Actions::EnterMode(Mode::Normal)),
+            KeyCode::Char(_) if key.modifiers.contains(KeyModifiers::CONTROL) => None,
             KeyCode::Char(c) => Some(Actions::PrintChar(c)),
             KeyCode::Backspace => Some(Actions::Backspace),
             KeyCode::Enter => Some(Actions::NewLine),
@@ -63,120 +482,1456 @@ pub fn handle_insert_event(ev: Event) -> Option<Actions> {
     }
 }
 
-use crate::buffer::Buffer;
+pub fn handle_command_event(ev: Event) -> Option<Actions> {
+    match ev {
+        Event::Key(key) => match key.code {
+            KeyCode::Esc => Some(Actions::EnterMode(Mode::Normal)),
+            KeyCode::Char(c) => Some(Actions::CommandChar(c)),
+            KeyCode::Backspace => Some(Actions::CommandBackspace),
+            KeyCode::Enter => Some(Actions::ExecuteCommand),
+            KeyCode::Tab => Some(Actions::CompleteCommand),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The key after an operator: doubling it (`dd`, `yy`) acts on the whole
+/// line, a motion key acts from the cursor to where the motion goes, and
+/// `i` starts a text object. `object` is set once `i` has been typed.
+pub fn handle_operator_event(operator: char, object: bool, ev: Event) -> Option<Actions> {
+    let Event::Key(key) = ev else {
+        return Some(Actions::CancelPending);
+    };
+    if object {
+        return match (operator, key.code) {
+            ('d', KeyCode::Char('w')) => Some(Actions::DeleteMotion(Motion::InnerWord)),
+            ('c', KeyCode::Char('w')) => Some(Actions::ChangeMotion(Motion::InnerWord)),
+            _ => Some(Actions::CancelPending),
+        };
+    }
+    match (operator, key.code) {
+        ('d' | 'c', KeyCode::Char('i')) => Some(Actions::AwaitTextObject(operator)),
+        ('d' | 'c', KeyCode::Char(c @ ('f' | 'F' | 't' | 'T'))) => Some(Actions::AwaitOperatorFind {
+            operator,
+            forward: c.is_ascii_lowercase(),
+            till: c.eq_ignore_ascii_case(&'t'),
+        }),
+        ('c', KeyCode::Char('$')) => Some(Actions::ChangeMotion(Motion::LineEnd)),
+        ('d', KeyCode::Char('d')) => Some(Actions::DeleteLine),
+        ('d', KeyCode::Char('w')) => Some(Actions::DeleteMotion(Motion::WordForward)),
+        ('d', KeyCode::Char('$')) => Some(Actions::DeleteMotion(Motion::LineEnd)),
+        ('d', KeyCode::Char('G')) => Some(Actions::DeleteMotion(Motion::LastLine)),
+        ('y', KeyCode::Char('y')) => Some(Actions::YankLine),
+        ('>', KeyCode::Char('>')) => Some(Actions::ShiftLines(true)),
+        ('<', KeyCode::Char('<')) => Some(Actions::ShiftLines(false)),
+        _ => Some(Actions::CancelPending),
+    }
+}
+
+/// Lines the view moves for one notch of the scroll wheel.
+const MOUSE_SCROLL_LINES: isize = 3;
+
+/// A left click puts the cursor on the clicked cell; the wheel scrolls.
+pub fn handle_mouse_event(ev: MouseEvent) -> Option<Actions> {
+    match ev.kind {
+        MouseEventKind::Down(MouseButton::Left) => Some(Actions::MouseClick(ev.column, ev.row)),
+        MouseEventKind::ScrollUp => Some(Actions::ScrollBy(-MOUSE_SCROLL_LINES)),
+        MouseEventKind::ScrollDown => Some(Actions::ScrollBy(MOUSE_SCROLL_LINES)),
+        _ => None,
+    }
+}
+
+/// Scrolls and closes the help screen; nothing else works while it is up.
+pub fn handle_help_event(ev: Event) -> Option<Actions> {
+    match ev {
+        Event::Key(key) => match key.code {
+            KeyCode::Esc | KeyCode::Char('q') => Some(Actions::CloseHelp),
+            KeyCode::Char('j') | KeyCode::Down => Some(Actions::ScrollHelp(true)),
+            KeyCode::Char('k') | KeyCode::Up => Some(Actions::ScrollHelp(false)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Answers the startup question about restoring a recovery file.
+pub fn handle_recovery_prompt(ev: Event) -> Option<Actions> {
+    match ev {
+        Event::Key(key) => match key.code {
+            KeyCode::Char('y') | KeyCode::Char('Y') => Some(Actions::RestoreRecovery(true)),
+            KeyCode::Char('n') | KeyCode::Char('N') | KeyCode::Esc => Some(Actions::RestoreRecovery(false)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Answers the question about a swap file left by another process. Deleting
+/// it is only offered once that process is gone.
+pub fn handle_swap_prompt(ev: Event, owner: SwapOwner) -> Option<Actions> {
+    match ev {
+        Event::Key(key) => match key.code {
+            KeyCode::Char('o') | KeyCode::Char('O') => Some(Actions::AnswerSwap(SwapChoice::ReadOnly)),
+            KeyCode::Char('e') | KeyCode::Char('E') => Some(Actions::AnswerSwap(SwapChoice::EditAnyway)),
+            KeyCode::Char('d') | KeyCode::Char('D') if !owner.alive => Some(Actions::AnswerSwap(SwapChoice::Delete)),
+            KeyCode::Char('q') | KeyCode::Char('Q') => Some(Actions::AnswerSwap(SwapChoice::Quit)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+pub fn handle_visual_event(keymap: &Keymap, ev: Event) -> Option<Actions> {
+    match ev {
+        Event::Key(key) => match key.code {
+            KeyCode::Esc | KeyCode::Char('v') => Some(Actions::EnterMode(Mode::Normal)),
+            KeyCode::Char('d') | KeyCode::Char('x') => Some(Actions::DeleteSelection),
+            KeyCode::Char('y') => Some(Actions::YankSelection),
+            KeyCode::Char('>') => Some(Actions::ShiftSelection(true)),
+            KeyCode::Char('<') => Some(Actions::ShiftSelection(false)),
+            KeyCode::Char(':') => Some(Actions::EnterMode(Mode::Command)),
+            _ => handle_normal_event(keymap, ev).filter(Actions::is_motion),
+        },
+        _ => None,
+    }
+}
+
+/// A `:` command: the names it goes by, what it takes after the name, and
+/// the action it makes of that, if the argument will do.
+struct Command {
+    names: &'static [&'static str],
+    argument: Argument,
+    parse: fn(&str) -> Option<Actions>,
+}
+
+/// Every `:` command, for parsing, Tab completion and the help screen. Only
+/// a bare line number is parsed without being listed here.
+const COMMANDS: &[Command] = &[
+    Command {
+        names: &["w"],
+        argument: Argument::Path,
+        parse: |path| Some(if path.is_empty() { Actions::Save } else { Actions::WriteCopy(path.to_string()) }),
+    },
+    Command { names: &["w!"], argument: Argument::None, parse: |arg| arg.is_empty().then_some(Actions::ForceSave) },
+    Command { names: &["e", "edit"], argument: Argument::Path, parse: |path| Some(edit_action(path, false)) },
+    Command { names: &["e!", "edit!"], argument: Argument::Path, parse: |path| Some(edit_action(path, true)) },
+    Command { names: &["r", "read"], argument: Argument::Path, parse: |source| Some(read_action(source)) },
+    Command {
+        names: &["r!", "read!"],
+        argument: Argument::Text,
+        parse: |shell| Some(Actions::ReadCommand(shell.to_string())),
+    },
+    Command { names: &["q"], argument: Argument::None, parse: |arg| arg.is_empty().then_some(Actions::Quit) },
+    Command { names: &["q!"], argument: Argument::None, parse: |arg| arg.is_empty().then_some(Actions::ForceQuit) },
+    Command { names: &["wq", "x"], argument: Argument::None, parse: |arg| arg.is_empty().then_some(Actions::WriteQuit) },
+    Command {
+        names: &["strip", "stripws"],
+        argument: Argument::None,
+        parse: |arg| arg.is_empty().then_some(Actions::StripWhitespace),
+    },
+    Command { names: &["diff"], argument: Argument::None, parse: |arg| arg.is_empty().then_some(Actions::DiffSummary) },
+    Command { names: &[">"], argument: Argument::None, parse: |arg| arg.is_empty().then_some(Actions::ShiftLines(true)) },
+    Command { names: &["<"], argument: Argument::None, parse: |arg| arg.is_empty().then_some(Actions::ShiftLines(false)) },
+    Command { names: &["checktime"], argument: Argument::None, parse: |arg| arg.is_empty().then_some(Actions::CheckTime) },
+    Command {
+        names: &["noh", "nohlsearch"],
+        argument: Argument::None,
+        parse: |arg| arg.is_empty().then_some(Actions::ClearHighlight),
+    },
+    Command {
+        names: &["syntax"],
+        argument: Argument::Text,
+        parse: |arg| match arg {
+            "on" => Some(Actions::SetSyntax(true)),
+            "off" => Some(Actions::SetSyntax(false)),
+            _ => None,
+        },
+    },
+    Command { names: &["bn", "bnext"], argument: Argument::None, parse: |arg| arg.is_empty().then_some(Actions::NextBuffer) },
+    Command {
+        names: &["bp", "bprevious"],
+        argument: Argument::None,
+        parse: |arg| arg.is_empty().then_some(Actions::PrevBuffer),
+    },
+    Command {
+        names: &["ls", "buffers"],
+        argument: Argument::None,
+        parse: |arg| arg.is_empty().then_some(Actions::ListBuffers),
+    },
+    Command { names: &["h", "help"], argument: Argument::None, parse: |arg| arg.is_empty().then_some(Actions::ShowHelp) },
+    Command {
+        names: &["startinsert"],
+        argument: Argument::None,
+        parse: |arg| arg.is_empty().then_some(Actions::EnterMode(Mode::Insert)),
+    },
+    Command { names: &["set"], argument: Argument::Text, parse: |option| parse_set(option).map(Actions::Set) },
+    Command { names: &["earlier"], argument: Argument::Text, parse: |span| parse_undo_span(span).map(Actions::Earlier) },
+    Command { names: &["later"], argument: Argument::Text, parse: |span| parse_undo_span(span).map(Actions::Later) },
+    Command {
+        names: &["s"],
+        argument: Argument::Text,
+        parse: |rest| parse_substitute(rest, false).map(Actions::Substitute),
+    },
+    Command {
+        names: &["%s"],
+        argument: Argument::Text,
+        parse: |rest| parse_substitute(rest, true).map(Actions::Substitute),
+    },
+];
+
+/// What `:` from Visual mode puts on the command line, standing for the
+/// selected lines.
+const VISUAL_RANGE: &str = "'<,'>";
+
+/// Every command name with what it takes after it, for Tab completion.
+pub fn command_names() -> Vec<(&'static str, Argument)> {
+    COMMANDS.iter().flat_map(|command| command.names.iter().map(|&name| (name, command.argument))).collect()
+}
+
+/// Splits a command line into the command's name and what follows it: a
+/// word with an optional `%` before it and `!` after it, or else a single
+/// symbol such as `>`.
+fn split_command(command: &str) -> (&str, &str) {
+    let word = command.strip_prefix('%').unwrap_or(command);
+    let mut end = command.len() - word.len() + word.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(word.len());
+    if command[end..].starts_with('!') {
+        end += 1;
+    }
+    if end == 0 {
+        end = command.chars().next().map_or(0, char::len_utf8);
+    }
+    let (name, rest) = command.split_at(end);
+    (name, rest.trim())
+}
+
+/// Splits a `--exec` script into its commands at each `|`, dropping a
+/// leading `:` from each. `\|` stands for a `|` that does not split, and a
+/// `|` inside the pattern or replacement of an `s` is left alone.
+fn split_script(script: &str) -> Vec<String> {
+    let mut commands = vec![String::new()];
+    let mut chars = script.chars().peekable();
+    while let Some(c) = chars.next() {
+        let Some(command) = commands.last_mut() else { break };
+        let in_substitute = in_substitute_fields(command);
+        match c {
+            '\\' if !in_substitute && chars.peek() == Some(&'|') => {
+                command.push('|');
+                chars.next();
+            }
+            '|' if !in_substitute => commands.push(String::new()),
+            c => command.push(c),
+        }
+    }
+    commands
+        .iter()
+        .map(|command| command.trim().trim_start_matches(':').trim().to_string())
+        .filter(|command| !command.is_empty())
+        .collect()
+}
+
+/// Whether the start of a command is an `s` that has not yet closed its
+/// pattern and replacement, counting one still waiting for its delimiter.
+fn in_substitute_fields(command: &str) -> bool {
+    let (name, rest) = split_command(command.trim_start().trim_start_matches(':').trim_start());
+    if name != "s" && name != "%s" {
+        return false;
+    }
+    let mut chars = rest.chars();
+    let Some(delimiter) = chars.next() else { return true };
+    if !delimiter.is_ascii_punctuation() || delimiter == '\\' {
+        return false;
+    }
+    let mut closed = 0;
+    while let Some(c) = chars.next() {
+        if c == '\\' && chars.clone().next() == Some(delimiter) {
+            chars.next();
+        } else if c == delimiter {
+            closed += 1;
+        }
+    }
+    closed < 2
+}
+
+/// Parses the text typed after `:` into the action it stands for.
+pub fn parse_command(command: &str) -> Option<Actions> {
+    let command = command.trim();
+    if let Ok(line) = command.parse() {
+        return Some(Actions::GotoLine(line));
+    }
+    let (name, rest) = split_command(command);
+    let command = COMMANDS.iter().find(|c| c.names.contains(&name))?;
+    (command.parse)(rest)
+}
+
+/// The help screen: every Normal-mode key as currently bound, then the `:`
+/// commands.
+pub fn help_lines(keymap: &Keymap) -> Vec<String> {
+    let mut lines = vec![
+        format!("vix - version {}", env!("CARGO_PKG_VERSION")),
+        String::new(),
+        "Normal mode keys:".to_string(),
+    ];
+    let bound = keymap.bound_keys();
+    lines.extend(bound.iter().map(|&(name, key)| {
+        format!("  {:<10}{}", keymap::key_name(key), name.replace('_', " "))
+    }));
+    let bound_name = |action| bound.iter().find(|(name, _)| *name == action).map(|&(_, key)| keymap::key_name(key));
+    if let Some(record) = bound_name("record_macro") {
+        lines.push(String::new());
+        lines.push("Macros:".to_string());
+        lines.push(format!("  {0}{{reg}} records keys into a register until {0} again", record));
+        if let Some(play) = bound_name("play_macro") {
+            lines.push(format!("  {0}{{reg}} plays them back, {0}{0} the last one again", play));
+        }
+        if bound_name("quit").as_deref() == Some("q") {
+            lines.push(format!("  (q quits, so {} stands in for vim's q)", record));
+        }
+    }
+    lines.push(String::new());
+    lines.push("Commands:".to_string());
+    lines.extend(COMMANDS.iter().map(|command| {
+        let names = command.names.join(", :");
+        match command.argument {
+            Argument::None => format!("  :{}", names),
+            Argument::Path => format!("  :{} [file]", names),
+            Argument::Text => format!("  :{} ...", names),
+        }
+    }));
+    lines
+}
+
+/// The char that closes a bracket or quote, for `auto_pairs`.
+fn closing_pair(c: char) -> Option<char> {
+    match c {
+        '(' => Some(')'),
+        '[' => Some(']'),
+        '{' => Some('}'),
+        '"' | '\'' => Some(c),
+        _ => None,
+    }
+}
+
+/// `:e` and `:edit`, forced with `!`, with or without a path.
+fn edit_action(path: &str, force: bool) -> Actions {
+    Actions::EditFile((!path.is_empty()).then(|| path.to_string()), force)
+}
+
+/// What `:r` reads: a path, nothing for the current file, or `!` and a
+/// shell command.
+fn read_action(source: &str) -> Actions {
+    match source.strip_prefix('!') {
+        Some(shell) => Actions::ReadCommand(shell.trim().to_string()),
+        None => Actions::ReadFile((!source.is_empty()).then(|| source.to_string())),
+    }
+}
+
+/// Runs the formatter `commands` has for the extension of `path` on the
+/// file, with the path as its last argument. `None` when there is none.
+fn run_formatter(commands: &HashMap<String, Vec<String>>, path: &Path) -> Option<Result<(), String>> {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_default();
+    let (program, args) = commands.get(extension)?.split_first()?;
+    info!("Formatting {} with {}", path.display(), program);
+    Some(match process::Command::new(program).args(args).arg(path).output() {
+        Ok(output) if output.status.success() => Ok(()),
+        Ok(output) => {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            warn!("Formatter exited with {}: {}", output.status, stderr.trim());
+            Err(output.status.to_string())
+        }
+        Err(e) => {
+            warn!("Could not run formatter {}: {}", program, e);
+            Err(e.to_string())
+        }
+    })
+}
+
+/// Where the view is in the buffer, as vim shows it: `All` when every line
+/// fits, `Top` or `Bot` at either end, otherwise how far the top visible
+/// line is through the lines that can be at the top.
+fn scroll_indicator(row_offset: usize, total_lines: usize, visible_height: usize) -> String {
+    if total_lines <= visible_height {
+        "All".to_string()
+    } else if row_offset == 0 {
+        "Top".to_string()
+    } else if row_offset >= total_lines - visible_height {
+        "Bot".to_string()
+    } else {
+        format!("{}%", row_offset * 100 / (total_lines - visible_height))
+    }
+}
+
+/// The status bar as the editor has always drawn it.
+const DEFAULT_STATUSLINE: &str = "%M > %f%m%r >%=Ln %l Col %c  %p";
+
+/// The `%` tokens `statusline_field` fills in.
+const STATUSLINE_TOKENS: &str = "Mfmrlcpy";
+
+/// Expands a `statusline` template into the left and right sides of the
+/// status bar, split at `%=`. `field` gives the text for the `%` tokens it
+/// knows; any other token is kept as it is, and `%%` is a literal `%`.
+fn expand_statusline(template: &str, mut field: impl FnMut(char) -> Option<String>) -> (String, String) {
+    let (mut left, mut right) = (String::new(), String::new());
+    let mut on_right = false;
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        let side = if on_right { &mut right } else { &mut left };
+        if c != '%' {
+            side.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('=') => on_right = true,
+            Some('%') => side.push('%'),
+            Some(token) => match field(token) {
+                Some(text) => side.push_str(&text),
+                None => {
+                    side.push('%');
+                    side.push(token);
+                }
+            },
+            None => side.push('%'),
+        }
+    }
+    (left, right)
+}
+
+/// The `%` tokens in a `statusline` template that the status bar doesn't
+/// fill in, so a config can be checked once rather than on every redraw.
+pub fn unknown_statusline_tokens(template: &str) -> Vec<char> {
+    let mut unknown = Vec::new();
+    expand_statusline(template, |token| {
+        if !STATUSLINE_TOKENS.contains(token) {
+            unknown.push(token);
+        }
+        Some(String::new())
+    });
+    unknown
+}
+
+/// Where a buffer line is drawn on screen.
+struct LineLayout {
+    index: usize,
+    /// Screen row of the line's first display row.
+    y: usize,
+    /// The chars each display row starts at; just `[0]` unless wrapped.
+    starts: Vec<usize>,
+    /// Screen column of every char boundary, as from [`buffer::visual_cols`].
+    cols: Vec<usize>,
+}
+
+impl LineLayout {
+    fn new(line: &str, index: usize, y: usize, wrap_width: Option<usize>, tab_width: usize) -> Self {
+        let starts = match wrap_width {
+            Some(width) => buffer::wrap_starts(line, width, tab_width),
+            None => vec![0],
+        };
+        Self { index, y, starts, cols: buffer::visual_cols(line, tab_width) }
+    }
+
+    /// The display row of char `col` within the line, and its column within
+    /// that row. One past the last char is placed just after it.
+    fn place(&self, col: usize) -> (usize, usize) {
+        let col = col.min(self.cols.len() - 1);
+        let row = self.starts.partition_point(|&start| start <= col) - 1;
+        (row, self.cols[col] - self.cols[self.starts[row]])
+    }
+
+    /// Screen cells, as `(x, y)` before the gutter, covered by chars `range`.
+    fn cells(&self, range: Range<usize>) -> impl Iterator<Item = (usize, usize)> + '_ {
+        range.filter(|&col| col + 1 < self.cols.len()).flat_map(move |col| {
+            let (row, x) = self.place(col);
+            let width = self.cols[col + 1] - self.cols[col];
+            (x..x + width).map(move |x| (x, self.y + row))
+        })
+    }
+}
+
+/// What each char of `line` is drawn as, given the screen column of every
+/// char boundary: a tab fills the columns up to its stop with spaces. With
+/// `list` set, a tab starts with `→` and each trailing space is a `·`; the
+/// flag marks these so they can be dimmed. Columns are the same either way.
+fn draw_chars(line: &str, cols: &[usize], list: bool) -> Vec<(String, bool)> {
+    let trailing = line.trim_end_matches([' ', '\t']).chars().count();
+    line.chars()
+        .enumerate()
+        .map(|(col, c)| match c {
+            '\t' => {
+                let width = cols[col + 1] - cols[col];
+                if list {
+                    (format!("→{}", " ".repeat(width - 1)), true)
+                } else {
+                    (" ".repeat(width), false)
+                }
+            }
+            ' ' if list && col >= trailing => ("·".to_string(), true),
+            c => (c.to_string(), false),
+        })
+        .collect()
+}
+
+/// A line as drawn: each char as from [`draw_chars`], and its highlighting.
+#[derive(Debug)]
+struct RenderedLine {
+    chars: Vec<(String, bool)>,
+    spans: Vec<syntax::Span>,
+}
+
+/// What a line is drawn with besides its text: the tab width, `list`, and
+/// the language it is highlighted as, if it is.
+type RenderSettings = (usize, bool, Option<Language>);
+
+/// Lines drawn in the last frame, by text, so that those unchanged are not
+/// expanded and highlighted again on every draw. An entry lasts while its
+/// line keeps the same text and stays on screen; other settings drop them
+/// all. Wrapping only moves chars to other rows, so the width plays no part.
+#[derive(Debug, Default)]
+struct RenderCache {
+    settings: Option<RenderSettings>,
+    lines: HashMap<String, Rc<RenderedLine>>,
+    /// Entries the frame being drawn has used, which become `lines` once
+    /// it is done.
+    used: HashMap<String, Rc<RenderedLine>>,
+}
+
+impl RenderCache {
+    /// `line` as drawn with `settings`, given the screen column of every
+    /// char boundary.
+    fn get(&mut self, line: &str, cols: &[usize], settings: RenderSettings) -> Rc<RenderedLine> {
+        if self.settings != Some(settings) {
+            self.settings = Some(settings);
+            self.lines.clear();
+            self.used.clear();
+        }
+        if let Some(rendered) = self.used.get(line) {
+            return rendered.clone();
+        }
+        let (_, list, language) = settings;
+        let rendered = self.lines.remove(line).unwrap_or_else(|| {
+            Rc::new(RenderedLine {
+                chars: draw_chars(line, cols, list),
+                spans: language.map(|language| syntax::highlight_line(line, language)).unwrap_or_default(),
+            })
+        });
+        self.used.insert(line.to_string(), rendered.clone());
+        rendered
+    }
+
+    /// Drops the entries the frame just drawn did not use.
+    fn finish_frame(&mut self) {
+        self.lines = std::mem::take(&mut self.used);
+    }
+}
+
+/// The top line of a view `visible_height` lines tall that shows line `cy`
+/// at `position`, never past the last line of a buffer `total` lines long.
+fn scroll_cursor_to(position: ScrollPosition, cy: usize, visible_height: usize, total: usize) -> usize {
+    let top = match position {
+        ScrollPosition::Top => cy,
+        ScrollPosition::Middle => cy.saturating_sub(visible_height / 2),
+        ScrollPosition::Bottom => (cy + 1).saturating_sub(visible_height),
+    };
+    top.min(total.saturating_sub(1))
+}
+
+/// The top line of a view `visible_height` lines tall that shows line `cy`,
+/// moving as little as possible from `row_offset`.
+fn scroll_to_line(row_offset: usize, cy: usize, visible_height: usize) -> usize {
+    if cy < row_offset {
+        cy
+    } else if cy >= row_offset + visible_height {
+        cy + 1 - visible_height.max(1)
+    } else {
+        row_offset
+    }
+}
+
+use crate::buffer::{self, Buffer, BufferError, CharFind, DiffOp, DiskChange};
+use crate::clipboard::{self, Clipboard};
+use crate::completion::{self, Argument};
+use crate::keymap::{self, Keymap};
+use crate::positions::Positions;
+use crate::syntax::{self, IndentStyle, Language};
+use crate::screen::{self, Cell, Frame};
+use crate::swap::{self, SwapChoice, SwapOwner};
+use crate::undo::{UndoHistory, UndoSpan};
+
+/// A buffer that isn't on screen, with the view and history to go back to.
+#[derive(Default)]
+struct BufferSlot {
+    buffer: Buffer,
+    cx: usize,
+    cy: usize,
+    row_offset: usize,
+    history: UndoHistory,
+    read_only: bool,
+}
+
+/// A file whose swap file belongs to another process, while asking what
+/// to do about it.
+struct SwapPrompt {
+    file: String,
+    owner: SwapOwner,
+    /// The buffer that was on screen before, which Quit goes back to rather
+    /// than leaving the editor. `None` at startup.
+    previous: Option<usize>,
+    /// The buffer was only just opened by `:e`, so going back closes it.
+    opened: bool,
+}
 
 pub struct Editor {
+    /// The buffer on screen.
     pub buffer: Buffer,
-    pub cx: u16,
-    pub cy: u16,
+    /// Every open buffer in `:ls` order. The slot at `current` is empty while
+    /// its buffer is the one in `buffer`.
+    buffers: Vec<BufferSlot>,
+    current: usize,
+    pub cx: usize,
+    pub cy: usize,
     pub row_offset: usize,
     pub mode: Mode,
     pub status_message: Option<String>,
+    pub start_screen: bool,
+    pub command_line: String,
+    /// The lines a command typed after `:` from Visual mode applies to,
+    /// while it runs.
+    pub command_range: Option<Range<usize>>,
+    /// The candidates Tab last offered on the command line, and which one
+    /// it is showing.
+    completion: Option<(Vec<String>, usize)>,
+    /// Set by `Ctrl-o` in Insert mode: the next Normal-mode command runs and
+    /// then the editor drops back into Insert mode.
+    pub insert_oneshot: bool,
+    /// Set while the command line is reading a file name to save as.
+    pub save_as_prompt: bool,
+    /// Set after `g` in Normal mode while waiting for the second key.
+    pub pending_g: bool,
+    /// Set after `z` while waiting for `z`, `t` or `b`.
+    pub pending_z: bool,
+    /// The fixed end of a visual selection; the cursor is the moving end.
+    pub vx: usize,
+    pub vy: usize,
+    /// Digits typed before a Normal-mode command, as in `5j`.
+    pub pending_count: Option<usize>,
+    /// Set after an operator key such as `d` while waiting for what it acts on.
+    pub pending_operator: Option<char>,
+    /// Set after the `i` of a text object such as `diw`, waiting for its kind.
+    pub pending_object: bool,
+    /// Text most recently yanked or deleted, one entry per line.
+    pub register: Vec<String>,
+    /// Whether `register` holds whole lines rather than a run of characters.
+    pub register_linewise: bool,
+    /// Set after `r` while waiting for the replacement character.
+    pub awaiting_replace: bool,
+    /// Set after `f`, `F`, `t` or `T` while waiting for the char to find.
+    awaiting_find: Option<(bool, bool)>,
+    /// The last `f`, `F`, `t` or `T`, for `;` and `,`.
+    last_find: Option<CharFind>,
+    /// Characters overwritten in the current Replace-mode session, so
+    /// Backspace can put them back. `None` marks a char typed past the end.
+    replaced: Vec<Option<char>>,
+    /// Set after `"` while waiting for the register name.
+    pub awaiting_register: bool,
+    /// Register named with `"x` for the next yank or paste; `+` and `*` mean
+    /// the system clipboard.
+    pub register_name: Option<char>,
+    /// Keys recorded with `Q`, by register, for `@` to play back.
+    pub macros: HashMap<char, Vec<Event>>,
+    /// The register `Q` is recording keys into.
+    pub macro_register: Option<char>,
+    /// `Q` or `@`, typed and waiting for the register name.
+    pub pending_macro: Option<char>,
+    /// The macro `@@` plays again.
+    last_macro: Option<char>,
+    /// Macros being played, innermost last, so that none plays itself.
+    playing_macros: Vec<char>,
+    /// Always yank to and paste from the system clipboard.
+    pub use_system_clipboard: bool,
+    pub clipboard: Option<Box<dyn Clipboard>>,
+    /// When set, `h` at column 0 and `l` at the end of a line continue onto
+    /// the neighbouring line instead of stopping.
+    pub whichwrap: bool,
+    pub show_line_numbers: bool,
+    /// What the status bar shows when there's no message, with `%` tokens
+    /// filled in; see [`expand_statusline`].
+    pub statusline: String,
+    /// Capture the mouse for clicking and scrolling. Off leaves the mouse to
+    /// the terminal, for its own text selection.
+    pub mouse: bool,
+    /// Let `:r !cmd` run commands through the shell. Off by default, so
+    /// that a command line typed by mistake can't run anything.
+    pub allow_shell: bool,
+    /// New lines opened with Enter, `o` or `O` start with the indentation of
+    /// the line the cursor was on.
+    pub auto_indent: bool,
+    /// Typing an opening bracket or quote in Insert mode adds its closing
+    /// one after the cursor.
+    pub auto_pairs: bool,
+    /// `Ctrl-a`, `Ctrl-e`, `Ctrl-b`, `Ctrl-f` and `Ctrl-k` work in Insert
+    /// mode as they do in a shell.
+    pub insert_emacs_keys: bool,
+    /// Highlight the matches of the last search.
+    pub hlsearch: bool,
+    /// The word last searched for with `*` or `#`, and whether forward.
+    last_search: Option<(String, bool)>,
+    /// Whether the last search's matches are highlighted right now; `:noh`
+    /// clears it until the next search.
+    search_highlight: bool,
+    /// Normal-mode key bindings, possibly remapped by the config file.
+    pub keymap: Keymap,
+    /// How far the help screen is scrolled, while it is shown over the text.
+    pub help: Option<usize>,
+    /// Colour keywords, strings, comments and numbers when drawing.
+    pub syntax_highlighting: bool,
+    /// Columns between tab stops when drawing tab characters.
+    pub tab_width: usize,
+    /// Type spaces rather than a tab character for Tab.
+    pub expand_tab: bool,
+    /// Whether `tab_width` and `expand_tab` were given in the config file or
+    /// with `:set`; a file's type no longer changes them once they were.
+    pub tab_width_set: bool,
+    pub expand_tab_set: bool,
+    /// Columns in one level of indentation; 0 follows `tab_width`.
+    pub shift_width: usize,
+    /// Strip trailing whitespace from every line when writing with `:w`,
+    /// `:wq` or save-as. Autosave leaves it alone, since it runs mid-edit.
+    pub trim_trailing_whitespace: bool,
+    /// Show tabs and trailing spaces, with `:set list`.
+    pub list: bool,
+    /// Break lines too long for the screen across several rows rather than
+    /// cutting them off at the right edge.
+    pub wrap: bool,
+    /// Refuse edits and writes to the buffer on screen, for browsing a file
+    /// without risk; `:w!` still writes. Each buffer keeps its own.
+    pub read_only: bool,
+    /// Keep a swap file next to each file on screen while it is open.
+    pub swap_file: bool,
+    /// Files whose swap file this editor wrote and removes on quit.
+    swaps: Vec<String>,
+    swap_prompt: Option<SwapPrompt>,
+    /// When the swap files were last rewritten, or when the clock started.
+    last_swap_write: Option<Instant>,
+    /// Set once the user asked to leave the editor.
+    pub quit: bool,
+    /// Set after `q` was refused because of unsaved changes; a second `q`
+    /// straight after quits anyway.
+    pub quit_confirm: bool,
+    history: UndoHistory,
+    /// The last change with its count, replayed by `.`: a single edit, or
+    /// everything from entering Insert mode to leaving it.
+    last_change: Option<(Option<usize>, Vec<Actions>)>,
+    /// The change being typed, moved to `last_change` once it is complete.
+    recording: Option<(Option<usize>, Vec<Actions>)>,
+    /// Set while `.` replays a change, so it isn't recorded again.
+    replaying: bool,
+    /// Formatter command per file extension, run on the file after each save.
+    /// The saved path is appended as the last argument.
+    pub format_commands: HashMap<String, Vec<String>>,
+    /// How the formatter the post-save hook ran went, until
+    /// `format_after_save` picks it up: `Err` holds why it failed.
+    format_result: Rc<RefCell<Option<Result<(), String>>>>,
+    /// The frame currently on screen, used to emit only changed cells.
+    last_frame: Option<Frame>,
+    render_cache: RenderCache,
+    /// Clear the terminal and draw every cell on the next draw, for when the
+    /// screen no longer matches `last_frame` (after a resize, say).
+    pub force_redraw: bool,
+    /// Where to scroll the cursor line to on the next draw, rather than
+    /// scrolling only as far as needed to keep it on screen.
+    scroll_cursor: Option<ScrollPosition>,
+    /// Cursor positions remembered across sessions, updated on save.
+    pub positions: Option<Positions>,
+    /// How long a status message stays before the cursor position shows
+    /// again; `None` keeps it until something replaces it.
+    pub status_timeout: Option<Duration>,
+    /// The status message as first drawn and when, to time it out.
+    status_shown: Option<(String, Instant)>,
+    /// Places jumped away from, oldest first, for `Ctrl-o` and `Ctrl-i`.
+    jumps: Vec<(usize, usize)>,
+    /// Where `Ctrl-o` and `Ctrl-i` are in `jumps`; its length when not
+    /// moving through it.
+    jump_index: usize,
+    /// Write unsaved changes this often: to the file, or to a recovery file
+    /// for a buffer with no name.
+    pub autosave_interval: Option<Duration>,
+    /// When changes were last autosaved, or when the clock started.
+    last_autosave: Option<Instant>,
+    /// Where a buffer with no name is autosaved, under `~/.vix`; `None`
+    /// leaves it alone.
+    pub unnamed_autosave: Option<PathBuf>,
 }
 
+const DEFAULT_TAB_WIDTH: usize = 4;
+/// Jump list entries kept before the oldest are dropped.
+const MAX_JUMPS: usize = 100;
+/// The largest count a prefix can give, so that a long run of digits
+/// can't keep a repeated command busy for ages.
+const MAX_COUNT: usize = 99_999;
+const DEFAULT_STATUS_TIMEOUT: Duration = Duration::from_secs(4);
+/// How often swap files are rewritten to show the editor is still running.
+const SWAP_INTERVAL: Duration = Duration::from_secs(60);
+
+const START_SCREEN: &[&str] = &[
+    "i        enter insert mode",
+    "Esc      back to normal mode",
+    ":w       save",
+    ":q       quit",
+];
+
 impl Editor {
     pub fn new() -> Self {
-        Self {
-            buffer: Buffer { file: None, lines: vec![String::new()], modified: false },
-            cx: 0,
-            cy: 0,
-            row_offset: 0,
-            mode: Mode::Normal,
-            status_message: None,
-        }
+        Self::with_buffer(Buffer { lines: vec![String::new()], ..Default::default() })
     }
 
     pub fn with_buffer(buffer: Buffer) -> Self {
+        let indent = syntax::indent_style(buffer.file.as_deref());
+        if let Some(style) = indent {
+            let kind = if style.expand_tab { "spaces" } else { "tabs" };
+            debug!("Indenting {} with {} {} wide", buffer.display_name(), kind, style.tab_width);
+        }
+        let read_only = buffer.invalid_utf8 || buffer.is_write_protected();
+        let status_message = buffer
+            .recovery
+            .as_ref()
+            .map(|_| "Recovery file found — restore? (y/n)".to_string());
         Self {
             buffer,
+            buffers: vec![BufferSlot::default()],
+            current: 0,
             cx: 0,
             cy: 0,
             row_offset: 0,
             mode: Mode::Normal,
-            status_message: None,
+            status_message,
+            start_screen: true,
+            command_line: String::new(),
+            command_range: None,
+            completion: None,
+            insert_oneshot: false,
+            save_as_prompt: false,
+            pending_g: false,
+            pending_z: false,
+            vx: 0,
+            vy: 0,
+            pending_count: None,
+            pending_operator: None,
+            pending_object: false,
+            register: Vec::new(),
+            register_linewise: false,
+            awaiting_replace: false,
+            awaiting_find: None,
+            last_find: None,
+            replaced: Vec::new(),
+            awaiting_register: false,
+            macros: HashMap::new(),
+            macro_register: None,
+            pending_macro: None,
+            last_macro: None,
+            playing_macros: Vec::new(),
+            register_name: None,
+            use_system_clipboard: false,
+            clipboard: None,
+            whichwrap: false,
+            show_line_numbers: true,
+            statusline: DEFAULT_STATUSLINE.to_string(),
+            mouse: true,
+            allow_shell: false,
+            auto_indent: true,
+            auto_pairs: false,
+            insert_emacs_keys: false,
+            hlsearch: true,
+            last_search: None,
+            search_highlight: false,
+            keymap: Keymap::default(),
+            help: None,
+            syntax_highlighting: true,
+            tab_width: indent.map_or(DEFAULT_TAB_WIDTH, |style| style.tab_width),
+            expand_tab: indent.is_some_and(|style| style.expand_tab),
+            tab_width_set: false,
+            expand_tab_set: false,
+            shift_width: 0,
+            trim_trailing_whitespace: false,
+            wrap: false,
+            list: false,
+            read_only,
+            swap_file: false,
+            swaps: Vec::new(),
+            swap_prompt: None,
+            last_swap_write: None,
+            quit: false,
+            quit_confirm: false,
+            history: UndoHistory::default(),
+            last_change: None,
+            recording: None,
+            replaying: false,
+            format_commands: HashMap::new(),
+            format_result: Rc::default(),
+            last_frame: None,
+            render_cache: RenderCache::default(),
+            force_redraw: false,
+            scroll_cursor: None,
+            positions: None,
+            status_timeout: Some(DEFAULT_STATUS_TIMEOUT),
+            status_shown: None,
+            jumps: Vec::new(),
+            jump_index: 0,
+            autosave_interval: None,
+            last_autosave: None,
+            unnamed_autosave: None,
         }
     }
-    pub fn handle_event(&self, ev: Event) -> Option<Actions> {
-        match self.mode {
-            Mode::Normal => handle_normal_event(ev),
-            Mode::Insert => handle_insert_event(ev),
+
+    /// The start screen is only shown for the untouched unnamed buffer and
+    /// goes away as soon as anything is typed into it.
+    pub fn shows_start_screen(&self) -> bool {
+        self.start_screen
+            && self.buffer.file.is_none()
+            && !self.buffer.scratch
+            && !self.buffer.modified
+            && self.buffer.lines.len() == 1
+            && self.buffer.lines[0].is_empty()
+    }
+
+    pub fn start_screen_lines() -> Vec<String> {
+        let mut lines = vec![
+            format!("vix - version {}", env!("CARGO_PKG_VERSION")),
+            String::new(),
+        ];
+        lines.extend(START_SCREEN.iter().map(|s| s.to_string()));
+        lines
+    }
+
+    /// Handles an event from the terminal, adding it to the macro being
+    /// recorded. Returns whether it did anything.
+    pub fn process_event(&mut self, ev: Event) -> bool {
+        let recording = self.macro_register;
+        let applied = self.dispatch_event(ev.clone());
+        // the `Q` that stops the recording isn't part of it
+        if let Some(name) = recording
+            && self.macro_register == recording
+        {
+            self.macros.entry(name).or_default().push(ev);
         }
+        applied
     }
-    pub fn apply_action(&mut self, action: Actions) {
-        debug!("Applying action: {:?}", action);
-        match action {
-            Actions::MoveLeft => {
-                if self.cx > 0 { 
-                    self.cx -= 1;
-                    debug!("Moved cursor left to column {}", self.cx);
-                }
+
+    fn dispatch_event(&mut self, ev: Event) -> bool {
+        match self.handle_event(ev) {
+            Some(action) => {
+                self.apply_action(action);
+                true
             }
-            Actions::MoveRight => {
-                if let Ok(line) = self.buffer.get_line(self.cy as usize) {
-                    let line_len = line.len() as u16;
-                    if self.cx < line_len { 
-                        self.cx += 1;
-                        debug!("Moved cursor right to column {}", self.cx);
-                    }
-                }
+            None => false,
+        }
+    }
+
+    /// Feeds the keys recorded into register `name` back through the editor.
+    /// A macro that would play itself, directly or through another, doesn't.
+    fn play_macro(&mut self, name: char) {
+        if self.playing_macros.contains(&name) {
+            warn!("Macro {} is already playing", name);
+            return;
+        }
+        let Some(events) = self.macros.get(&name).cloned() else {
+            self.status_message = Some(format!("Nothing recorded in register {}", name));
+            return;
+        };
+        self.last_macro = Some(name);
+        self.playing_macros.push(name);
+        for ev in events {
+            if self.quit {
+                break;
             }
-            Actions::MoveUp => {
-                if self.cy > 0 {
-                    self.cy -= 1;
-                    if let Ok(line) = self.buffer.get_line(self.cy as usize) {
-                        let line_len = line.len() as u16;
-                        if self.cx > line_len {
-                            self.cx = line_len;
-                        }
+            self.dispatch_event(ev);
+        }
+        self.playing_macros.pop();
+    }
+
+    pub fn handle_event(&self, ev: Event) -> Option<Actions> {
+        if let Some(prompt) = &self.swap_prompt {
+            return handle_swap_prompt(ev, prompt.owner);
+        }
+        if self.buffer.recovery.is_some() {
+            return handle_recovery_prompt(ev);
+        }
+        if self.help.is_some() {
+            return handle_help_event(ev);
+        }
+        // a bracketed paste goes in verbatim, without auto-indent or pairs;
+        // terminals may send its line breaks as CR
+        if let Event::Paste(text) = ev {
+            let text = text.replace("\r\n", "\n").replace('\r', "\n");
+            return (self.mode == Mode::Insert).then_some(Actions::InsertText(text));
+        }
+        if let Event::Mouse(mouse) = ev {
+            return if self.mouse && self.mode != Mode::Command { handle_mouse_event(mouse) } else { None };
+        }
+        match self.mode {
+            Mode::Normal | Mode::Visual if self.pending_g => match ev {
+                Event::Key(key) => match key.code {
+                    KeyCode::Char('g') if key.modifiers.contains(crossterm::event::KeyModifiers::CONTROL) => {
+                        Some(Actions::ShowStats)
                     }
+                    KeyCode::Char('g') => Some(Actions::GotoFirstLine),
+                    KeyCode::Char('j') => Some(Actions::MoveDisplayLineDown),
+                    KeyCode::Char('k') => Some(Actions::MoveDisplayLineUp),
+                    _ => Some(Actions::CancelPending),
+                },
+                _ => Some(Actions::CancelPending),
+            },
+            Mode::Normal | Mode::Visual if self.pending_z => match ev {
+                Event::Key(key) => match key.code {
+                    KeyCode::Char('t') => Some(Actions::ScrollCursor(ScrollPosition::Top)),
+                    KeyCode::Char('z') => Some(Actions::ScrollCursor(ScrollPosition::Middle)),
+                    KeyCode::Char('b') => Some(Actions::ScrollCursor(ScrollPosition::Bottom)),
+                    _ => Some(Actions::CancelPending),
+                },
+                _ => Some(Actions::CancelPending),
+            },
+            Mode::Normal | Mode::Visual if let Some((forward, till)) = self.awaiting_find => match ev {
+                Event::Key(key) if let KeyCode::Char(target) = key.code => {
+                    let find = CharFind { target, forward, till };
+                    Some(match self.pending_operator {
+                        Some('c') => Actions::ChangeMotion(Motion::Find(find)),
+                        Some(_) => Actions::DeleteMotion(Motion::Find(find)),
+                        None => Actions::FindChar(find),
+                    })
+                }
+                _ => Some(Actions::CancelPending),
+            },
+            Mode::Normal if self.awaiting_replace => match ev {
+                Event::Key(key) if let KeyCode::Char(c) = key.code => Some(Actions::ReplaceChar(c)),
+                _ => Some(Actions::CancelPending),
+            },
+            Mode::Normal if self.awaiting_register => match ev {
+                Event::Key(key) if let KeyCode::Char(c) = key.code => Some(Actions::SelectRegister(c)),
+                _ => Some(Actions::CancelPending),
+            },
+            Mode::Normal if let Some(command) = self.pending_macro => match ev {
+                Event::Key(key) => match key.code {
+                    KeyCode::Char('@') if command == '@' => {
+                        Some(self.last_macro.map_or(Actions::CancelPending, Actions::PlayMacro))
+                    }
+                    KeyCode::Char(c) if c.is_ascii_alphanumeric() => {
+                        Some(if command == 'Q' { Actions::RecordMacro(c) } else { Actions::PlayMacro(c) })
+                    }
+                    _ => Some(Actions::CancelPending),
+                },
+                _ => Some(Actions::CancelPending),
+            },
+            Mode::Normal if let Some(operator) = self.pending_operator => {
+                handle_operator_event(operator, self.pending_object, ev)
+            }
+            Mode::Normal if let Event::Key(key) = ev
+                && let KeyCode::Char(c @ '0'..='9') = key.code
+                && (c != '0' || self.pending_count.is_some()) =>
+            {
+                c.to_digit(10).map(|digit| Actions::CountDigit(digit as usize))
+            }
+            // an unmapped key still ends the one-shot command
+            Mode::Normal if self.insert_oneshot => {
+                handle_normal_event(&self.keymap, ev).or(Some(Actions::EnterMode(Mode::Insert)))
+            }
+            Mode::Normal => handle_normal_event(&self.keymap, ev),
+            Mode::Insert => handle_insert_event(ev, self.insert_emacs_keys),
+            Mode::Replace => handle_replace_event(ev),
+            Mode::Command if self.save_as_prompt => match ev {
+                Event::Key(key) if key.code == KeyCode::Enter => {
+                    Some(Actions::SaveAs(self.command_line.trim().to_string()))
+                }
+                _ => handle_command_event(ev),
+            },
+            Mode::Command => handle_command_event(ev),
+            Mode::Visual => handle_visual_event(&self.keymap, ev),
+        }
+    }
+    pub fn apply_action(&mut self, action: Actions) {
+        debug!("Applying action: {:?}", action);
+        if self.read_only && action.needs_write_access() {
+            info!("Refusing {:?} in read-only mode", action);
+            self.status_message = Some("Read-only (use :w! to write anyway)".to_string());
+            self.pending_count = None;
+            self.pending_operator = None;
+            self.pending_object = false;
+            return;
+        }
+        let resume_insert = self.insert_oneshot;
+        self.pending_g = false;
+        self.pending_z = false;
+        self.pending_operator = None;
+        self.pending_object = false;
+        self.awaiting_register = false;
+        self.pending_macro = None;
+        self.awaiting_replace = false;
+        self.awaiting_find = None;
+        let count = self.pending_count.take();
+        let quit_confirmed = std::mem::take(&mut self.quit_confirm);
+        if action.is_edit() {
+            self.history.begin((self.cy, self.cx));
+        }
+        if let Actions::CountDigit(digit) = action {
+            // still waiting for the command, so a Ctrl-o one-shot stays pending
+            self.pending_count = Some(count.unwrap_or(0).saturating_mul(10).saturating_add(digit).min(MAX_COUNT));
+            return;
+        }
+        if !self.replaying {
+            self.record_change(&action, count);
+        }
+        let repeat = if action.takes_count() { count.unwrap_or(1) } else { 1 };
+        let before = (self.cy, self.cx);
+        for _ in 0..repeat {
+            self.perform(action.clone(), count, quit_confirmed);
+            self.clamp_cursor();
+        }
+        if action.is_jump() && (self.cy, self.cx) != before {
+            self.push_jump(before);
+        }
+        // a count or register given before a multi-key command carries over to its next key
+        if matches!(
+            action,
+            Actions::PendingG
+                | Actions::AwaitFind { .. }
+                | Actions::PendingOperator(_)
+                | Actions::AwaitTextObject(_)
+                | Actions::AwaitOperatorFind { .. }
+                | Actions::AwaitRegister
+                | Actions::SelectRegister(_)
+                | Actions::PendingMacro(_)
+        ) {
+            self.pending_count = count;
+        } else {
+            self.register_name = None;
+        }
+        // a `:` command or selection started with Ctrl-o resumes Insert once it finishes
+        if resume_insert && !matches!(self.mode, Mode::Command | Mode::Visual) {
+            self.insert_oneshot = false;
+            if self.mode == Mode::Normal {
+                self.mode = Mode::Insert;
+            }
+        }
+        self.clamp_cursor();
+        // everything typed in one Insert or Replace session is undone and
+        // repeated as one change
+        if !matches!(self.mode, Mode::Insert | Mode::Replace) {
+            self.history.commit(self.buffer.take_edits(), (self.cy, self.cx), Instant::now());
+            if !self.buffer.modified {
+                self.history.mark_saved();
+            }
+            if !self.replaying
+                && let Some(change) = self.recording.take()
+                && change.1.iter().any(Actions::is_repeatable)
+            {
+                self.last_change = Some(change);
+            }
+        }
+    }
+
+    /// Adds an action to the change being recorded for `.`, starting a new
+    /// one when a change begins in Normal mode.
+    fn record_change(&mut self, action: &Actions, count: Option<usize>) {
+        match self.mode {
+            Mode::Normal if action.starts_change() => self.recording = Some((count, vec![action.clone()])),
+            Mode::Insert | Mode::Replace => {
+                if let Some((_, actions)) = &mut self.recording {
+                    actions.push(action.clone());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Carries out a single action; `apply_action` handles what surrounds it.
+    /// `count` is only read by actions that use it other than as a repeat.
+    fn perform(&mut self, action: Actions, count: Option<usize>, quit_confirmed: bool) {
+        match action {
+            Actions::MoveLeft => {
+                if self.cx > 0 { 
+                    self.cx -= 1;
+                    debug!("Moved cursor left to column {}", self.cx);
+                } else if self.whichwrap && self.cy > 0 {
+                    self.cy -= 1;
+                    self.cx = usize::MAX;
+                    debug!("Wrapped cursor to end of line {}", self.cy);
                 }
             }
+            Actions::MoveRight => {
+                let (_, max_col) = self.buffer.clamp_position(self.cy, usize::MAX, self.cursor_past_end());
+                if self.cx < max_col { 
+                    self.cx += 1;
+                    debug!("Moved cursor right to column {}", self.cx);
+                } else if self.whichwrap && self.cy + 1 < self.buffer.len() {
+                    self.cy += 1;
+                    self.cx = 0;
+                    debug!("Wrapped cursor to start of line {}", self.cy);
+                }
+            }
+            Actions::MoveUp => {
+                self.cy = self.cy.saturating_sub(1);
+            }
             Actions::MoveDown => {
-                if (self.cy as usize) + 1 < self.buffer.len() {
+                if self.cy + 1 < self.buffer.len() {
                     self.cy += 1;
-                    if let Ok(line) = self.buffer.get_line(self.cy as usize) {
-                        let line_len = line.len() as u16;
-                        if self.cx > line_len {
-                            self.cx = line_len;
-                        }
-                    }
                 }
             }
+            Actions::MoveDisplayLineDown | Actions::MoveDisplayLineUp => {
+                let down = action == Actions::MoveDisplayLineDown;
+                match self.wrap_width() {
+                    Some(width) => self.move_display_line(width, down),
+                    None => self.perform(if down { Actions::MoveDown } else { Actions::MoveUp }, None, quit_confirmed),
+                }
+            }
+            Actions::MoveWordForward => {
+                (self.cy, self.cx) = self.buffer.next_word_start(self.cy, self.cx);
+            }
+            Actions::MoveWordBackward => {
+                (self.cy, self.cx) = self.buffer.prev_word_start(self.cy, self.cx);
+            }
+            Actions::MoveWordEnd => {
+                (self.cy, self.cx) = self.buffer.word_end(self.cy, self.cx);
+            }
+            Actions::MoveLineStart => self.cx = 0,
+            Actions::MoveLineEnd => {
+                self.cx = self.buffer.line_length(self.cy).unwrap_or(0);
+            }
+            Actions::MoveFirstNonBlank => {
+                self.cx = self.buffer.first_non_blank(self.cy);
+            }
+            Actions::PendingG => self.pending_g = true,
+            Actions::PendingZ => self.pending_z = true,
+            Actions::ScrollCursor(position) => self.scroll_cursor = Some(position),
+            Actions::JumpBack => self.jump_back(),
+            Actions::JumpForward => {
+                if self.jump_index + 1 < self.jumps.len() {
+                    self.jump_index += 1;
+                    self.go_to_jump();
+                }
+            }
+            Actions::MatchBracket => match self.buffer.find_matching_bracket(self.cy, self.cx) {
+                Some((line, col)) => (self.cy, self.cx) = (line, col),
+                None => info!("No matching bracket from {}:{}", self.cy, self.cx),
+            },
+            // digits only build up the count, in apply_action
+            Actions::CountDigit(_) => {}
+            Actions::SetSyntax(on) => self.syntax_highlighting = on,
+            Actions::Set(setting) => match setting {
+                Setting::Number(on) => self.show_line_numbers = on,
+                Setting::Wrap(on) => self.wrap = on,
+                Setting::List(on) => self.list = on,
+                Setting::AutoIndent(on) => self.auto_indent = on,
+                Setting::AutoPairs(on) => self.auto_pairs = on,
+                Setting::HlSearch(on) => self.hlsearch = on,
+                Setting::ReadOnly(on) => self.read_only = on,
+                Setting::TabWidth(width) => (self.tab_width, self.tab_width_set) = (width, true),
+                Setting::ExpandTab(on) => (self.expand_tab, self.expand_tab_set) = (on, true),
+                Setting::ShiftWidth(width) => self.shift_width = width,
+            },
+            Actions::AwaitRegister => self.awaiting_register = true,
+            Actions::AwaitReplace => self.awaiting_replace = true,
+            Actions::AwaitFind { forward, till } => self.awaiting_find = Some((forward, till)),
+            Actions::AwaitOperatorFind { operator, forward, till } => {
+                self.pending_operator = Some(operator);
+                self.awaiting_find = Some((forward, till));
+            }
+            // a count finds the nth match rather than repeating the search,
+            // so that `2t,` gets past the first comma
+            Actions::FindChar(find) => {
+                self.last_find = Some(find);
+                self.find_char(find, self.cx, count.unwrap_or(1));
+            }
+            Actions::RepeatFind(reverse) => match self.last_find {
+                Some(find) => {
+                    let find = CharFind { forward: find.forward != reverse, ..find };
+                    // a repeated `t` would find the char it already stopped
+                    // beside, so look from one step further on
+                    let from = match (find.till, find.forward) {
+                        (true, true) => self.cx + 1,
+                        (true, false) => self.cx.saturating_sub(1),
+                        (false, _) => self.cx,
+                    };
+                    self.find_char(find, from, count.unwrap_or(1));
+                }
+                None => self.status_message = Some("No previous find".to_string()),
+            },
+            Actions::ReplaceChar(c) => {
+                // nothing under the cursor on an empty line
+                let _ = self.buffer.replace_char(self.cy, self.cx, c);
+            }
+            Actions::SelectRegister(name) => self.register_name = Some(name),
+            Actions::PendingMacro('Q') if let Some(name) = self.macro_register.take() => {
+                info!("Recorded {} keys into macro {}", self.macros.get(&name).map_or(0, Vec::len), name);
+            }
+            Actions::PendingMacro(command) => self.pending_macro = Some(command),
+            Actions::RecordMacro(name) => {
+                self.macros.insert(name, Vec::new());
+                self.macro_register = Some(name);
+            }
+            Actions::PlayMacro(name) => self.play_macro(name),
+            Actions::CancelPending => {}
+            Actions::GotoFirstLine => self.cy = 0,
+            Actions::GotoLastLine => self.cy = self.buffer.len().saturating_sub(1),
+            Actions::GotoLine(line) => {
+                if line > self.buffer.len() {
+                    self.status_message =
+                        Some(format!("Only {} lines, jumped to the last", self.buffer.len()));
+                }
+                self.goto_line(line);
+            }
             Actions::EnterMode(m) => {
                 info!("Switching mode from {:?} to {:?}", self.mode, m);
+                self.command_line.clear();
+                self.command_range = None;
+                if m == Mode::Command && self.mode == Mode::Visual {
+                    let ((start, _), (end, _)) = self.selection();
+                    self.command_range = Some(start..end + 1);
+                    self.command_line.push_str(VISUAL_RANGE);
+                }
+                self.completion = None;
+                self.replaced.clear();
+                self.save_as_prompt = false;
+                if m == Mode::Visual {
+                    (self.vx, self.vy) = (self.cx, self.cy);
+                }
                 self.mode = m;
             },
+            Actions::PrintChar(c) if self.mode == Mode::Replace => {
+                let overwritten = self.buffer.replace_char(self.cy, self.cx, c).ok();
+                if overwritten.is_some() || self.buffer.insert_char(self.cy, self.cx, c).is_ok() {
+                    self.replaced.push(overwritten);
+                    self.cx += 1;
+                }
+            }
+            Actions::Backspace if self.mode == Mode::Replace => {
+                // only what was typed in this session can be taken back
+                if self.cx > 0 && let Some(original) = self.replaced.pop() {
+                    self.cx -= 1;
+                    let restored = match original {
+                        Some(c) => self.buffer.replace_char(self.cy, self.cx, c).map(|_| ()),
+                        None => self.buffer.remove_char(self.cy, self.cx).map(|_| ()),
+                    };
+                    if let Err(e) = restored {
+                        warn!("Could not undo replaced character: {}", e);
+                    }
+                } else {
+                    self.cx = self.cx.saturating_sub(1);
+                }
+            }
+            // typing the closing char that is already there steps over it
+            Actions::PrintChar(c) if self.auto_pairs && self.char_at(self.cy, self.cx) == Some(c)
+                && matches!(c, ')' | ']' | '}' | '"' | '\'') =>
+            {
+                self.cx += 1;
+            }
+            Actions::PrintChar(c) if let Some(close) = self.auto_pair(c) => {
+                match self.buffer.insert_text(self.cy, self.cx, &[format!("{}{}", c, close)]) {
+                    Ok(_) => self.cx += 1,
+                    Err(e) => warn!("Error inserting pair: {}", e),
+                }
+            }
             Actions::PrintChar(c) => {
-                if let Ok(_) = self.buffer.insert_char(self.cy as usize, self.cx as usize, c) {
+                if self.buffer.insert_char(self.cy, self.cx, c).is_ok() {
                     self.cx += 1;
                 }
             }
             Actions::Backspace => {
-                if self.cx > 0 {
-                    if let Ok(_) = self.buffer.remove_char(self.cy as usize, (self.cx - 1) as usize) {
-                        self.cx -= 1;
+                if self.in_empty_pair() {
+                    match self.buffer.delete_range((self.cy, self.cx - 1), (self.cy, self.cx)) {
+                        Ok(_) => self.cx -= 1,
+                        Err(e) => warn!("Error deleting pair: {}", e),
                     }
-                } else if self.cy > 0 {
-                    if let Ok(prev_line_len) = self.buffer.join_with_previous_line(self.cy as usize) {
-                        self.cy -= 1;
-                        self.cx = prev_line_len as u16;
+                } else if self.cx > 0 {
+                    if self.buffer.remove_char(self.cy, self.cx - 1).is_ok() {
+                        self.cx -= 1;
                     }
+                } else if self.cy > 0
+                    && let Ok(prev_line_len) = self.buffer.join_with_previous_line(self.cy)
+                {
+                    self.cy -= 1;
+                    self.cx = prev_line_len;
+                }
+            }
+            Actions::InsertText(text) => {
+                match self.buffer.insert_str(self.cy, self.cx, &text) {
+                    Ok(end) => (self.cy, self.cx) = end,
+                    Err(e) => warn!("Error inserting text: {}", e),
+                }
+            }
+            Actions::InsertTab => {
+                let text = self.tab_text();
+                match self.buffer.insert_text(self.cy, self.cx, &[text]) {
+                    Ok((_, col)) => self.cx = col,
+                    Err(e) => warn!("Error inserting tab: {}", e),
+                }
+            }
+            // at the start of a line there is nothing before the cursor to
+            // delete; unlike Backspace it doesn't join onto the line above
+            Actions::DeleteWordBack if self.cx > 0 => {
+                let start = self.buffer.word_delete_start(self.cy, self.cx);
+                match self.buffer.delete_range((self.cy, start), (self.cy, self.cx - 1)) {
+                    Ok(_) => self.cx = start,
+                    Err(e) => warn!("Error deleting word: {}", e),
+                }
+            }
+            Actions::DeleteWordBack => {}
+            Actions::DeleteToLineEnd => {
+                if let Err(e) = self.buffer.delete_to_line_end(self.cy, self.cx) {
+                    warn!("Error deleting to line end: {}", e);
+                }
+            }
+            // the deleted text is dropped, leaving the register as it was
+            Actions::DeleteToLineStart => match self.buffer.delete_to_line_start(self.cy, self.cx) {
+                Ok(_) => self.cx = 0,
+                Err(e) => warn!("Error deleting to line start: {}", e),
+            },
+            Actions::ToggleCase => {
+                let Some(c) = self.buffer.line_chars(self.cy).get(self.cx).copied() else { return };
+                let toggled: Vec<char> = if c.is_lowercase() {
+                    c.to_uppercase().collect()
+                } else {
+                    c.to_lowercase().collect()
+                };
+                // a char whose other case is several chars, like ß, stays as it is
+                if let [toggled] = toggled[..]
+                    && toggled != c
+                    && let Err(e) = self.buffer.replace_char(self.cy, self.cx, toggled)
+                {
+                    warn!("Error toggling case: {}", e);
+                }
+                self.cx += 1;
+            }
+            Actions::JoinLine => {
+                // nothing below the last line to join
+                if let Ok(col) = self.buffer.join_next_line(self.cy) {
+                    self.cx = col;
                 }
             }
             Actions::NewLine => {
-                if let Ok(line) = self.buffer.get_line_mut(self.cy as usize) {
-                    let tail = line.split_off(self.cx as usize);
-                    self.buffer.lines.insert((self.cy + 1) as usize, tail);
+                if self.buffer.split_line(self.cy, self.cx).is_ok() {
                     self.cy += 1;
-                    self.cx = 0;
+                    self.cx = self.indent_like(self.cy - 1, self.cy);
                 }
             }
-            Actions::Save => {
+            action @ (Actions::Save | Actions::ForceSave) => {
                 info!("Attempting to save file");
-                match self.buffer.save() {
-                    Ok(()) => {
-                        info!("File saved successfully");
-                        self.status_message = Some("Saved.".to_string());
+                let trimmed = self.trim_before_save();
+                let existed = self.buffer.file.as_deref().is_some_and(|file| Path::new(file).exists());
+                self.set_format_hook();
+                let result = if action == Actions::ForceSave { self.buffer.force_save() } else { self.buffer.save() };
+                self.buffer.post_save_hook = None;
+                match result {
+                    Ok(bytes) => {
+                        info!("File saved successfully ({} bytes)", bytes);
+                        let path = self.buffer.display_name();
+                        self.status_message = Some(format!("{}{}", self.written_message(&path, existed, bytes), trimmed));
+                        self.history.mark_saved();
+                        self.format_after_save();
+                        self.remember_positions();
+                    }
+                    Err(BufferError::ChangedOnDisk) => {
+                        self.status_message = Some("File changed on disk — overwrite? (:w! to force)".to_string());
+                    }
+                    Err(BufferError::InvalidUtf8) => {
+                        self.status_message =
+                            Some("File is not valid UTF-8 — write replaced chars? (:w! to force)".to_string());
                     }
                     Err(e) => {
                         warn!("Error saving file: {}", e);
@@ -185,119 +1940,4563 @@ impl Editor {
                 }
             }
             Actions::SaveAs(path) => {
-                info!("Attempting to save file as: {}", path);
-                match self.buffer.save_as(path) {
-                    Ok(()) => {
-                        info!("File saved successfully");
-                        self.status_message = Some("Saved (as).".to_string());
+                self.command_line.clear();
+                self.save_as_prompt = false;
+                if self.mode == Mode::Command {
+                    self.mode = Mode::Normal;
+                }
+                if path.is_empty() {
+                    self.status_message = Some("Save cancelled".to_string());
+                } else {
+                    self.save_as(path);
+                }
+            }
+            Actions::EditFile(path, force) => self.edit_file(path, force),
+            Actions::WriteCopy(path) => self.write_copy(&buffer::expand_tilde(&path)),
+            Actions::ReadFile(path) => match path.or_else(|| self.buffer.file.clone()) {
+                None => self.status_message = Some("No file name".to_string()),
+                Some(path) => match std::fs::read(buffer::expand_tilde(&path)) {
+                    Ok(bytes) => {
+                        let lines = buffer::text_lines(&bytes);
+                        self.status_message = Some(format!("\"{}\" {}L, {}B", path, lines.len(), bytes.len()));
+                        self.read_lines_in(&lines);
                     }
                     Err(e) => {
-                        warn!("Error saving file: {}", e);
-                        self.status_message = Some(format!("Error saving file: {}", e));
+                        warn!("Could not read {}: {}", path, e);
+                        self.status_message = Some(format!("Can't open file {}: {}", path, e));
                     }
-                }
+                },
+            },
+            Actions::ReadCommand(command) => self.read_command(&command),
+            Actions::PromptSaveAs => {
+                self.command_line.clear();
+                self.mode = Mode::Command;
+                self.save_as_prompt = true;
             }
+            // a count takes that many lines, as far as the last one, into the
+            // register together
             Actions::DeleteLine => {
-                match self.buffer.delete_line(self.cy as usize) {
-                    Ok(()) => {
-                        // adjust cursor if we were on the last line
-                        if (self.cy as usize) >= self.buffer.len() {
-                            self.cy = (self.buffer.len().saturating_sub(1)) as u16;
-                        }
-                        // ensure cx is not past end of line
-                        if let Ok(len) = self.buffer.line_length(self.cy as usize) {
-                            if self.cx as usize > len {
-                                self.cx = len as u16;
-                            }
-                        }
-                        self.status_message = Some("Line deleted".to_string());
+                let end = self.cy.saturating_add(count.unwrap_or(1)).min(self.buffer.len()) - 1;
+                match self.buffer.delete_lines(self.cy, end) {
+                    Ok(lines) => {
+                        self.status_message = Some(match lines.len() {
+                            1 => "Line deleted".to_string(),
+                            n => format!("{} lines deleted", n),
+                        });
+                        self.store_register(lines, true);
                     }
                     Err(e) => {
                         self.status_message = Some(format!("Error deleting line: {}", e));
                     }
                 }
             }
+            Actions::DeleteMotion(motion) => {
+                self.delete_motion(motion, count.unwrap_or(1));
+            }
+            Actions::DeleteChar => {
+                // nothing to delete on an empty line; not worth an error message
+                if self.buffer.line_length(self.cy).unwrap_or(0) > 0
+                    && let Err(e) = self.buffer.remove_char(self.cy, self.cx)
+                {
+                    warn!("Error deleting character: {}", e);
+                }
+            }
+            Actions::CommandChar(c) => {
+                self.command_line.push(c);
+                self.completion = None;
+            }
+            Actions::CommandBackspace => {
+                self.completion = None;
+                if self.command_line.pop().is_none() {
+                    self.mode = Mode::Normal;
+                    self.save_as_prompt = false;
+                }
+            }
+            Actions::CompleteCommand => self.complete_command(),
+            Actions::ExecuteCommand => {
+                self.completion = None;
+                let command = std::mem::take(&mut self.command_line);
+                let range = self.command_range.take();
+                self.mode = Mode::Normal;
+                // the selection's lines count only while `'<,'>` is left in front
+                match command.strip_prefix(VISUAL_RANGE) {
+                    Some(rest) if range.is_some() => {
+                        self.command_range = range;
+                        self.execute_command(rest);
+                        self.command_range = None;
+                    }
+                    _ => {
+                        self.execute_command(&command);
+                    }
+                }
+            }
+            Actions::StripWhitespace => {
+                let count = match self.command_range.clone() {
+                    Some(lines) => {
+                        lines.filter(|&index| self.buffer.trim_line_trailing(index).unwrap_or(false)).count()
+                    }
+                    None => self.buffer.strip_trailing_whitespace(),
+                };
+                self.status_message = Some(format!("Trimmed trailing whitespace on {} lines", count));
+            }
+            Actions::SearchWordForward | Actions::SearchWordBackward => {
+                let forward = matches!(action, Actions::SearchWordForward);
+                match self.buffer.word_at(self.cy, self.cx) {
+                    Some(word) => {
+                        self.last_search = Some((word.clone(), forward));
+                        self.search_word(&word, forward);
+                    }
+                    None => self.status_message = Some("No string under cursor".to_string()),
+                }
+            }
+            Actions::SearchNext(reverse) => match self.last_search.clone() {
+                Some((word, forward)) => self.search_word(&word, forward != reverse),
+                None => self.status_message = Some("No previous search".to_string()),
+            },
+            // the word is kept, so `n` still finds it and highlights again
+            Actions::ClearHighlight => self.search_highlight = false,
+            Actions::DiffSummary => {
+                self.status_message = Some(self.diff_summary());
+            }
+            Actions::FileInfo => self.status_message = Some(self.file_info()),
+            Actions::ShowStats => {
+                let stats = self.buffer.stats(self.cy, self.cx);
+                self.status_message = Some(format!(
+                    "Line {} of {}; Col {}; Char {} of {}; {} words",
+                    stats.line, stats.lines, stats.col, stats.char, stats.chars, stats.words
+                ));
+            }
+            Actions::CheckTime => {
+                self.status_message = Some(match self.buffer.disk_change() {
+                    Some(DiskChange::Modified) => "File changed on disk — reload with :e!".to_string(),
+                    Some(DiskChange::Deleted) => "File deleted on disk".to_string(),
+                    None => "File unchanged on disk".to_string(),
+                });
+            }
+            Actions::Quit => {
+                if self.buffer.has_unsaved_changes() {
+                    info!("Refusing to quit with unsaved changes");
+                    self.status_message =
+                        Some("No write since last change (add ! to override)".to_string());
+                } else if let Some(name) = self.unsaved_hidden_buffer() {
+                    info!("Refusing to quit with unsaved changes in {}", name);
+                    self.status_message =
+                        Some(format!("No write since last change for buffer \"{}\"", name));
+                } else {
+                    info!("Quit requested");
+                    self.quit = true;
+                }
+            }
+            Actions::QuitKey => {
+                let unsaved = self.buffer.has_unsaved_changes() || self.unsaved_hidden_buffer().is_some();
+                if unsaved && !quit_confirmed {
+                    self.status_message =
+                        Some("Unsaved changes — press q again to quit or :w to save".to_string());
+                    self.quit_confirm = true;
+                } else {
+                    info!("Quit requested");
+                    self.quit = true;
+                }
+            }
+            Actions::ForceQuit => {
+                info!("Quitting without saving");
+                self.quit = true;
+            }
+            // the hidden buffers still get the same check as `:q`
+            Actions::WriteQuit => {
+                self.apply_action(Actions::Save);
+                if !self.buffer.has_unsaved_changes() {
+                    self.apply_action(Actions::Quit);
+                }
+            }
+            Actions::Undo => match self.history.undo(&mut self.buffer.lines) {
+                Some((cy, cx)) => {
+                    (self.cy, self.cx) = (cy, cx);
+                    self.buffer.modified = !self.history.is_saved();
+                    self.status_message = Some("Undid 1 change".to_string());
+                }
+                None => self.status_message = Some("Already at oldest change".to_string()),
+            },
+            Actions::Redo => match self.history.redo(&mut self.buffer.lines) {
+                Some((cy, cx)) => {
+                    (self.cy, self.cx) = (cy, cx);
+                    self.buffer.modified = !self.history.is_saved();
+                    self.status_message = Some("Redid 1 change".to_string());
+                }
+                None => self.status_message = Some("Already at newest change".to_string()),
+            },
+            Actions::Earlier(span) => match self.history.earlier(span, &mut self.buffer.lines) {
+                Some((count, (cy, cx))) => {
+                    (self.cy, self.cx) = (cy, cx);
+                    self.buffer.modified = !self.history.is_saved();
+                    self.status_message = Some(format!("Undid {} change{}", count, if count == 1 { "" } else { "s" }));
+                }
+                None => self.status_message = Some("Already at oldest change".to_string()),
+            },
+            Actions::Later(span) => match self.history.later(span, &mut self.buffer.lines) {
+                Some((count, (cy, cx))) => {
+                    (self.cy, self.cx) = (cy, cx);
+                    self.buffer.modified = !self.history.is_saved();
+                    self.status_message = Some(format!("Redid {} change{}", count, if count == 1 { "" } else { "s" }));
+                }
+                None => self.status_message = Some("Already at newest change".to_string()),
+            },
+            Actions::DeleteSelection => {
+                let (start, end) = self.selection();
+                match self.buffer.delete_range(start, end) {
+                    Ok(text) => {
+                        self.store_register(text, false);
+                        (self.cy, self.cx) = start;
+                    }
+                    Err(e) => self.status_message = Some(format!("Error deleting selection: {}", e)),
+                }
+                self.mode = Mode::Normal;
+            }
+            Actions::YankSelection => {
+                let (start, end) = self.selection();
+                let text = self.buffer.text_range(start, end);
+                self.store_register(text, false);
+                (self.cy, self.cx) = start;
+                self.mode = Mode::Normal;
+            }
+            // a count is how many lines to shift, not how many times
+            Actions::ShiftLines(right) => match self.command_range.clone() {
+                Some(lines) => self.shift_lines(lines.start, lines.end - 1, right),
+                None => self.shift_lines(self.cy, self.cy.saturating_add(count.unwrap_or(1) - 1), right),
+            },
+            Actions::ShiftSelection(right) => {
+                let (start, end) = self.selection();
+                self.shift_lines(start.0, end.0, right);
+                self.mode = Mode::Normal;
+            }
+            Actions::PendingOperator(op) => self.pending_operator = Some(op),
+            Actions::AwaitTextObject(op) => {
+                self.pending_operator = Some(op);
+                self.pending_object = true;
+            }
+            Actions::ChangeMotion(motion) => {
+                // a failed motion leaves nothing changed to type over
+                if self.delete_motion(motion, count.unwrap_or(1)) {
+                    self.mode = Mode::Insert;
+                }
+            }
+            Actions::YankLine => {
+                let end = self.cy.saturating_add(count.unwrap_or(1)).min(self.buffer.len());
+                let lines = self.buffer.lines[self.cy.min(end)..end].to_vec();
+                self.store_register(lines, true);
+            }
+            Actions::RestoreRecovery(true) => {
+                self.status_message = Some(match self.buffer.restore_recovery() {
+                    Ok(()) => "Restored from recovery file".to_string(),
+                    Err(e) => format!("Error restoring recovery file: {}", e),
+                });
+            }
+            Actions::AnswerSwap(choice) => self.answer_swap(choice),
+            Actions::RestoreRecovery(false) => {
+                self.buffer.discard_recovery();
+                self.status_message = Some("Recovery file discarded".to_string());
+            }
+            // the cursor is clamped for Insert mode afterwards, so `a` on an
+            // empty line stays at column 0 like `i`
+            Actions::Append => {
+                self.mode = Mode::Insert;
+                self.cx += 1;
+            }
+            Actions::AppendLineEnd => {
+                self.mode = Mode::Insert;
+                self.cx = self.buffer.line_length(self.cy).unwrap_or(0);
+            }
+            Actions::InsertLineStart => {
+                self.mode = Mode::Insert;
+                self.cx = self.buffer.first_non_blank(self.cy);
+            }
+            Actions::OpenLineBelow => self.open_line(self.cy + 1),
+            Actions::OpenLineAbove => self.open_line(self.cy),
+            Actions::Paste => self.paste(true),
+            Actions::PasteBefore => self.paste(false),
+            Actions::NextBuffer => self.switch_buffer((self.current + 1) % self.buffers.len()),
+            Actions::PrevBuffer => {
+                self.switch_buffer((self.current + self.buffers.len() - 1) % self.buffers.len())
+            }
+            Actions::ListBuffers => self.status_message = Some(self.buffer_list()),
+            Actions::ShowHelp => {
+                self.help = Some(0);
+                self.status_message = Some("Help: j/k to scroll, q or Esc to close".to_string());
+            }
+            Actions::CloseHelp => {
+                self.help = None;
+                self.status_message = None;
+            }
+            Actions::ScrollHelp(down) => {
+                let last = help_lines(&self.keymap).len() - 1;
+                if let Some(offset) = &mut self.help {
+                    *offset = if down { (*offset + 1).min(last) } else { offset.saturating_sub(1) };
+                }
+            }
+            Actions::Substitute(substitution) => {
+                let lines = match self.command_range.clone() {
+                    Some(lines) => lines,
+                    None if substitution.whole_buffer => 0..self.buffer.len(),
+                    None => self.cy..self.cy + 1,
+                };
+                let (count, changed) = self.buffer.substitute(
+                    lines,
+                    substitution.pattern.as_str(),
+                    &substitution.replacement,
+                    substitution.global,
+                );
+                match changed.last() {
+                    Some(&line) => {
+                        self.status_message =
+                            Some(format!("{} substitutions on {} lines", count, changed.len()));
+                        self.cy = line;
+                        self.cx = self.buffer.first_non_blank(line);
+                    }
+                    None => self.status_message = Some(format!("Pattern not found: {}", substitution.pattern)),
+                }
+            }
+            Actions::MouseClick(column, row) => {
+                let (row, height) = (row as usize, self.text_height());
+                let width = self.last_frame.as_ref().map_or(0, Frame::width);
+                let clicked = self
+                    .layout(width, height)
+                    .into_iter()
+                    .find(|line| row < height && (line.y..line.y + line.starts.len()).contains(&row));
+                if let Some(clicked) = clicked {
+                    let line = &self.buffer.lines[clicked.index];
+                    let start = clicked.starts[row - clicked.y];
+                    let visual = clicked.cols[start] + (column as usize).saturating_sub(self.gutter_width());
+                    // a click past the end of a wrapped row lands on its last char
+                    let end = clicked.starts.get(row - clicked.y + 1).map_or(usize::MAX, |&next| next - 1);
+                    self.cy = clicked.index;
+                    self.cx = buffer::char_col(line, visual, self.tab_width).min(end);
+                }
+            }
+            Actions::ScrollBy(lines) => {
+                let last = self.buffer.len().saturating_sub(1);
+                self.row_offset = self.row_offset.saturating_add_signed(lines).min(last);
+                // keep the cursor on screen rather than scrolling back to it
+                let height = self.text_height().max(1);
+                self.cy = self.cy.clamp(self.row_offset, self.row_offset + height - 1);
+            }
+            Actions::RepeatChange => match self.last_change.clone() {
+                Some((count, actions)) => {
+                    self.replaying = true;
+                    self.pending_count = count;
+                    for action in actions {
+                        self.apply_action(action);
+                    }
+                    self.replaying = false;
+                }
+                None => self.status_message = Some("No change to repeat".to_string()),
+            },
+            Actions::InsertNormalOnce => {
+                debug!("Running one Normal-mode command from Insert mode");
+                self.mode = Mode::Normal;
+                self.insert_oneshot = true;
+            }
         }
     }
-    pub fn render(&mut self, stdout: &mut impl Write) -> Result<()> {
-        let (w, h) = terminal::size()?;
-        stdout.queue(terminal::Clear(terminal::ClearType::All))?;
-        let visible_height = h.saturating_sub(1) as usize; // leave last line for status
 
-        // adjust row_offset so cy is visible
-        if (self.cy as usize) < self.row_offset {
-            self.row_offset = self.cy as usize;
-        } else if (self.cy as usize) >= self.row_offset + visible_height {
-            self.row_offset = (self.cy as usize).saturating_sub(visible_height).saturating_add(1);
+    /// Runs one `:` command, returning whether it was recognised.
+    fn execute_command(&mut self, command: &str) -> bool {
+        info!("Executing command: {}", command);
+        match parse_command(command) {
+            Some(action) => {
+                self.apply_action(action);
+                true
+            }
+            None => {
+                warn!("Unknown command: {}", command);
+                self.status_message = Some(format!("Not an editor command: {}", command));
+                false
+            }
+        }
+    }
+
+    /// Runs `|`-separated commands without any terminal interaction, as for
+    /// `--exec`. Stops at the first `q` or unknown command.
+    pub fn run_commands(&mut self, script: &str) -> std::result::Result<(), String> {
+        for command in split_script(script) {
+            if !self.execute_command(&command) {
+                return Err(self.status_message.clone().unwrap_or_default());
+            }
+            if self.quit {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs startup commands such as those from `.vixrc`, one per entry.
+    /// Unknown ones are logged and skipped so the rest still apply.
+    pub fn run_startup_commands(&mut self, commands: &[String]) {
+        for command in commands {
+            if !self.execute_command(command) {
+                warn!("Skipping startup command: {}", command);
+            }
+        }
+    }
+
+    /// Opens another buffer behind the current one, reachable with `:bn`.
+    pub fn add_buffer(&mut self, buffer: Buffer) {
+        info!("Adding buffer {}", buffer.display_name());
+        let read_only = buffer.invalid_utf8 || buffer.is_write_protected();
+        self.buffers.push(BufferSlot { buffer, read_only, ..Default::default() });
+    }
+
+    /// Makes every open buffer read-only, as `-R` does.
+    pub fn make_all_read_only(&mut self) {
+        self.read_only = true;
+        for slot in &mut self.buffers {
+            slot.read_only = true;
         }
+    }
 
-        for (i, line) in self.buffer.lines.iter().enumerate().skip(self.row_offset) {
-            let y = (i - self.row_offset) as u16;
-            if y as u16 >= h.saturating_sub(1) { break; }
-            stdout.queue(MoveTo(0, y))?;
-            stdout.queue(Print(line))?;
+    /// Parks the current buffer with its cursor and history, and brings up
+    /// the buffer at `index` where it was left.
+    fn switch_buffer(&mut self, index: usize) {
+        if index == self.current {
+            self.status_message = Some("Only one buffer".to_string());
+            return;
         }
-        let mode_name = match self.mode {
-            Mode::Normal => "NORMAL",
-            Mode::Insert => "INSERT",
+        let previous = self.current;
+        let parked = BufferSlot {
+            buffer: std::mem::take(&mut self.buffer),
+            cx: self.cx,
+            cy: self.cy,
+            row_offset: self.row_offset,
+            history: std::mem::take(&mut self.history),
+            read_only: self.read_only,
         };
-    let filename = self.buffer.display_name();
-    let modified_marker = if self.buffer.modified { "*" } else { "" };
-        let line = (self.cy + 1).to_string();
-        let col = (self.cx + 1).to_string();
-        let percent = if self.buffer.len() <= 1 {
-            100
+        let slot = std::mem::replace(&mut self.buffers[index], parked);
+        self.buffers.swap(index, self.current);
+        self.current = index;
+        self.buffer = slot.buffer;
+        (self.cx, self.cy, self.row_offset) = (slot.cx, slot.cy, slot.row_offset);
+        self.history = slot.history;
+        self.read_only = slot.read_only;
+        self.detect_indent();
+        // jump list places belong to the buffer they were recorded in
+        self.jumps.clear();
+        self.jump_index = 0;
+        info!("Switched to buffer {}: {}", index + 1, self.buffer.display_name());
+        self.claim_swap_after(Some(previous));
+        if self.swap_prompt.is_some() {
+            return;
+        }
+        self.status_message = Some(if self.buffer.recovery.is_some() {
+            "Recovery file found — restore? (y/n)".to_string()
         } else {
-            let last = (self.buffer.len() - 1) as f64;
-            let pct = (self.cy as f64 / last) * 100.0;
-            pct.round() as u16
+            format!("\"{}\" {} lines", self.buffer.display_name(), self.buffer.len())
+        });
+    }
+
+    /// The name of a buffer other than the current one with unsaved changes.
+    fn unsaved_hidden_buffer(&self) -> Option<String> {
+        self.buffers
+            .iter()
+            .enumerate()
+            .find(|(i, slot)| *i != self.current && slot.buffer.has_unsaved_changes())
+            .map(|(_, slot)| slot.buffer.display_name())
+    }
+
+    /// Every open buffer on one line for `:ls`: its number, `%` for the
+    /// current one, its name and `+` when modified.
+    fn buffer_list(&self) -> String {
+        self.buffers
+            .iter()
+            .enumerate()
+            .map(|(i, slot)| {
+                let (buffer, marker) = if i == self.current { (&self.buffer, "%") } else { (&slot.buffer, "") };
+                let modified = if buffer.has_unsaved_changes() { " +" } else { "" };
+                format!("{} {}\"{}\"{}", i + 1, marker, buffer.display_name(), modified)
+            })
+            .collect::<Vec<_>>()
+            .join("  ")
+    }
+
+    /// Writes a recovery file for every open buffer with unsaved changes.
+    pub fn try_save_recovery(&self) {
+        self.buffer.try_save_recovery();
+        for (i, slot) in self.buffers.iter().enumerate() {
+            if i != self.current {
+                slot.buffer.try_save_recovery();
+            }
+        }
+    }
+
+    /// Records where the cursor is in every open file.
+    pub fn remember_positions(&mut self) {
+        let Some(positions) = &mut self.positions else { return };
+        if let Some(file) = &self.buffer.file {
+            positions.set(file, (self.cy, self.cx));
+        }
+        for (i, slot) in self.buffers.iter().enumerate() {
+            if i != self.current
+                && let Some(file) = &slot.buffer.file
+            {
+                positions.set(file, (slot.cy, slot.cx));
+            }
+        }
+        positions.save();
+    }
+
+    /// Puts the cursor in every open file back where it was last left, when
+    /// that is still inside the file.
+    pub fn restore_positions(&mut self) {
+        let Some(positions) = &self.positions else { return };
+        let remembered = |buffer: &Buffer| {
+            let (line, col) = positions.get(buffer.file.as_deref()?)?;
+            (col <= buffer.line_length(line).ok()?).then_some((line, col))
         };
-        let left = format!("{} > {}{} >", mode_name, filename, modified_marker);
-        // show status_message on right if present, otherwise show Ln/Col/percent
-        let right = if let Some(msg) = &self.status_message {
-            msg.clone()
-        } else {
-            format!("Ln {} Col {}  {}%", line, col, percent)
+        if let Some((cy, cx)) = remembered(&self.buffer) {
+            (self.cy, self.cx) = (cy, cx);
+            self.scroll_cursor = Some(ScrollPosition::Middle);
+        }
+        for slot in &mut self.buffers {
+            if let Some((cy, cx)) = remembered(&slot.buffer) {
+                (slot.cy, slot.cx) = (cy, cx);
+            }
+        }
+        self.clamp_cursor();
+    }
+
+    /// Puts the cursor on the first non-blank of a 1-based line, clamped to
+    /// the buffer, and shows that line in the middle of the screen.
+    pub fn goto_line(&mut self, line: usize) {
+        self.cy = line.saturating_sub(1).min(self.buffer.len().saturating_sub(1));
+        self.cx = self.buffer.first_non_blank(self.cy);
+        self.clamp_cursor();
+        self.scroll_cursor = Some(ScrollPosition::Middle);
+    }
+
+    /// Whether the cursor may sit just past the last char: in Insert and
+    /// Replace mode, and for a Ctrl-o command so it can stay at line end.
+    fn cursor_past_end(&self) -> bool {
+        self.insert_oneshot || matches!(self.mode, Mode::Insert | Mode::Replace)
+    }
+
+    fn save_as(&mut self, path: String) {
+        info!("Attempting to save file as: {}", path);
+        let trimmed = self.trim_before_save();
+        let existed = Path::new(&path).exists();
+        self.set_format_hook();
+        let result = self.buffer.save_as(path.clone());
+        self.buffer.post_save_hook = None;
+        match result {
+            Ok(bytes) => {
+                info!("File saved successfully ({} bytes)", bytes);
+                self.status_message = Some(format!("{}{}", self.written_message(&path, existed, bytes), trimmed));
+                self.history.mark_saved();
+                self.format_after_save();
+                self.remember_positions();
+            }
+            Err(e) => {
+                warn!("Error saving file: {}", e);
+                self.status_message = Some(format!("Error saving file: {}", e));
+            }
+        }
+    }
+
+    /// Opens `path` in a buffer of its own, or goes to the buffer that
+    /// already has it. Without a path, or given the current file, rereads
+    /// the current file from disk, keeping unsaved changes unless `force` is
+    /// set. A path that doesn't exist yet opens empty, to be created by the
+    /// first save.
+    fn edit_file(&mut self, path: Option<String>, force: bool) {
+        let current = self.buffer.file.clone();
+        let Some(path) = path.map(|path| buffer::expand_tilde(&path)).or_else(|| current.clone()) else {
+            self.status_message = Some("No file name".to_string());
+            return;
         };
-        let status_y = h.saturating_sub(1);
-        let mut status_line = String::new();
-        let left_len = left.len();
-        let right_len = right.len();
-        let total_width = w as usize;
-        if left_len + right_len >= total_width {
-            let available = total_width.saturating_sub(left_len + 1);
-            status_line.push_str(&left);
-            if available > 0 {
-                let truncated = &right[..available.min(right.len())];
-                status_line.push_str(truncated);
+        let rereading = current.as_deref().is_some_and(|file| buffer::same_file(file, &path));
+        if rereading && self.buffer.has_unsaved_changes() && !force {
+            self.status_message = Some("No write since last change (add ! to override)".to_string());
+            return;
+        }
+        if !rereading {
+            let open = self.buffers.iter().enumerate().position(|(i, slot)| {
+                i != self.current && slot.buffer.file.as_deref().is_some_and(|file| buffer::same_file(file, &path))
+            });
+            if let Some(index) = open {
+                self.switch_buffer(index);
+                return;
             }
-        } else {
-            status_line.push_str(&left);
-            let pad = total_width.saturating_sub(left_len + right_len);
-            for _ in 0..pad { status_line.push(' '); }
-            status_line.push_str(&right);
         }
-        let bar_bg = Color::DarkGrey;
-        let mode_color = match self.mode {
-            Mode::Normal => Color::Magenta,
-            Mode::Insert => Color::Cyan,
+        let buffer = if Path::new(&path).exists() {
+            match Buffer::from_file(Some(path.clone())) {
+                Ok(buffer) => buffer,
+                Err(e) => {
+                    warn!("Error opening {}: {}", path, e);
+                    self.status_message = Some(format!("Error opening {}: {}", path, e));
+                    return;
+                }
+            }
+        } else {
+            Buffer::new_file(path.clone())
         };
-        stdout.queue(MoveTo(0, status_y))?;
-        stdout.queue(SetBackgroundColor(bar_bg))?;
-        let filler = " ".repeat(total_width);
-        stdout.queue(Print(&filler))?;
-        stdout.queue(MoveTo(0, status_y))?;
-        stdout.queue(SetForegroundColor(mode_color))?;
-        stdout.queue(Print(&left))?;
-        let right_x = (w as usize).saturating_sub(right.len() as usize) as u16;
-        stdout.queue(MoveTo(right_x, status_y))?;
-        stdout.queue(Print(&right))?;
-        stdout.queue(ResetColor)?;
-        let cx = self.cx.min(w.saturating_sub(1));
-        let cy = self.cy.min(h.saturating_sub(1));
-        stdout.queue(MoveTo(cx, cy))?;
-        stdout.flush()?;
-        Ok(())
+        let message = if buffer.recovery.is_some() {
+            "Recovery file found — restore? (y/n)".to_string()
+        } else if buffer.invalid_utf8 {
+            format!("\"{}\" {} lines [invalid UTF-8, read-only]", path, buffer.len())
+        } else if Path::new(&path).exists() {
+            format!("\"{}\" {} lines", path, buffer.len())
+        } else {
+            format!("\"{}\" [New]", path)
+        };
+        self.remember_positions();
+        if rereading {
+            self.read_only |= buffer.invalid_utf8;
+            self.buffer = buffer;
+            self.history = UndoHistory::default();
+            self.jumps.clear();
+            self.jump_index = 0;
+            (self.cx, self.cy, self.row_offset) = (0, 0, 0);
+        } else {
+            self.add_buffer(buffer);
+            self.switch_buffer(self.buffers.len() - 1);
+        }
+        self.restore_positions();
+        match &mut self.swap_prompt {
+            Some(prompt) => prompt.opened = !rereading,
+            None => self.status_message = Some(message),
+        }
+    }
+
+    /// Takes the indentation usual for the current file's type, for the
+    /// options the user hasn't set themselves.
+    fn detect_indent(&mut self) {
+        let Some(style) = syntax::indent_style(self.buffer.file.as_deref()) else { return };
+        if !self.tab_width_set {
+            self.tab_width = style.tab_width;
+        }
+        if !self.expand_tab_set {
+            self.expand_tab = style.expand_tab;
+        }
+    }
+
+    /// Writes the swap file for the file on screen, unless this editor has
+    /// already. When another process holds it, asks what to do instead.
+    pub fn claim_swap(&mut self) {
+        self.claim_swap_after(None);
+    }
+
+    /// `claim_swap` for a buffer switched to from `previous`, which the
+    /// prompt offers to go back to.
+    fn claim_swap_after(&mut self, previous: Option<usize>) {
+        let Some(file) = self.buffer.file.clone().filter(|_| self.swap_file) else { return };
+        if self.swaps.contains(&file) {
+            return;
+        }
+        match swap::owner(&file) {
+            Some(owner) if owner.pid != process::id() => {
+                info!("Swap file for {} belongs to process {}", file, owner.pid);
+                let quit = if previous.is_some() { "(Q) go back" } else { "(Q)uit" };
+                self.status_message = Some(if owner.alive {
+                    format!("Swap file exists — (O)pen read-only, (E)dit anyway, {}", quit)
+                } else {
+                    format!(
+                        "Stale swap file from process {} — (D)elete it, (O)pen read-only, (E)dit anyway, {}",
+                        owner.pid, quit
+                    )
+                });
+                self.swap_prompt = Some(SwapPrompt { file, owner, previous, opened: false });
+            }
+            _ => {
+                swap::write(&file);
+                self.swaps.push(file);
+            }
+        }
+    }
+
+    fn answer_swap(&mut self, choice: SwapChoice) {
+        let Some(SwapPrompt { file, owner, previous, opened }) = self.swap_prompt.take() else { return };
+        info!("Swap file for {}: {:?}", file, choice);
+        self.status_message = None;
+        match choice {
+            SwapChoice::ReadOnly => {
+                self.read_only = true;
+                self.status_message = Some("Opened read-only".to_string());
+            }
+            // the other editor keeps its swap file; this one doesn't write one
+            SwapChoice::EditAnyway => {}
+            SwapChoice::Delete => {
+                swap::remove(&file);
+                self.claim_swap();
+                self.status_message = Some(format!("Deleted stale swap file from process {}", owner.pid));
+            }
+            // leaving the editor from a buffer switch would lose the other
+            // buffers, so only the switch is undone
+            SwapChoice::Quit => match previous {
+                Some(index) => {
+                    let abandoned = self.current;
+                    self.switch_buffer(index);
+                    if opened {
+                        self.buffers.remove(abandoned);
+                        if self.current > abandoned {
+                            self.current -= 1;
+                        }
+                    }
+                }
+                None => self.quit = true,
+            },
+        }
+        if self.buffer.recovery.is_some() {
+            self.status_message = Some("Recovery file found — restore? (y/n)".to_string());
+        }
+    }
+
+    /// Removes every swap file this editor wrote, on a clean quit, along
+    /// with the autosave of an unnamed buffer.
+    pub fn release_swaps(&mut self) {
+        for file in self.swaps.drain(..) {
+            swap::remove(&file);
+        }
+        if let Some(path) = &self.unnamed_autosave
+            && path.exists()
+            && let Err(e) = std::fs::remove_file(path)
+        {
+            warn!("Failed to remove {}: {}", path.display(), e);
+        }
+    }
+
+    /// Adds a place to the end of the jump list. An older entry on the same
+    /// line is dropped, as is the oldest one when the list is full.
+    fn push_jump(&mut self, (line, col): (usize, usize)) {
+        self.jumps.retain(|&(jumped, _)| jumped != line);
+        self.jumps.push((line, col));
+        if self.jumps.len() > MAX_JUMPS {
+            self.jumps.remove(0);
+        }
+        self.jump_index = self.jumps.len();
+    }
+
+    /// `Ctrl-o`: goes back to the previous place on the jump list. Leaving
+    /// the newest end remembers the cursor first, so `Ctrl-i` can return.
+    fn jump_back(&mut self) {
+        if self.jump_index == self.jumps.len() {
+            self.push_jump((self.cy, self.cx));
+            self.jump_index -= 1;
+        }
+        if self.jump_index > 0 {
+            self.jump_index -= 1;
+            self.go_to_jump();
+        }
+    }
+
+    /// Moves to the current jump list entry, clamped in case lines have been
+    /// deleted since it was recorded.
+    fn go_to_jump(&mut self) {
+        let (line, col) = self.jumps[self.jump_index];
+        self.cy = line.min(self.buffer.len().saturating_sub(1));
+        self.cx = col.min(self.buffer.line_length(self.cy).unwrap_or(0));
+    }
+
+    fn char_at(&self, line: usize, col: usize) -> Option<char> {
+        self.buffer.get_line(line).ok()?.chars().nth(col)
+    }
+
+    /// Whether the cursor is between a bracket or quote and its closing char,
+    /// as `auto_pairs` left them.
+    fn in_empty_pair(&self) -> bool {
+        self.auto_pairs
+            && self.cx > 0
+            && self
+                .char_at(self.cy, self.cx - 1)
+                .and_then(closing_pair)
+                .is_some_and(|close| self.char_at(self.cy, self.cx) == Some(close))
+    }
+
+    /// The closing char to add after `c` when typing it with `auto_pairs`.
+    /// A quote straight after a word is taken as an apostrophe or a closing
+    /// quote and left unpaired.
+    fn auto_pair(&self, c: char) -> Option<char> {
+        let close = closing_pair(c).filter(|_| self.auto_pairs)?;
+        let after_word = self.cx > 0 && self.char_at(self.cy, self.cx - 1).is_some_and(char::is_alphanumeric);
+        (close != c || !after_word).then_some(close)
+    }
+
+    /// Writes the buffer to `path` as a copy. Naming the buffer's own file
+    /// is a plain `:w`.
+    fn write_copy(&mut self, path: &str) {
+        if self.buffer.file.as_deref().is_some_and(|file| buffer::same_file(file, path)) {
+            self.apply_action(Actions::Save);
+            return;
+        }
+        let existed = Path::new(path).exists();
+        match self.buffer.write_to(path) {
+            Ok(bytes) => {
+                info!("Wrote a copy to {} ({} bytes)", path, bytes);
+                self.status_message = Some(self.written_message(path, existed, bytes));
+            }
+            Err(e) => {
+                warn!("Error writing {}: {}", path, e);
+                self.status_message = Some(format!("Error writing {}: {}", path, e));
+            }
+        }
+    }
+
+    /// Runs `command` through the shell for `:r !cmd` and puts its output
+    /// below the cursor, unless `allow_shell` is off.
+    fn read_command(&mut self, command: &str) {
+        if !self.allow_shell {
+            self.status_message = Some("Shell commands are off; set allow_shell in config.toml".to_string());
+            return;
+        }
+        info!("Reading the output of {}", command);
+        match process::Command::new("sh").arg("-c").arg(command).output() {
+            Ok(output) if output.status.success() => {
+                let lines = buffer::text_lines(&output.stdout);
+                self.status_message = Some(format!("{} more lines", lines.len()));
+                self.read_lines_in(&lines);
+            }
+            Ok(output) => {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                warn!("{} exited with {}: {}", command, output.status, stderr.trim());
+                self.status_message = Some(format!("{} failed: {}", command, output.status));
+            }
+            Err(e) => {
+                warn!("Could not run {}: {}", command, e);
+                self.status_message = Some(format!("Could not run {}: {}", command, e));
+            }
+        }
+    }
+
+    /// Puts lines read by `:r` below the cursor and moves onto the first.
+    fn read_lines_in(&mut self, lines: &[String]) {
+        if lines.is_empty() {
+            return;
+        }
+        match self.buffer.insert_lines(self.cy + 1, lines) {
+            Ok(()) => {
+                self.cy += 1;
+                self.cx = self.buffer.first_non_blank(self.cy);
+            }
+            Err(e) => self.status_message = Some(format!("Error inserting lines: {}", e)),
+        }
+    }
+
+    /// What vim says after a write: `"notes.txt" [New] 42L, 1024B written`.
+    fn written_message(&self, path: &str, existed: bool, bytes: usize) -> String {
+        let new = if existed { "" } else { "[New] " };
+        format!("\"{}\" {}{}L, {}B written", path, new, self.buffer.len(), bytes)
+    }
+
+    /// Strips trailing whitespace ahead of a write when that is turned on.
+    /// Returns a note for the save message saying how many lines changed.
+    fn trim_before_save(&mut self) -> String {
+        if !self.trim_trailing_whitespace {
+            return String::new();
+        }
+        match self.buffer.strip_trailing_whitespace() {
+            0 => String::new(),
+            count => format!(", trimmed trailing whitespace on {} lines", count),
+        }
+    }
+
+    /// What the status line shows in front of the text being typed in Command mode.
+    fn command_prompt(&self) -> &'static str {
+        if self.save_as_prompt { "Save as: " } else { ":" }
+    }
+
+    /// Tab on the command line: puts the first completion of what has been
+    /// typed in its place, or while one is showing, the next.
+    fn complete_command(&mut self) {
+        let index = match &self.completion {
+            Some((candidates, index)) if candidates[*index] == self.command_line => (index + 1) % candidates.len(),
+            _ => {
+                let candidates = if self.save_as_prompt {
+                    completion::complete_path(&self.command_line)
+                } else {
+                    completion::complete(&self.command_line, &command_names())
+                };
+                if candidates.is_empty() {
+                    self.completion = None;
+                    return;
+                }
+                self.completion = Some((candidates, 0));
+                0
+            }
+        };
+        if let Some((candidates, shown)) = &mut self.completion {
+            *shown = index;
+            self.command_line = candidates[index].clone();
+        }
+    }
+
+    /// "match 2 of 5" while Tab completion is showing a candidate.
+    fn completion_status(&self) -> Option<String> {
+        match &self.completion {
+            Some((candidates, index)) if self.mode == Mode::Command && candidates[*index] == self.command_line => {
+                Some(format!("match {} of {}", index + 1, candidates.len()))
+            }
+            _ => None,
+        }
+    }
+
+    /// Inserts an empty line at `index` and starts typing on it.
+    fn open_line(&mut self, index: usize) {
+        if self.buffer.insert_lines(index, &[String::new()]).is_ok() {
+            // the cursor line has moved down when opening above it
+            let from = if index <= self.cy { self.cy + 1 } else { self.cy };
+            self.cy = index;
+            self.cx = self.indent_like(from, index);
+            self.mode = Mode::Insert;
+        }
+    }
+
+    /// With `auto_indent` on, gives line `index` the indentation of line
+    /// `from` in place of its own. Returns the column after the indentation.
+    fn indent_like(&mut self, from: usize, index: usize) -> usize {
+        if !self.auto_indent {
+            return 0;
+        }
+        let indent = self.buffer.indentation(from);
+        self.buffer.reindent(index, &indent).unwrap_or(0)
+    }
+
+    /// Columns in one level of indentation.
+    fn shift_width(&self) -> usize {
+        if self.shift_width == 0 { self.tab_width } else { self.shift_width }
+    }
+
+    fn indent_style(&self) -> IndentStyle {
+        IndentStyle { tab_width: self.tab_width, expand_tab: self.expand_tab }
+    }
+
+    /// One level of indentation, in the current indent style.
+    fn indent_unit(&self) -> String {
+        buffer::indent_string(self.shift_width(), self.indent_style())
+    }
+
+    /// Indents (`right`) or dedents lines `start` to `end` by one shiftwidth,
+    /// leaving the cursor on the first non-blank of the first line.
+    fn shift_lines(&mut self, start: usize, end: usize, right: bool) {
+        let end = end.min(self.buffer.len().saturating_sub(1));
+        for index in start..=end {
+            let shifted = if right {
+                self.buffer.indent_line(index, self.shift_width(), self.indent_style())
+            } else {
+                self.buffer.dedent_line(index, self.shift_width(), self.indent_style())
+            };
+            if let Err(e) = shifted {
+                warn!("Error shifting line {}: {}", index, e);
+            }
+        }
+        self.cy = start;
+        self.cx = self.buffer.first_non_blank(start);
+        if end > start {
+            let arrow = if right { '>' } else { '<' };
+            self.status_message = Some(format!("{} lines {}ed 1 time", end - start + 1, arrow));
+        }
+    }
+
+    /// What Tab types in Insert mode: a tab character, or `tab_width` spaces
+    /// with `expand_tab`. Within the indentation at the start of a line it
+    /// types one level of indentation instead.
+    fn tab_text(&self) -> String {
+        if self.cx <= self.buffer.first_non_blank(self.cy) {
+            self.indent_unit()
+        } else if self.expand_tab {
+            " ".repeat(self.tab_width)
+        } else {
+            "\t".to_string()
+        }
+    }
+
+    /// Deletes the text `motion` covers into the register. `count` only
+    /// matters to find motions, which go to the count'th match. Returns
+    /// false when the motion fails, such as a find with no match; an empty
+    /// span at the end of a line still counts, as `c$` there inserts.
+    fn delete_motion(&mut self, motion: Motion, count: usize) -> bool {
+        let (start, end) = match motion {
+            Motion::WordForward => (self.cx, self.buffer.word_delete_end(self.cy, self.cx)),
+            Motion::LineEnd => (self.cx, self.buffer.line_length(self.cy).unwrap_or(0)),
+            Motion::InnerWord => match self.buffer.inner_word(self.cy, self.cx) {
+                Some(range) => range,
+                None => return false,
+            },
+            Motion::Find(find) => {
+                self.last_find = Some(find);
+                // a backward find stops short of the cursor's own char
+                match self.buffer.find_char(self.cy, self.cx, find, count) {
+                    Some(col) if find.forward => (self.cx, col + 1),
+                    Some(col) => (col, self.cx),
+                    None => return false,
+                }
+            }
+            Motion::LastLine => {
+                let last = self.buffer.len().saturating_sub(1);
+                match self.buffer.delete_lines(self.cy, last) {
+                    Ok(lines) => {
+                        self.status_message = Some(format!("{} fewer lines", lines.len()));
+                        self.store_register(lines, true);
+                        self.cy = self.cy.min(self.buffer.len() - 1);
+                        self.cx = self.buffer.first_non_blank(self.cy);
+                    }
+                    Err(e) => {
+                        self.status_message = Some(format!("Error deleting lines: {}", e));
+                        return false;
+                    }
+                }
+                return true;
+            }
+        };
+        // an empty line or the cursor already at the end leaves nothing to delete
+        if end <= start {
+            return true;
+        }
+        match self.buffer.delete_range((self.cy, start), (self.cy, end - 1)) {
+            Ok(text) => {
+                self.store_register(text, false);
+                self.cx = start;
+                true
+            }
+            Err(e) => {
+                self.status_message = Some(format!("Error deleting text: {}", e));
+                false
+            }
+        }
+    }
+
+    /// Whether yanks and pastes right now go through the system clipboard.
+    fn clipboard_selected(&self) -> bool {
+        self.use_system_clipboard || matches!(self.register_name, Some('+' | '*'))
+    }
+
+    /// Saves yanked or deleted text, copying it to the system clipboard too
+    /// when that was asked for.
+    fn store_register(&mut self, text: Vec<String>, linewise: bool) {
+        if self.clipboard_selected() {
+            match &mut self.clipboard {
+                Some(clipboard) => {
+                    clipboard.set_text(&clipboard::to_text(&text, linewise));
+                }
+                None => warn!("No system clipboard, yanking to the internal register"),
+            }
+        }
+        self.register = text;
+        self.register_linewise = linewise;
+    }
+
+    /// The text to paste: the system clipboard when selected and readable,
+    /// otherwise the internal register.
+    fn load_register(&mut self) -> (Vec<String>, bool) {
+        if self.clipboard_selected() {
+            match self.clipboard.as_mut().and_then(|c| c.get_text()) {
+                Some(text) => return clipboard::from_text(&text),
+                None => warn!("System clipboard unavailable, pasting the internal register"),
+            }
+        }
+        (self.register.clone(), self.register_linewise)
+    }
+
+    /// Puts the register after (`p`) or before (`P`) the cursor. Whole lines
+    /// go below or above the cursor line.
+    fn paste(&mut self, after: bool) {
+        let (register, linewise) = self.load_register();
+        if register.is_empty() {
+            self.status_message = Some("Nothing in register".to_string());
+            return;
+        }
+        if linewise {
+            let index = if after { self.cy + 1 } else { self.cy };
+            if self.buffer.insert_lines(index, &register).is_ok() {
+                self.cy = index;
+                self.cx = self.buffer.first_non_blank(index);
+            }
+            return;
+        }
+        let empty = self.buffer.line_length(self.cy).unwrap_or(0) == 0;
+        let col = if after && !empty { self.cx + 1 } else { self.cx };
+        match self.buffer.insert_text(self.cy, col, &register) {
+            Ok((line, end)) => {
+                self.cy = line;
+                self.cx = end.saturating_sub(1);
+            }
+            Err(e) => self.status_message = Some(format!("Error pasting: {}", e)),
+        }
+    }
+
+    /// The visual selection as (start, end) positions in buffer order, both inclusive.
+    fn selection(&self) -> ((usize, usize), (usize, usize)) {
+        let anchor = (self.vy, self.vx);
+        let cursor = (self.cy, self.cx);
+        if anchor <= cursor { (anchor, cursor) } else { (cursor, anchor) }
+    }
+
+    fn clamp_cursor(&mut self) {
+        (self.cy, self.cx) = self.buffer.clamp_position(self.cy, self.cx, self.cursor_past_end());
+    }
+
+    /// The text for a `%` token in the `statusline` template, if it is one.
+    fn statusline_field(&self, token: char, visible_height: usize) -> Option<String> {
+        let text = match token {
+            'M' => match self.mode {
+                Mode::Normal if self.insert_oneshot => "(INSERT)",
+                Mode::Normal => "NORMAL",
+                Mode::Insert => "INSERT",
+                Mode::Replace => "REPLACE",
+                Mode::Command => "COMMAND",
+                Mode::Visual => "VISUAL",
+            }
+            .to_string(),
+            'f' => self.buffer.display_name(),
+            'm' => if self.buffer.has_unsaved_changes() { "*" } else { "" }.to_string(),
+            'r' => if self.read_only { " [RO]" } else { "" }.to_string(),
+            'l' => (self.cy + 1).to_string(),
+            'c' => (self.cursor_col() + 1).to_string(),
+            'p' => scroll_indicator(self.row_offset, self.buffer.len(), visible_height),
+            'y' => Language::from_path(self.buffer.file.as_deref())
+                .name()
+                .map(|name| format!("[{}]", name))
+                .unwrap_or_default(),
+            _ => return None,
+        };
+        Some(text)
+    }
+
+    /// Vim's `Ctrl-g` message: the file, whether it has unsaved changes, its
+    /// length, and how far through it the cursor is.
+    fn file_info(&self) -> String {
+        let modified = if self.buffer.has_unsaved_changes() { " [Modified]" } else { "" };
+        let lines = self.buffer.len();
+        let percent = (self.cy + 1) * 100 / lines;
+        format!("\"{}\"{} {} lines --{}%--", self.buffer.display_name(), modified, lines, percent)
+    }
+
+    /// Summarises how the buffer differs from its file on disk.
+    fn diff_summary(&self) -> String {
+        let Some(path) = &self.buffer.file else {
+            return "No file name".to_string();
+        };
+        let saved = match self.buffer.disk_lines() {
+            Ok(lines) => lines,
+            Err(e) => {
+                warn!("Could not read {} for diff: {}", path, e);
+                return format!("Can't read {}: {}", path, e);
+            }
+        };
+        let (mut added, mut removed) = (0, 0);
+        for op in self.buffer.diff_against(&saved) {
+            match op {
+                DiffOp::Added(_) => added += 1,
+                DiffOp::Removed(_) => removed += 1,
+                DiffOp::Unchanged(_) => {}
+            }
+        }
+        format!("+{} -{} lines since last write", added, removed)
+    }
+
+    /// Has the buffer's post-save hook run the formatter for the extension
+    /// of whatever path it is saved to. Only `:w` formats, so the hook is
+    /// taken off again once that save returns.
+    fn set_format_hook(&mut self) {
+        let commands = self.format_commands.clone();
+        let result = Rc::clone(&self.format_result);
+        self.buffer.post_save_hook = Some(Box::new(move |path| *result.borrow_mut() = run_formatter(&commands, path)));
+    }
+
+    /// Loads the output of the formatter the save ran back into the buffer.
+    /// A failing formatter is reported but the content that was just saved
+    /// is left alone.
+    fn format_after_save(&mut self) {
+        let Some(result) = self.format_result.borrow_mut().take() else { return };
+        match result {
+            Ok(()) => match self.buffer.reload() {
+                Ok(()) => {
+                    self.clamp_cursor();
+                    let written = self.status_message.take().unwrap_or_default();
+                    self.status_message = Some(format!("{}, formatted", written));
+                }
+                Err(e) => {
+                    warn!("Could not reload formatted file: {}", e);
+                    self.status_message = Some(format!("Error reloading formatted file: {}", e));
+                }
+            },
+            Err(e) => self.status_message = Some(format!("Saved, but formatter failed: {}", e)),
+        }
+    }
+
+    /// Keys typed so far for a multi-key command that hasn't completed yet.
+    pub fn pending_indicator(&self) -> String {
+        let mut pending = String::new();
+        if self.insert_oneshot && self.mode == Mode::Normal {
+            pending.push_str("^O");
+        }
+        if let Some(count) = self.pending_count {
+            pending.push_str(&count.to_string());
+        }
+        if let Some(command) = self.pending_macro {
+            pending.push(command);
+        }
+        if self.awaiting_register {
+            pending.push('"');
+        } else if let Some(name) = self.register_name {
+            pending.push('"');
+            pending.push(name);
+        }
+        if self.pending_g {
+            pending.push('g');
+        }
+        if self.pending_z {
+            pending.push('z');
+        }
+        if self.awaiting_replace {
+            pending.push('r');
+        }
+        if let Some(operator) = self.pending_operator {
+            pending.push(operator);
+        }
+        if self.pending_object {
+            pending.push('i');
+        }
+        if let Some((forward, till)) = self.awaiting_find {
+            pending.push(match (forward, till) {
+                (true, false) => 'f',
+                (false, false) => 'F',
+                (true, true) => 't',
+                (false, true) => 'T',
+            });
+        }
+        pending
+    }
+
+    /// Moves to where `find` lands from `from` on the cursor line; stays put
+    /// when there is no such char.
+    fn find_char(&mut self, find: CharFind, from: usize, count: usize) {
+        match self.buffer.find_char(self.cy, from, find, count) {
+            Some(col) => self.cx = col,
+            None => debug!("No {:?} on line {}", find, self.cy),
+        }
+    }
+
+    fn search_word(&mut self, word: &str, forward: bool) {
+        self.search_highlight = true;
+        match self.buffer.find_whole_word(word, self.cy, self.cx, forward) {
+            Some(((line, col), wrapped)) => {
+                debug!("Found '{}' at {}:{}", word, line, col);
+                self.cy = line;
+                self.cx = col;
+                self.status_message = match (wrapped, forward) {
+                    (true, true) => Some("search hit BOTTOM, continuing at TOP".to_string()),
+                    (true, false) => Some("search hit TOP, continuing at BOTTOM".to_string()),
+                    (false, _) => Some(format!("{}{}", if forward { "*" } else { "#" }, word)),
+                };
+            }
+            None => {
+                self.status_message = Some(format!("Pattern not found: {}", word));
+            }
+        }
+    }
+
+    pub fn render(&mut self, stdout: &mut impl Write) -> Result<()> {
+        let (w, h) = terminal::size()?;
+        self.draw(stdout, w, h)
+    }
+
+    /// Draws a frame for a `w` x `h` terminal, emitting only the cells that
+    /// changed since the previously drawn frame.
+    pub fn draw(&mut self, stdout: &mut impl Write, w: u16, h: u16) -> Result<()> {
+        if std::mem::take(&mut self.force_redraw) {
+            debug!("Forcing a full redraw");
+            self.last_frame = None;
+            stdout.queue(terminal::Clear(terminal::ClearType::All))?;
+        }
+        let frame = self.compose_frame(w, h);
+        let changes = frame.diff(self.last_frame.as_ref());
+        debug!("Drawing {} changed cells", changes.len());
+        screen::write_changes(stdout, &changes)?;
+        let (cx, cy) = self.screen_cursor(&frame);
+        stdout.queue(MoveTo(cx, cy))?;
+        stdout.flush()?;
+        self.last_frame = Some(frame);
+        Ok(())
+    }
+
+    /// Where the terminal cursor belongs for a composed frame.
+    fn screen_cursor(&self, frame: &Frame) -> (u16, u16) {
+        let (w, h) = (frame.width(), frame.height());
+        let status_y = h.saturating_sub(1);
+        if self.mode == Mode::Command {
+            let typed = buffer::display_width(self.command_prompt()) + buffer::display_width(&self.command_line);
+            let cx = (typed as u16).min(w.saturating_sub(1));
+            (cx, status_y)
+        } else {
+            // cursor coordinates are only narrowed to u16 here, clamped to the viewport
+            let visible_height = status_y as usize;
+            let (x, y) = self
+                .layout(w, visible_height)
+                .iter()
+                .find(|line| line.index == self.cy)
+                .map(|line| {
+                    let (row, x) = line.place(self.cx);
+                    (x, line.y + row)
+                })
+                .unwrap_or((self.cursor_col(), self.cy.saturating_sub(self.row_offset)));
+            let cx = (self.gutter_width() + x).min(w.saturating_sub(1) as usize) as u16;
+            let cy = y.min(visible_height.saturating_sub(1)) as u16;
+            (cx, cy)
+        }
+    }
+
+    /// Rows of text on screen when last drawn, leaving out the status line.
+    fn text_height(&self) -> usize {
+        self.last_frame.as_ref().map_or(0, |frame| frame.height().saturating_sub(1) as usize)
+    }
+
+    /// Screen columns that long lines wrap at, or `None` when they aren't
+    /// wrapped and every line takes one row.
+    fn wrap_width(&self) -> Option<usize> {
+        self.wrap_width_for(self.last_frame.as_ref()?.width())
+    }
+
+    /// [`Self::wrap_width`] on a screen `width` columns wide.
+    fn wrap_width_for(&self, width: u16) -> Option<usize> {
+        self.wrap.then(|| (width as usize).saturating_sub(self.gutter_width()).max(1))
+    }
+
+    /// How the lines from `row_offset` down fill a screen `width` columns
+    /// wide with `height` rows of text.
+    fn layout(&self, width: u16, height: usize) -> Vec<LineLayout> {
+        let wrap_width = self.wrap_width_for(width);
+        let mut layout = Vec::new();
+        let mut y = 0;
+        for (index, line) in self.buffer.lines.iter().enumerate().skip(self.row_offset) {
+            if y >= height {
+                break;
+            }
+            let line = LineLayout::new(line, index, y, wrap_width, self.tab_width);
+            y += line.starts.len();
+            layout.push(line);
+        }
+        layout
+    }
+
+    /// Scrolls down until the cursor's display row fits on screen, for when
+    /// wrapped lines above it take more rows than there are.
+    fn scroll_wrapped_cursor_into_view(&mut self, width: u16, height: usize) {
+        let Some(wrap_width) = self.wrap_width_for(width) else { return };
+        let tab_width = self.tab_width;
+        let rows = |line: &str| buffer::wrap_starts(line, wrap_width, tab_width);
+        let cursor_line = self.buffer.lines.get(self.cy).map(String::as_str).unwrap_or("");
+        let cursor_row = rows(cursor_line).partition_point(|&start| start <= self.cx) - 1;
+        let mut above: usize = self.buffer.lines[self.row_offset..self.cy].iter().map(|l| rows(l).len()).sum();
+        while self.row_offset < self.cy && above + cursor_row >= height {
+            above -= rows(&self.buffer.lines[self.row_offset]).len();
+            self.row_offset += 1;
+        }
+    }
+
+    /// Moves the cursor one display row down or up, as `gj` and `gk` do:
+    /// to the next row of a wrapped line, or on to the neighbouring line.
+    /// The cursor keeps its column within the row, counted in screen columns
+    /// from the row's first char (see [`buffer::wrap_starts`]), and stays
+    /// inside the row it lands on.
+    fn move_display_line(&mut self, width: usize, down: bool) {
+        let rows = |index: usize| {
+            let line = self.buffer.get_line(index).map(String::as_str).unwrap_or("");
+            (line, buffer::wrap_starts(line, width, self.tab_width))
+        };
+        let (line, starts) = rows(self.cy);
+        let row = starts.partition_point(|&start| start <= self.cx) - 1;
+        let offset = buffer::visual_col(line, self.cx, self.tab_width)
+            - buffer::visual_col(line, starts[row], self.tab_width);
+        let (cy, row) = if down && row + 1 < starts.len() {
+            (self.cy, row + 1)
+        } else if down && self.cy + 1 < self.buffer.len() {
+            (self.cy + 1, 0)
+        } else if !down && row > 0 {
+            (self.cy, row - 1)
+        } else if !down && self.cy > 0 {
+            (self.cy - 1, rows(self.cy - 1).1.len() - 1)
+        } else {
+            return;
+        };
+        let (line, starts) = rows(cy);
+        let visual = buffer::visual_col(line, starts[row], self.tab_width) + offset;
+        let end = starts.get(row + 1).map_or(usize::MAX, |&next| next - 1);
+        self.cy = cy;
+        self.cx = buffer::char_col(line, visual, self.tab_width).min(end);
+    }
+
+    /// Screen column of the cursor within its line, with tabs expanded.
+    fn cursor_col(&self) -> usize {
+        let line = self.buffer.get_line(self.cy).map(String::as_str).unwrap_or("");
+        buffer::visual_col(line, self.cx, self.tab_width)
+    }
+
+    /// Columns taken by the line-number gutter, including its trailing space.
+    pub fn gutter_width(&self) -> usize {
+        if self.show_line_numbers {
+            self.buffer.len().to_string().len() + 1
+        } else {
+            0
+        }
+    }
+
+    /// Clears the status message once it has been on screen for
+    /// `status_timeout`, returning whether it did. The recovery and swap file
+    /// questions stay until they are answered.
+    fn expire_status_message(&mut self, now: Instant) -> bool {
+        let Some(message) = &self.status_message else {
+            self.status_shown = None;
+            return false;
+        };
+        match &self.status_shown {
+            Some((shown, since)) if shown == message => {
+                let expired = self.status_timeout.is_some_and(|timeout| now.duration_since(*since) >= timeout);
+                if expired && self.buffer.recovery.is_none() && self.swap_prompt.is_none() {
+                    debug!("Status message timed out: {}", message);
+                    self.status_message = None;
+                    self.status_shown = None;
+                    return true;
+                }
+            }
+            _ => self.status_shown = Some((message.clone(), now)),
+        }
+        false
+    }
+
+    /// Periodic work between events. Returns whether the screen needs
+    /// drawing again.
+    pub fn tick(&mut self, now: Instant) -> bool {
+        let expired = self.expire_status_message(now);
+        let autosaved = self.autosave(now);
+        self.refresh_swaps(now);
+        expired || autosaved
+    }
+
+    /// Rewrites the swap files every `SWAP_INTERVAL` so their timestamps
+    /// show this editor is still running.
+    fn refresh_swaps(&mut self, now: Instant) {
+        let last = *self.last_swap_write.get_or_insert(now);
+        if now.duration_since(last) < SWAP_INTERVAL {
+            return;
+        }
+        self.last_swap_write = Some(now);
+        for file in &self.swaps {
+            swap::write(file);
+        }
+    }
+
+    /// Writes unsaved changes once `autosave_interval` has passed since the
+    /// last autosave. Returns whether it tried to.
+    fn autosave(&mut self, now: Instant) -> bool {
+        let Some(interval) = self.autosave_interval.filter(|_| !self.read_only) else { return false };
+        let last = *self.last_autosave.get_or_insert(now);
+        if now.duration_since(last) < interval || !self.buffer.has_unsaved_changes() {
+            return false;
+        }
+        // never guess a real path for an unnamed buffer; it goes under ~/.vix
+        // and stays modified
+        let unnamed = match (&self.buffer.file, &self.unnamed_autosave) {
+            (Some(_), _) => None,
+            (None, Some(path)) => Some(path.display().to_string()),
+            (None, None) => return false,
+        };
+        self.last_autosave = Some(now);
+        let result = match &unnamed {
+            Some(path) => self.buffer.write_to(path),
+            None => self.buffer.save(),
+        };
+        self.status_message = Some(match result {
+            Ok(bytes) => {
+                info!("Autosaved {} bytes", bytes);
+                if unnamed.is_none() {
+                    self.history.mark_saved();
+                }
+                "autosaved".to_string()
+            }
+            Err(e) => {
+                warn!("Autosave failed: {}", e);
+                format!("Autosave failed: {}", e)
+            }
+        });
+        true
+    }
+
+    /// Composes the whole screen for a `w` x `h` terminal into a cell grid.
+    pub fn compose_frame(&mut self, w: u16, h: u16) -> Frame {
+        self.expire_status_message(Instant::now());
+        let mut frame = Frame::new(w, h);
+        let visible_height = h.saturating_sub(1) as usize; // leave last line for status
+
+        // adjust row_offset so cy is visible
+        self.row_offset = match self.scroll_cursor.take() {
+            Some(position) => scroll_cursor_to(position, self.cy, visible_height, self.buffer.len()),
+            None => scroll_to_line(self.row_offset, self.cy, visible_height),
+        };
+        self.scroll_wrapped_cursor_into_view(w, visible_height);
+
+        let gutter = self.gutter_width();
+        let language = Language::from_path(self.buffer.file.as_deref());
+        // only cells above the status line are text; anything past it is cut off
+        let recolor = |frame: &mut Frame, (x, y): (usize, usize), fg: Option<Color>, bg: Option<Color>| {
+            let (x, y) = ((gutter + x) as u16, y as u16);
+            if (y as usize) < visible_height && let Some(&cell) = frame.get(x, y) {
+                frame.set(x, y, Cell { fg, bg: bg.or(cell.bg), ..cell });
+            }
+        };
+        let layout = self.layout(w, visible_height);
+        let settings = (self.tab_width, self.list, self.syntax_highlighting.then_some(language));
+        for line_layout in &layout {
+            let line = &self.buffer.lines[line_layout.index];
+            if gutter > 0 {
+                let number = format!("{:>width$} ", line_layout.index + 1, width = gutter - 1);
+                frame.put_str(0, line_layout.y as u16, &number, Some(Color::DarkGrey), None);
+            }
+            let rendered = self.render_cache.get(line, &line_layout.cols, settings);
+            for (col, (text, marker)) in rendered.chars.iter().enumerate() {
+                let (row, x) = line_layout.place(col);
+                let y = line_layout.y + row;
+                if y >= visible_height {
+                    break;
+                }
+                let fg = marker.then_some(Color::DarkGrey);
+                frame.put_str((gutter + x) as u16, y as u16, text, fg, None);
+            }
+            for span in &rendered.spans {
+                for cell in line_layout.cells(span.start..span.end) {
+                    recolor(&mut frame, cell, Some(span.kind.color()), None);
+                }
+            }
+        }
+        self.render_cache.finish_frame();
+        if let Some((word, _)) = self.last_search.as_ref().filter(|_| self.hlsearch && self.search_highlight) {
+            let len = word.chars().count();
+            for line_layout in &layout {
+                for col in self.buffer.whole_word_columns(line_layout.index, word) {
+                    for cell in line_layout.cells(col..col + len) {
+                        recolor(&mut frame, cell, Some(Color::Black), Some(Color::Yellow));
+                    }
+                }
+            }
+        }
+        if self.mode == Mode::Visual {
+            let (start, end) = self.selection();
+            for line_layout in &layout {
+                let index = line_layout.index;
+                if index < start.0 || index > end.0 {
+                    continue;
+                }
+                let len = line_layout.cols.len() - 1;
+                let from = if index == start.0 { start.1 } else { 0 };
+                let to = if index == end.0 { (end.1 + 1).min(len) } else { len };
+                for cell in line_layout.cells(from..to) {
+                    recolor(&mut frame, cell, Some(Color::Black), Some(Color::Grey));
+                }
+            }
+        }
+        if self.shows_start_screen() {
+            let lines = Self::start_screen_lines();
+            let width = lines.iter().map(|l| l.chars().count()).max().unwrap_or(0);
+            let top = visible_height.saturating_sub(lines.len()) / 2;
+            let left = (w as usize).saturating_sub(width) / 2;
+            for (i, line) in lines.iter().enumerate() {
+                if top + i >= visible_height { break; }
+                frame.put_str(left as u16, (top + i) as u16, line, None, None);
+            }
+        }
+        if let Some(offset) = self.help {
+            let lines = help_lines(&self.keymap);
+            for y in 0..visible_height {
+                frame.fill_row(y as u16, Cell { ch: ' ', fg: None, bg: None });
+                if let Some(line) = lines.get(offset + y) {
+                    frame.put_str(0, y as u16, line, None, None);
+                }
+            }
+        }
+        let (left, status_right) =
+            expand_statusline(&self.statusline, |token| self.statusline_field(token, visible_height));
+        let left = if self.mode == Mode::Command {
+            format!("{}{}", self.command_prompt(), self.command_line)
+        } else {
+            left
+        };
+        // a status message takes the place of the right side of the template
+        let mut right = if let Some(matches) = self.completion_status() {
+            matches
+        } else if let Some(msg) = &self.status_message {
+            msg.clone()
+        } else {
+            status_right
+        };
+        let pending = self.pending_indicator();
+        if !pending.is_empty() {
+            right = format!("{}  {}", pending, right);
+        }
+        if let Some(name) = self.macro_register {
+            right = format!("recording @{}  {}", name, right);
+        }
+        let status_y = h.saturating_sub(1);
+        let bar_bg = Some(Color::DarkGrey);
+        let mode_color = Some(match self.mode {
+            Mode::Normal => Color::Magenta,
+            Mode::Insert => Color::Cyan,
+            Mode::Replace => Color::Red,
+            Mode::Command => Color::Yellow,
+            Mode::Visual => Color::Green,
+        });
+        frame.fill_row(status_y, Cell { ch: ' ', fg: None, bg: bar_bg });
+        // the right side is drawn first so a long left side wins when they overlap
+        let right_x = (w as usize).saturating_sub(buffer::display_width(&right)) as u16;
+        frame.put_str(right_x, status_y, &right, mode_color, bar_bg);
+        frame.put_str(0, status_y, &left, mode_color, bar_bg);
+        frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::event::KeyModifiers;
+
+    #[test]
+    fn test_start_screen_for_default_buffer() {
+        let mut editor = Editor::new();
+        assert!(editor.shows_start_screen());
+
+        editor.apply_action(Actions::EnterMode(Mode::Insert));
+        editor.apply_action(Actions::PrintChar('a'));
+        assert!(!editor.shows_start_screen());
+    }
+
+    fn editor_with(lines: &[&str]) -> Editor {
+        Editor::with_buffer(Buffer {
+            lines: lines.iter().map(|s| s.to_string()).collect(),
+            ..Default::default()
+        })
+    }
+
+    fn key(code: KeyCode, modifiers: KeyModifiers) -> Event {
+        Event::Key(crossterm::event::KeyEvent::new(code, modifiers))
+    }
+
+    fn press(editor: &mut Editor, ev: Event) {
+        editor.process_event(ev);
+    }
+
+    fn run_command(editor: &mut Editor, command: &str) {
+        editor.apply_action(Actions::EnterMode(Mode::Command));
+        for c in command.chars() {
+            editor.apply_action(Actions::CommandChar(c));
+        }
+        editor.apply_action(Actions::ExecuteCommand);
+    }
+
+    #[test]
+    fn test_stripws_command() {
+        let mut editor = editor_with(&["one  ", "two", "three\t"]);
+        run_command(&mut editor, "stripws");
+        assert_eq!(editor.buffer.lines, vec!["one", "two", "three"]);
+        assert_eq!(editor.mode, Mode::Normal);
+        assert_eq!(
+            editor.status_message.as_deref(),
+            Some("Trimmed trailing whitespace on 2 lines")
+        );
+    }
+
+    #[test]
+    fn test_ctrl_o_runs_one_normal_command() {
+        let mut editor = editor_with(&["first", "second line"]);
+        editor.apply_action(Actions::EnterMode(Mode::Insert));
+        editor.cx = 3;
+
+        press(&mut editor, key(KeyCode::Char('o'), KeyModifiers::CONTROL));
+        assert_eq!(editor.mode, Mode::Normal);
+        assert!(editor.insert_oneshot);
+        assert_eq!(editor.pending_indicator(), "^O");
+
+        press(&mut editor, key(KeyCode::Char('j'), KeyModifiers::NONE));
+        assert_eq!((editor.cx, editor.cy), (3, 1));
+        assert_eq!(editor.mode, Mode::Insert);
+        assert!(!editor.insert_oneshot);
+        assert_eq!(editor.pending_indicator(), "");
+
+        // the following key is inserted as text again
+        press(&mut editor, key(KeyCode::Char('j'), KeyModifiers::NONE));
+        assert_eq!(editor.buffer.lines[1], "secjond line");
+    }
+
+    #[test]
+    fn test_ctrl_w_deletes_the_word_before_the_cursor() {
+        let mut editor = editor_with(&["hello world", "next"]);
+        editor.apply_action(Actions::EnterMode(Mode::Insert));
+        editor.cx = 11;
+        press(&mut editor, key(KeyCode::Char('w'), KeyModifiers::CONTROL));
+        assert_eq!(editor.buffer.lines[0], "hello ");
+        assert_eq!(editor.cx, 6);
+        press(&mut editor, key(KeyCode::Char('w'), KeyModifiers::CONTROL));
+        assert_eq!(editor.buffer.lines[0], "");
+        assert_eq!(editor.cx, 0);
+
+        // column 0 is left alone rather than joined with the line above
+        editor.cy = 1;
+        press(&mut editor, key(KeyCode::Char('w'), KeyModifiers::CONTROL));
+        assert_eq!(editor.buffer.lines, vec!["", "next"]);
+        assert_eq!((editor.cy, editor.cx), (1, 0));
+
+        editor.apply_action(Actions::EnterMode(Mode::Normal));
+        editor.apply_action(Actions::Undo);
+        assert_eq!(editor.buffer.lines, vec!["hello world", "next"]);
+    }
+
+    #[test]
+    fn test_tab_inserts_a_tab_or_spaces() {
+        let mut editor = editor_with(&["ab"]);
+        editor.apply_action(Actions::EnterMode(Mode::Insert));
+        editor.cx = 1;
+        press(&mut editor, key(KeyCode::Tab, KeyModifiers::NONE));
+        assert_eq!(editor.buffer.lines, vec!["a\tb"]);
+        assert_eq!(editor.cx, 2);
+
+        editor.expand_tab = true;
+        editor.tab_width = 2;
+        press(&mut editor, key(KeyCode::Tab, KeyModifiers::NONE));
+        assert_eq!(editor.buffer.lines, vec!["a\t  b"]);
+        assert_eq!(editor.cx, 4);
+    }
+
+    #[test]
+    fn test_tab_in_indentation_types_a_shiftwidth() {
+        let mut editor = editor_with(&["x"]);
+        editor.apply_action(Actions::EnterMode(Mode::Insert));
+        editor.shift_width = 6;
+        press(&mut editor, key(KeyCode::Tab, KeyModifiers::NONE));
+        assert_eq!(editor.buffer.lines, vec!["\t  x"]);
+
+        editor.expand_tab = true;
+        editor.shift_width = 0;
+        press(&mut editor, key(KeyCode::Tab, KeyModifiers::NONE));
+        assert_eq!(editor.buffer.lines, vec!["\t      x"]);
+        assert_eq!(editor.cx, 7);
+    }
+
+    #[test]
+    fn test_shift_lines_with_count_and_repeat() {
+        let mut editor = editor_with(&["a", "  b", "c", "d"]);
+        editor.expand_tab = true;
+        editor.tab_width = 4;
+        press_keys(&mut editor, ">>");
+        assert_eq!(editor.buffer.lines, vec!["    a", "  b", "c", "d"]);
+        assert_eq!(editor.cx, 4);
+
+        press_keys(&mut editor, "3<<");
+        assert_eq!(editor.buffer.lines, vec!["a", "b", "c", "d"]);
+        assert_eq!(editor.status_message.as_deref(), Some("3 lines <ed 1 time"));
+
+        press_keys(&mut editor, "j.");
+        assert_eq!(editor.buffer.lines, vec!["a", "b", "c", "d"]);
+        press_keys(&mut editor, "2>>j.");
+        assert_eq!(editor.buffer.lines, vec!["a", "    b", "        c", "    d"]);
+        assert_eq!((editor.cy, editor.cx), (2, 8));
+    }
+
+    #[test]
+    fn test_visual_shift_indents_selected_lines() {
+        let mut editor = editor_with(&["a", "b", "c"]);
+        editor.shift_width = 2;
+        press_keys(&mut editor, "vj>");
+        assert_eq!(editor.buffer.lines, vec!["  a", "  b", "c"]);
+        assert_eq!(editor.mode, Mode::Normal);
+        assert_eq!((editor.cy, editor.cx), (0, 2));
+        press_keys(&mut editor, "jvj<");
+        assert_eq!(editor.buffer.lines, vec!["  a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_auto_pairs_close_brackets_and_quotes() {
+        let mut editor = editor_with(&[""]);
+        editor.auto_pairs = true;
+        editor.apply_action(Actions::EnterMode(Mode::Insert));
+        type_text(&mut editor, "f(");
+        assert_eq!(editor.buffer.lines, vec!["f()"]);
+        assert_eq!(editor.cx, 2);
+        type_text(&mut editor, "[\"");
+        assert_eq!(editor.buffer.lines, vec!["f([\"\"])"]);
+        type_text(&mut editor, "it's");
+        assert_eq!(editor.buffer.lines, vec!["f([\"it's\"])"]);
+
+        editor.auto_pairs = false;
+        type_text(&mut editor, "{");
+        assert_eq!(editor.buffer.lines, vec!["f([\"it's{\"])"]);
+    }
+
+    #[test]
+    fn test_auto_pairs_type_over_closing_char() {
+        let mut editor = editor_with(&[""]);
+        editor.auto_pairs = true;
+        editor.apply_action(Actions::EnterMode(Mode::Insert));
+        type_text(&mut editor, "(x)");
+        assert_eq!(editor.buffer.lines, vec!["(x)"]);
+        assert_eq!(editor.cx, 3);
+        type_text(&mut editor, ")");
+        assert_eq!(editor.buffer.lines, vec!["(x))"]);
+        type_text(&mut editor, " \"a\"");
+        assert_eq!(editor.buffer.lines, vec!["(x)) \"a\""]);
+        assert_eq!(editor.cx, 8);
+    }
+
+    #[test]
+    fn test_backspace_deletes_an_empty_pair() {
+        let mut editor = editor_with(&["ab"]);
+        editor.auto_pairs = true;
+        editor.apply_action(Actions::EnterMode(Mode::Insert));
+        editor.cx = 1;
+        type_text(&mut editor, "{");
+        assert_eq!(editor.buffer.lines, vec!["a{}b"]);
+        editor.apply_action(Actions::Backspace);
+        assert_eq!(editor.buffer.lines, vec!["ab"]);
+        assert_eq!(editor.cx, 1);
+
+        type_text(&mut editor, "(x");
+        editor.apply_action(Actions::Backspace);
+        editor.apply_action(Actions::Backspace);
+        assert_eq!(editor.buffer.lines, vec!["ab"]);
+        type_text(&mut editor, ")");
+        editor.apply_action(Actions::Backspace);
+        assert_eq!(editor.buffer.lines, vec!["ab"]);
+    }
+
+    #[test]
+    fn test_emacs_keys_move_and_kill_in_insert_mode() {
+        let ctrl = |c| key(KeyCode::Char(c), KeyModifiers::CONTROL);
+        let mut editor = editor_with(&["hello world"]);
+        editor.insert_emacs_keys = true;
+        editor.apply_action(Actions::EnterMode(Mode::Insert));
+        for (c, col) in [('a', 0), ('e', 11), ('b', 4), ('f', 6)] {
+            editor.cx = 5;
+            press(&mut editor, ctrl(c));
+            assert_eq!(editor.cx, col, "Ctrl-{}", c);
+        }
+        editor.cx = 5;
+        press(&mut editor, ctrl('k'));
+        assert_eq!(editor.buffer.lines, vec!["hello"]);
+        assert_eq!(editor.mode, Mode::Insert);
+        editor.apply_action(Actions::EnterMode(Mode::Normal));
+        editor.apply_action(Actions::Undo);
+        assert_eq!(editor.buffer.lines, vec!["hello world"]);
+
+        let mut editor = editor_with(&["text"]);
+        editor.apply_action(Actions::EnterMode(Mode::Insert));
+        press(&mut editor, ctrl('a'));
+        assert_eq!(editor.buffer.lines, vec!["text"]);
+        assert_eq!(editor.cx, 0);
+    }
+
+    #[test]
+    fn test_insert_text_mid_line() {
+        let mut editor = editor_with(&["héllo"]);
+        editor.apply_action(Actions::EnterMode(Mode::Insert));
+        editor.cx = 2;
+        editor.apply_action(Actions::InsertText("a\nb".to_string()));
+        assert_eq!(editor.buffer.lines, vec!["héa", "bllo"]);
+        assert_eq!((editor.cy, editor.cx), (1, 1));
+        editor.apply_action(Actions::EnterMode(Mode::Normal));
+
+        editor.apply_action(Actions::RepeatChange);
+        assert_eq!(editor.buffer.lines, vec!["héa", "ba", "bllo"]);
+    }
+
+    #[test]
+    fn test_paste_is_inserted_verbatim() {
+        let mut editor = editor_with(&["    fn main() {}"]);
+        editor.auto_pairs = true;
+        editor.cx = 15;
+        press(&mut editor, Event::Paste("x".to_string()));
+        assert_eq!(editor.buffer.lines, vec!["    fn main() {}"]);
+
+        editor.apply_action(Actions::EnterMode(Mode::Insert));
+        press(&mut editor, Event::Paste("\r\n        let (a\r\n        b);\r\n    ".to_string()));
+        assert_eq!(
+            editor.buffer.lines,
+            vec!["    fn main() {", "        let (a", "        b);", "    }"]
+        );
+        assert_eq!((editor.cy, editor.cx), (3, 4));
+
+        editor.apply_action(Actions::EnterMode(Mode::Normal));
+        editor.apply_action(Actions::Undo);
+        assert_eq!(editor.buffer.lines, vec!["    fn main() {}"]);
+    }
+
+    #[test]
+    fn test_ctrl_u_deletes_to_line_start() {
+        let mut editor = editor_with(&["keep this text"]);
+        editor.register = vec!["yanked".to_string()];
+        editor.apply_action(Actions::EnterMode(Mode::Insert));
+        editor.cx = 10;
+        press(&mut editor, key(KeyCode::Char('u'), KeyModifiers::CONTROL));
+        assert_eq!(editor.buffer.lines, vec!["text"]);
+        assert_eq!(editor.cx, 0);
+        assert_eq!(editor.register, vec!["yanked"]);
+
+        press(&mut editor, key(KeyCode::Char('u'), KeyModifiers::CONTROL));
+        assert_eq!(editor.buffer.lines, vec!["text"]);
+    }
+
+    #[test]
+    fn test_ctrl_o_unmapped_key_returns_to_insert() {
+        let mut editor = editor_with(&["text"]);
+        editor.apply_action(Actions::EnterMode(Mode::Insert));
+        press(&mut editor, key(KeyCode::Char('o'), KeyModifiers::CONTROL));
+        press(&mut editor, key(KeyCode::Char('z'), KeyModifiers::NONE));
+        assert_eq!(editor.mode, Mode::Insert);
+        assert_eq!(editor.buffer.lines, vec!["text"]);
+    }
+
+    #[test]
+    fn test_star_jumps_to_next_whole_word() {
+        let mut editor = editor_with(&["foo bar", "foobar foo_1", "  foo"]);
+        editor.apply_action(Actions::SearchWordForward);
+        assert_eq!((editor.cx, editor.cy), (2, 2));
+
+        editor.apply_action(Actions::SearchWordForward);
+        assert_eq!((editor.cx, editor.cy), (0, 0));
+        assert_eq!(
+            editor.status_message.as_deref(),
+            Some("search hit BOTTOM, continuing at TOP")
+        );
+
+        editor.apply_action(Actions::SearchWordBackward);
+        assert_eq!((editor.cx, editor.cy), (2, 2));
+    }
+
+    #[test]
+    fn test_diff_command_counts_changed_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("diff.txt");
+        std::fs::write(&path, "a\nb\nc").unwrap();
+        let mut editor = Editor::with_buffer(
+            Buffer::from_file(Some(path.to_string_lossy().into_owned())).unwrap(),
+        );
+        editor.apply_action(Actions::DeleteLine);
+        editor.apply_action(Actions::EnterMode(Mode::Insert));
+        editor.apply_action(Actions::PrintChar('x'));
+        run_command(&mut editor, "diff");
+        assert_eq!(
+            editor.status_message.as_deref(),
+            Some("+1 -2 lines since last write")
+        );
+    }
+
+    #[test]
+    fn test_diff_command_reads_file_as_opened() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("diff.txt");
+        std::fs::write(&path, b"\xef\xbb\xbfa\r\nb\xff\r\n").unwrap();
+        let mut editor = Editor::with_buffer(
+            Buffer::from_file(Some(path.to_string_lossy().into_owned())).unwrap(),
+        );
+        run_command(&mut editor, "diff");
+        assert_eq!(editor.status_message.as_deref(), Some("+0 -0 lines since last write"));
+
+        std::fs::remove_file(&path).unwrap();
+        run_command(&mut editor, "diff");
+        let message = editor.status_message.clone().unwrap();
+        assert!(message.starts_with("Can't read "), "{}", message);
+    }
+
+    #[test]
+    fn test_move_right_wraps_only_with_whichwrap() {
+        let mut editor = editor_with(&["ab", "cd"]);
+        editor.mode = Mode::Insert;
+        editor.cx = 2;
+        editor.apply_action(Actions::MoveRight);
+        assert_eq!((editor.cx, editor.cy), (2, 0));
+
+        editor.whichwrap = true;
+        editor.apply_action(Actions::MoveRight);
+        assert_eq!((editor.cx, editor.cy), (0, 1));
+
+        // clamped at the end of the buffer
+        editor.cx = 2;
+        editor.apply_action(Actions::MoveRight);
+        assert_eq!((editor.cx, editor.cy), (2, 1));
+    }
+
+    #[test]
+    fn test_move_left_wraps_only_with_whichwrap() {
+        let mut editor = editor_with(&["abc", "d"]);
+        editor.mode = Mode::Insert;
+        editor.cy = 1;
+        editor.apply_action(Actions::MoveLeft);
+        assert_eq!((editor.cx, editor.cy), (0, 1));
+
+        editor.whichwrap = true;
+        editor.apply_action(Actions::MoveLeft);
+        assert_eq!((editor.cx, editor.cy), (3, 0));
+
+        // clamped at the start of the buffer
+        editor.cx = 0;
+        editor.apply_action(Actions::MoveLeft);
+        assert_eq!((editor.cx, editor.cy), (0, 0));
+    }
+
+    #[test]
+    fn test_whichwrap_in_normal_mode_stays_on_a_char() {
+        let mut editor = editor_with(&["ab", "cd"]);
+        editor.apply_action(Actions::MoveRight);
+        editor.apply_action(Actions::MoveRight);
+        assert_eq!((editor.cx, editor.cy), (1, 0));
+
+        editor.whichwrap = true;
+        editor.apply_action(Actions::MoveRight);
+        assert_eq!((editor.cx, editor.cy), (0, 1));
+        editor.apply_action(Actions::MoveLeft);
+        assert_eq!((editor.cx, editor.cy), (1, 0));
+    }
+
+    #[test]
+    fn test_insert_mode_allows_cursor_past_last_char() {
+        let mut editor = editor_with(&["ab"]);
+        editor.apply_action(Actions::EnterMode(Mode::Insert));
+        editor.apply_action(Actions::MoveRight);
+        editor.apply_action(Actions::MoveRight);
+        assert_eq!(editor.cx, 2);
+
+        editor.apply_action(Actions::EnterMode(Mode::Normal));
+        assert_eq!(editor.cx, 1);
+    }
+
+    #[test]
+    fn test_vertical_moves_clamp_to_shorter_lines() {
+        let mut editor = editor_with(&["a long line", "ab", "", "another long line"]);
+        editor.cx = 8;
+        editor.apply_action(Actions::MoveDown);
+        assert_eq!((editor.cx, editor.cy), (1, 1));
+        editor.apply_action(Actions::MoveDown);
+        assert_eq!((editor.cx, editor.cy), (0, 2));
+        editor.apply_action(Actions::MoveDown);
+        assert_eq!((editor.cx, editor.cy), (0, 3));
+    }
+
+    #[test]
+    fn test_delete_last_line_clamps_cursor() {
+        let mut editor = editor_with(&["first line", "x"]);
+        editor.cy = 1;
+        editor.apply_action(Actions::DeleteLine);
+        assert_eq!((editor.cx, editor.cy), (0, 0));
+        assert_eq!(editor.buffer.lines, vec!["first line"]);
+    }
+
+    #[test]
+    fn test_cursor_reaches_lines_past_u16_range() {
+        let lines: Vec<String> = (0..70_000).map(|i| format!("line {}", i)).collect();
+        let mut editor = Editor::with_buffer(Buffer {
+            lines,
+            ..Default::default()
+        });
+        for _ in 0..69_999 {
+            editor.apply_action(Actions::MoveDown);
+        }
+        assert_eq!(editor.cy, 69_999);
+        editor.apply_action(Actions::MoveDown);
+        assert_eq!(editor.cy, 69_999);
+        assert_eq!(editor.buffer.get_line(editor.cy).unwrap(), "line 69999");
+    }
+
+    #[test]
+    fn test_status_bar_not_redrawn_when_unchanged() {
+        let mut editor = editor_with(&["hello world"]);
+        editor.status_message = Some("Saved.".to_string());
+        let mut out = Vec::new();
+        editor.draw(&mut out, 80, 24).unwrap();
+        assert!(String::from_utf8_lossy(&out).contains("Saved."));
+
+        editor.apply_action(Actions::MoveRight);
+        let mut out = Vec::new();
+        editor.draw(&mut out, 80, 24).unwrap();
+        assert!(!String::from_utf8_lossy(&out).contains("Saved."));
+    }
+
+    #[test]
+    fn test_help_screen_covers_the_text_until_closed() {
+        let mut editor = editor_with(&["hello world"]);
+        editor.keymap.bind("save", "<C-w>").unwrap();
+        run_command(&mut editor, "help");
+        let frame = editor.compose_frame(40, 6);
+        assert!(frame.row_text(0).starts_with("vix - version"));
+        assert_eq!(frame.row_text(2).trim_end(), "Normal mode keys:");
+        assert_eq!(frame.row_text(3).trim_end(), "  h         move left");
+        assert!(help_lines(&editor.keymap).contains(&"  <C-w>     save".to_string()));
+        assert!(help_lines(&editor.keymap).contains(&"  Q{reg} records keys into a register until Q again".to_string()));
+
+        press(&mut editor, key(KeyCode::Char('j'), KeyModifiers::NONE));
+        press(&mut editor, key(KeyCode::Char('x'), KeyModifiers::NONE));
+        assert_eq!(editor.compose_frame(40, 6).row_text(0).trim_end(), "");
+        press(&mut editor, key(KeyCode::Char('q'), KeyModifiers::NONE));
+        assert!(!editor.quit);
+        assert_eq!(editor.help, None);
+        assert_eq!(editor.buffer.lines, vec!["hello world"]);
+        assert_eq!(editor.compose_frame(40, 6).row_text(0).trim_end(), "1 hello world");
+
+        press(&mut editor, key(KeyCode::F(1), KeyModifiers::NONE));
+        assert_eq!(editor.help, Some(0));
+        press(&mut editor, key(KeyCode::Esc, KeyModifiers::NONE));
+        assert_eq!(editor.help, None);
+    }
+
+    #[test]
+    fn test_expand_statusline() {
+        let field = |token| match token {
+            'f' => Some("notes.txt".to_string()),
+            'l' => Some("7".to_string()),
+            _ => None,
+        };
+        assert_eq!(expand_statusline("%f:%l", field), ("notes.txt:7".to_string(), String::new()));
+        assert_eq!(expand_statusline("%f%=%l %%", field), ("notes.txt".to_string(), "7 %".to_string()));
+        assert_eq!(expand_statusline("%x %f %", field), ("%x notes.txt %".to_string(), String::new()));
+    }
+
+    #[test]
+    fn test_unknown_statusline_tokens() {
+        assert_eq!(unknown_statusline_tokens("%f%x%=%l %% %q"), vec!['x', 'q']);
+        assert!(unknown_statusline_tokens(DEFAULT_STATUSLINE).is_empty());
+        let editor = editor_with(&["a"]);
+        for token in STATUSLINE_TOKENS.chars() {
+            assert!(editor.statusline_field(token, 10).is_some(), "%{} is not filled in", token);
+        }
+    }
+
+    #[test]
+    fn test_statusline_template_draws_both_sides() {
+        let mut editor = editor_with(&["fn main() {}"]);
+        editor.buffer.file = Some("main.rs".to_string());
+        editor.statusline = "%f%m %y%=%l:%c".to_string();
+        editor.cx = 3;
+        editor.apply_action(Actions::DeleteChar);
+        let status = editor.compose_frame(40, 3).row_text(2);
+        assert!(status.starts_with("main.rs* [rust] "));
+        assert!(status.ends_with(" 1:4"));
+    }
+
+    #[test]
+    fn test_status_bar_redrawn_when_cursor_column_changes() {
+        let mut editor = editor_with(&["hello world"]);
+        let mut out = Vec::new();
+        editor.draw(&mut out, 80, 24).unwrap();
+        editor.apply_action(Actions::MoveRight);
+        let mut out = Vec::new();
+        editor.draw(&mut out, 80, 24).unwrap();
+        assert!(editor.last_frame.as_ref().unwrap().row_text(23).contains("Ln 1 Col 2"));
+        // only the column number changed, so only it is written, on the status row
+        let out = String::from_utf8_lossy(&out);
+        assert!(out.starts_with("\u{1b}[24;75H"));
+        assert!(out.contains("m2\u{1b}"));
+    }
+
+    #[test]
+    fn test_compose_frame_lays_out_text_and_status() {
+        let mut editor = editor_with(&["hello world", "second"]);
+        editor.apply_action(Actions::MoveRight);
+        let frame = editor.compose_frame(40, 5);
+        assert_eq!(frame.row_text(0).trim_end(), "1 hello world");
+        assert_eq!(frame.row_text(1).trim_end(), "2 second");
+        assert_eq!(frame.row_text(2).trim_end(), "");
+        let status = frame.row_text(4);
+        assert!(status.starts_with("NORMAL > [No Name] >"));
+        assert!(status.ends_with("Ln 1 Col 2  All"));
+        assert_eq!(frame.get(0, 4).unwrap().bg, Some(Color::DarkGrey));
+    }
+
+    #[test]
+    fn test_scroll_indicator() {
+        assert_eq!(scroll_indicator(0, 1, 10), "All");
+        assert_eq!(scroll_indicator(0, 10, 10), "All");
+        assert_eq!(scroll_indicator(0, 30, 10), "Top");
+        assert_eq!(scroll_indicator(5, 30, 10), "25%");
+        assert_eq!(scroll_indicator(20, 30, 10), "Bot");
+        assert_eq!(scroll_indicator(0, 5, 0), "Top");
+    }
+
+    #[test]
+    fn test_scroll_to_line_with_shrinking_heights() {
+        assert_eq!(scroll_to_line(0, 15, 20), 0);
+        assert_eq!(scroll_to_line(0, 15, 10), 6);
+        assert_eq!(scroll_to_line(6, 15, 5), 11);
+        assert_eq!(scroll_to_line(11, 15, 1), 15);
+        assert_eq!(scroll_to_line(11, 15, 0), 15);
+        assert_eq!(scroll_to_line(11, 3, 5), 3);
+    }
+
+    #[test]
+    fn test_resize_keeps_cursor_visible_and_redraws_everything() {
+        let lines: Vec<String> = (1..=30).map(|n| n.to_string()).collect();
+        let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+        let mut editor = editor_with(&lines);
+        editor.cy = 20;
+        editor.draw(&mut Vec::new(), 60, 25).unwrap();
+        assert_eq!(editor.row_offset, 0);
+
+        editor.force_redraw = true;
+        let mut out = Vec::new();
+        editor.draw(&mut out, 40, 8).unwrap();
+        assert_eq!(editor.row_offset, 14);
+        let frame = editor.last_frame.clone().unwrap();
+        assert_eq!(frame.row_text(6).trim_end(), "21 21");
+        assert_eq!(frame.row_text(7).len(), 40);
+        assert!(frame.row_text(7).ends_with("Ln 21 Col 1  60%"));
+        assert!(String::from_utf8_lossy(&out).contains("\u{1b}[2J"));
+    }
+
+    #[test]
+    fn test_status_shows_bot_when_scrolled_to_the_end() {
+        let lines: Vec<String> = (1..=20).map(|n| n.to_string()).collect();
+        let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+        let mut editor = editor_with(&lines);
+        press_keys(&mut editor, "G");
+        let frame = editor.compose_frame(40, 6);
+        assert!(frame.row_text(5).ends_with("Ln 20 Col 1  Bot"));
+    }
+
+    #[test]
+    fn test_goto_line_clamps_and_centers() {
+        let lines: Vec<String> = (1..=50).map(|n| format!("  line {}", n)).collect();
+        let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+        let mut editor = editor_with(&lines);
+        editor.goto_line(30);
+        assert_eq!((editor.cy, editor.cx), (29, 2));
+        editor.compose_frame(40, 11);
+        assert_eq!(editor.row_offset, 24);
+
+        editor.goto_line(500);
+        assert_eq!(editor.cy, 49);
+        editor.compose_frame(40, 11);
+        assert_eq!(editor.row_offset, 44);
+    }
+
+    #[test]
+    fn test_moving_cursor_emits_only_changed_cells() {
+        let mut editor = editor_with(&["first line", "second line"]);
+        let mut out = Vec::new();
+        editor.draw(&mut out, 40, 5).unwrap();
+        let before = editor.last_frame.clone().unwrap();
+
+        editor.apply_action(Actions::MoveDown);
+        let after = editor.compose_frame(40, 5);
+        let changes = after.diff(Some(&before));
+        assert!(!changes.is_empty());
+        assert!(changes.iter().all(|&(_, y, _)| y == 4));
+
+        let mut out = Vec::new();
+        editor.draw(&mut out, 40, 5).unwrap();
+        let written = String::from_utf8_lossy(&out);
+        assert!(!written.contains("first line"));
+        assert!(!written.contains("second line"));
+    }
+
+    #[test]
+    fn test_typing_a_char_redraws_far_less_than_a_full_frame() {
+        let lines: Vec<String> = (0..22).map(|n| format!("line {} of some text", n)).collect();
+        let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+        let mut editor = editor_with(&lines);
+        let mut full = Vec::new();
+        editor.draw(&mut full, 80, 24).unwrap();
+
+        editor.apply_action(Actions::EnterMode(Mode::Insert));
+        editor.draw(&mut Vec::new(), 80, 24).unwrap();
+        editor.apply_action(Actions::PrintChar('x'));
+        let mut typed = Vec::new();
+        editor.draw(&mut typed, 80, 24).unwrap();
+        assert!(typed.len() * 10 < full.len(), "{} vs {} bytes", typed.len(), full.len());
+
+        editor.force_redraw = true;
+        let mut forced = Vec::new();
+        editor.draw(&mut forced, 80, 24).unwrap();
+        assert!(forced.len() >= full.len());
+        assert!(!editor.force_redraw);
+    }
+
+    fn editor_for_file(dir: &tempfile::TempDir, name: &str, content: &str) -> Editor {
+        let path = dir.path().join(name);
+        std::fs::write(&path, content).unwrap();
+        Editor::with_buffer(Buffer::from_file(Some(path.to_string_lossy().into_owned())).unwrap())
+    }
+
+    #[test]
+    fn test_recovery_prompt_restores_on_yes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.txt");
+        std::fs::write(&path, "saved").unwrap();
+        std::fs::write(dir.path().join("notes.txt.recovery"), "unsaved").unwrap();
+        let an_hour_ago = std::time::SystemTime::now() - std::time::Duration::from_secs(3600);
+        std::fs::File::options().write(true).open(&path).unwrap().set_modified(an_hour_ago).unwrap();
+
+        let mut editor = Editor::with_buffer(Buffer::from_file(Some(path.to_string_lossy().into_owned())).unwrap());
+        assert_eq!(editor.status_message.as_deref(), Some("Recovery file found — restore? (y/n)"));
+        press(&mut editor, key(KeyCode::Char('j'), KeyModifiers::NONE));
+        assert!(editor.buffer.recovery.is_some());
+        press(&mut editor, key(KeyCode::Char('y'), KeyModifiers::NONE));
+        assert_eq!(editor.buffer.lines, vec!["unsaved"]);
+        assert!(editor.buffer.has_unsaved_changes());
+
+        editor.apply_action(Actions::Undo);
+        assert_eq!(editor.buffer.lines, vec!["saved"]);
+    }
+
+    #[test]
+    fn test_save_as_prompts_for_a_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.txt").to_string_lossy().into_owned();
+        let mut editor = editor_with(&["hello"]);
+        press(&mut editor, key(KeyCode::Char('S'), KeyModifiers::CONTROL));
+        assert_eq!(editor.mode, Mode::Command);
+        for c in path.chars() {
+            press(&mut editor, key(KeyCode::Char(c), KeyModifiers::NONE));
+        }
+        let frame = editor.compose_frame(200, 3);
+        assert!(frame.row_text(2).starts_with(&format!("Save as: {}", path)));
+        press(&mut editor, key(KeyCode::Enter, KeyModifiers::NONE));
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "hello");
+        assert_eq!(editor.status_message, Some(format!("\"{}\" [New] 1L, 5B written", path)));
+        assert!(!editor.save_as_prompt);
+        assert_eq!(editor.mode, Mode::Normal);
+    }
+
+    #[test]
+    fn test_escape_cancels_save_as_prompt() {
+        let mut editor = editor_with(&["hello"]);
+        press(&mut editor, key(KeyCode::Char('S'), KeyModifiers::CONTROL));
+        press(&mut editor, key(KeyCode::Char('w'), KeyModifiers::NONE));
+        press(&mut editor, key(KeyCode::Esc, KeyModifiers::NONE));
+        assert!(!editor.save_as_prompt);
+        assert!(editor.buffer.file.is_none());
+
+        press(&mut editor, key(KeyCode::Char(':'), KeyModifiers::NONE));
+        let frame = editor.compose_frame(40, 3);
+        assert!(frame.row_text(2).starts_with(": "));
+    }
+
+    #[test]
+    fn test_format_on_save_reloads_formatted_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut editor = editor_for_file(&dir, "code.rs", "fn  main( ){}\nlet x=1;");
+        editor.format_commands.insert(
+            "rs".to_string(),
+            vec!["sh", "-c", "printf 'fn main() {}\\n' > \"$1\"", "sh"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+        );
+        editor.cy = 1;
+        editor.cx = 5;
+        editor.apply_action(Actions::Save);
+
+        assert_eq!(editor.buffer.lines, vec!["fn main() {}"]);
+        assert!(!editor.buffer.modified);
+        assert_eq!((editor.cx, editor.cy), (5, 0));
+        let name = dir.path().join("code.rs").display().to_string();
+        assert_eq!(editor.status_message, Some(format!("\"{}\" 2L, 22B written, formatted", name)));
+    }
+
+    #[test]
+    fn test_autosave_after_a_formatted_save_does_not_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut editor = editor_for_file(&dir, "code.rs", "one\n");
+        let upcase = "tr a-z A-Z < \"$1\" > \"$1.tmp\" && mv \"$1.tmp\" \"$1\"";
+        editor.format_commands.insert(
+            "rs".to_string(),
+            vec!["sh", "-c", upcase, "sh"].into_iter().map(String::from).collect(),
+        );
+        run_command(&mut editor, "w");
+        assert_eq!(editor.buffer.lines, vec!["ONE"]);
+
+        editor.autosave_interval = Some(Duration::from_secs(30));
+        let start = Instant::now();
+        editor.tick(start);
+        press_keys(&mut editor, "ox");
+        press(&mut editor, key(KeyCode::Esc, KeyModifiers::NONE));
+        assert!(editor.tick(start + Duration::from_secs(30)));
+        assert_eq!(editor.buffer.lines, vec!["ONE", "x"]);
+        assert_eq!(std::fs::read_to_string(dir.path().join("code.rs")).unwrap(), "ONE\nx\n");
+        assert!(!editor.buffer.modified);
+        assert!(editor.format_result.borrow().is_none());
+    }
+
+    #[test]
+    fn test_format_note_follows_the_trimmed_note() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut editor = editor_for_file(&dir, "code.rs", "x  \ny ");
+        editor.trim_trailing_whitespace = true;
+        editor.format_commands.insert("rs".to_string(), vec!["true".to_string()]);
+        editor.apply_action(Actions::Save);
+        let name = dir.path().join("code.rs").display().to_string();
+        assert_eq!(
+            editor.status_message,
+            Some(format!("\"{}\" 2L, 3B written, trimmed trailing whitespace on 2 lines, formatted", name))
+        );
+    }
+
+    #[test]
+    fn test_trim_trailing_whitespace_on_save() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut editor = editor_for_file(&dir, "notes.txt", "keep\nend  \n\t\n");
+        (editor.cy, editor.cx) = (1, 4);
+        editor.apply_action(Actions::Save);
+        assert_eq!(std::fs::read_to_string(dir.path().join("notes.txt")).unwrap(), "keep\nend  \n\t\n");
+
+        editor.trim_trailing_whitespace = true;
+        editor.apply_action(Actions::Save);
+        assert_eq!(std::fs::read_to_string(dir.path().join("notes.txt")).unwrap(), "keep\nend\n\n");
+        let name = dir.path().join("notes.txt").display().to_string();
+        assert_eq!(
+            editor.status_message,
+            Some(format!("\"{}\" 3L, 10B written, trimmed trailing whitespace on 2 lines", name))
+        );
+        assert_eq!((editor.cy, editor.cx), (1, 2));
+
+        editor.apply_action(Actions::Save);
+        assert_eq!(editor.status_message, Some(format!("\"{}\" 3L, 10B written", name)));
+        editor.apply_action(Actions::Undo);
+        assert_eq!(editor.buffer.lines, vec!["keep", "end  ", "\t"]);
+    }
+
+    #[test]
+    fn test_failing_formatter_keeps_saved_content() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut editor = editor_for_file(&dir, "code.rs", "original");
+        editor.format_commands.insert("rs".to_string(), vec!["false".to_string()]);
+        editor.apply_action(Actions::EnterMode(Mode::Insert));
+        editor.apply_action(Actions::PrintChar('x'));
+        editor.apply_action(Actions::Save);
+
+        assert_eq!(editor.buffer.lines, vec!["xoriginal"]);
+        let saved = std::fs::read_to_string(dir.path().join("code.rs")).unwrap();
+        assert_eq!(saved, "xoriginal");
+        assert!(editor.status_message.unwrap().starts_with("Saved, but formatter failed"));
+    }
+
+    #[test]
+    fn test_formatter_only_runs_for_its_extension() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut editor = editor_for_file(&dir, "notes.txt", "keep  me");
+        editor.format_commands.insert("rs".to_string(), vec!["false".to_string()]);
+        editor.apply_action(Actions::Save);
+        assert!(editor.status_message.unwrap().ends_with("notes.txt\" 1L, 8B written"));
+    }
+
+    #[test]
+    fn test_run_commands_edits_and_writes_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut editor = editor_for_file(&dir, "batch.txt", "foo foo\nfood");
+        editor.run_commands(":%s/foo/bar/g | :w | q").unwrap();
+        assert!(editor.quit);
+        let written = std::fs::read_to_string(dir.path().join("batch.txt")).unwrap();
+        assert_eq!(written, "bar bar\nbard");
+    }
+
+    #[test]
+    fn test_split_script_keeps_bars_in_substitute_and_escaped() {
+        assert_eq!(split_script(":s/a|b/c|d/g|w"), vec!["s/a|b/c|d/g", "w"]);
+        assert_eq!(split_script("%s|a|b| | w"), vec!["%s|a|b|", "w"]);
+        assert_eq!(split_script("r !echo a\\|b | q"), vec!["r !echo a|b", "q"]);
+        assert_eq!(split_script(" | :stripws |"), vec!["stripws"]);
+    }
+
+    #[test]
+    fn test_run_commands_stops_at_unknown_command() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut editor = editor_for_file(&dir, "batch.txt", "one  ");
+        let err = editor.run_commands("stripws | nope | w").unwrap_err();
+        assert_eq!(err, "Not an editor command: nope");
+        let written = std::fs::read_to_string(dir.path().join("batch.txt")).unwrap();
+        assert_eq!(written, "one  ");
+    }
+
+    fn type_text(editor: &mut Editor, text: &str) {
+        for c in text.chars() {
+            match c {
+                '\n' => editor.apply_action(Actions::NewLine),
+                c => editor.apply_action(Actions::PrintChar(c)),
+            }
+        }
+    }
+
+    #[test]
+    fn test_undo_removes_whole_insert_session() {
+        let mut editor = editor_with(&["start"]);
+        editor.apply_action(Actions::EnterMode(Mode::Insert));
+        type_text(&mut editor, "hello ");
+        editor.apply_action(Actions::EnterMode(Mode::Normal));
+        assert_eq!(editor.buffer.lines, vec!["hello start"]);
+
+        editor.apply_action(Actions::Undo);
+        assert_eq!(editor.buffer.lines, vec!["start"]);
+        assert_eq!((editor.cx, editor.cy), (0, 0));
+
+        editor.apply_action(Actions::Redo);
+        assert_eq!(editor.buffer.lines, vec!["hello start"]);
+        assert_eq!((editor.cx, editor.cy), (6, 0));
+    }
+
+    #[test]
+    fn test_undo_restores_deleted_line_and_cursor() {
+        let mut editor = editor_with(&["one", "two", "three"]);
+        editor.apply_action(Actions::MoveDown);
+        editor.apply_action(Actions::MoveRight);
+        editor.apply_action(Actions::DeleteLine);
+        editor.apply_action(Actions::EnterMode(Mode::Insert));
+        type_text(&mut editor, "x\ny");
+        editor.apply_action(Actions::EnterMode(Mode::Normal));
+        assert_eq!(editor.buffer.lines, vec!["one", "tx", "yhree"]);
+
+        editor.apply_action(Actions::Undo);
+        assert_eq!(editor.buffer.lines, vec!["one", "three"]);
+        editor.apply_action(Actions::Undo);
+        assert_eq!(editor.buffer.lines, vec!["one", "two", "three"]);
+        assert_eq!((editor.cx, editor.cy), (1, 1));
+    }
+
+    #[test]
+    fn test_undo_past_oldest_change_is_noop() {
+        let mut editor = editor_with(&["text"]);
+        editor.apply_action(Actions::Undo);
+        assert_eq!(editor.buffer.lines, vec!["text"]);
+        assert_eq!(editor.status_message.as_deref(), Some("Already at oldest change"));
+        editor.apply_action(Actions::Redo);
+        assert_eq!(editor.status_message.as_deref(), Some("Already at newest change"));
+    }
+
+    #[test]
+    fn test_undo_back_to_the_saved_text_clears_modified() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut editor = editor_for_file(&dir, "notes.txt", "abc");
+        press_keys(&mut editor, "x");
+        assert!(editor.buffer.modified);
+        press_keys(&mut editor, "u");
+        assert!(!editor.buffer.modified);
+        editor.apply_action(Actions::Redo);
+        assert!(editor.buffer.modified);
+
+        run_command(&mut editor, "w");
+        press_keys(&mut editor, "x");
+        press_keys(&mut editor, "u");
+        assert_eq!(editor.buffer.lines, vec!["bc"]);
+        assert!(!editor.buffer.modified);
+        press_keys(&mut editor, "u");
+        assert_eq!(editor.buffer.lines, vec!["abc"]);
+        assert!(editor.buffer.modified);
+    }
+
+    #[test]
+    fn test_undo_reverts_a_formatter_run_on_save() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut editor = editor_for_file(&dir, "code.rs", "fn  main( ){}");
+        editor.format_commands.insert(
+            "rs".to_string(),
+            vec!["sh", "-c", "printf 'fn main() {}\\n' > \"$1\"", "sh"].into_iter().map(String::from).collect(),
+        );
+        editor.apply_action(Actions::Save);
+        assert_eq!(editor.buffer.lines, vec!["fn main() {}"]);
+        assert!(!editor.buffer.modified);
+
+        editor.apply_action(Actions::Undo);
+        assert_eq!(editor.buffer.lines, vec!["fn  main( ){}"]);
+        assert!(editor.buffer.modified);
+    }
+
+    #[test]
+    fn test_x_deletes_char_and_clamps_at_line_end() {
+        let mut editor = editor_with(&["abc", ""]);
+        editor.cx = 1;
+        editor.apply_action(Actions::DeleteChar);
+        assert_eq!(editor.buffer.lines[0], "ac");
+        assert_eq!(editor.cx, 1);
+
+        editor.apply_action(Actions::DeleteChar);
+        assert_eq!(editor.buffer.lines[0], "a");
+        assert_eq!(editor.cx, 0);
+
+        editor.apply_action(Actions::MoveDown);
+        editor.apply_action(Actions::DeleteChar);
+        assert_eq!(editor.buffer.lines, vec!["a", ""]);
+        assert_eq!(editor.status_message, None);
+    }
+
+    #[test]
+    fn test_quit_refused_with_unsaved_changes() {
+        let mut editor = editor_with(&["text"]);
+        editor.apply_action(Actions::DeleteChar);
+        run_command(&mut editor, "q");
+        assert!(!editor.quit);
+        assert_eq!(
+            editor.status_message.as_deref(),
+            Some("No write since last change (add ! to override)")
+        );
+
+        run_command(&mut editor, "q!");
+        assert!(editor.quit);
+    }
+
+    #[test]
+    fn test_quit_allowed_after_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut editor = editor_for_file(&dir, "file.txt", "text");
+        editor.apply_action(Actions::DeleteChar);
+        run_command(&mut editor, "w");
+        run_command(&mut editor, "q");
+        assert!(editor.quit);
+    }
+
+    #[test]
+    fn test_wq_writes_then_quits() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut editor = editor_for_file(&dir, "file.txt", "text");
+        editor.apply_action(Actions::DeleteChar);
+        run_command(&mut editor, "wq");
+        assert!(editor.quit);
+        assert_eq!(std::fs::read_to_string(dir.path().join("file.txt")).unwrap(), "ext");
+    }
+
+    #[test]
+    fn test_wq_without_file_name_does_not_quit() {
+        let mut editor = editor_with(&["text"]);
+        editor.apply_action(Actions::DeleteChar);
+        run_command(&mut editor, "wq");
+        assert!(!editor.quit);
+    }
+
+    #[test]
+    fn test_q_key_returns_quit_action() {
+        let mut editor = editor_with(&["text"]);
+        press(&mut editor, key(KeyCode::Char('q'), KeyModifiers::NONE));
+        assert!(editor.quit);
+    }
+
+    #[test]
+    fn test_command_line_is_echoed_on_status_bar() {
+        let mut editor = editor_with(&["text"]);
+        editor.apply_action(Actions::EnterMode(Mode::Command));
+        editor.apply_action(Actions::CommandChar('w'));
+        editor.apply_action(Actions::CommandChar('q'));
+        let frame = editor.compose_frame(40, 5);
+        assert!(frame.row_text(4).starts_with(":wq "));
+    }
+
+    #[test]
+    fn test_tab_cycles_through_command_completions() {
+        let mut editor = editor_with(&["text"]);
+        editor.apply_action(Actions::EnterMode(Mode::Command));
+        editor.apply_action(Actions::CommandChar('b'));
+        let tab = || key(KeyCode::Tab, KeyModifiers::NONE);
+        press(&mut editor, tab());
+        assert_eq!(editor.command_line, "bn");
+        let frame = editor.compose_frame(40, 5);
+        assert!(frame.row_text(4).starts_with(":bn "));
+        assert!(frame.row_text(4).contains("match 1 of 5"));
+
+        press(&mut editor, tab());
+        assert_eq!(editor.command_line, "bnext");
+        for _ in 0..5 {
+            press(&mut editor, tab());
+        }
+        assert_eq!(editor.command_line, "bnext");
+
+        editor.apply_action(Actions::CommandBackspace);
+        editor.apply_action(Actions::CommandChar('t'));
+        press(&mut editor, tab());
+        assert_eq!(editor.command_line, "bnext");
+        assert_eq!(editor.completion_status(), Some("match 1 of 1".to_string()));
+        press(&mut editor, key(KeyCode::Esc, KeyModifiers::NONE));
+        assert_eq!(editor.completion_status(), None);
+    }
+
+    #[test]
+    fn test_edit_opens_a_file_in_its_own_buffer() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = dir.path().join("first.txt");
+        let second = dir.path().join("second.txt");
+        std::fs::write(&first, "one\n").unwrap();
+        std::fs::write(&second, "two\nlines\n").unwrap();
+        let mut editor = Editor::with_buffer(Buffer::from_file(Some(first.display().to_string())).unwrap());
+
+        press_keys(&mut editor, "x");
+        run_command(&mut editor, &format!("e {}", second.display()));
+        assert_eq!(editor.buffer.lines, vec!["two", "lines"]);
+        assert!(!editor.buffer.modified);
+        editor.apply_action(Actions::Undo);
+        assert_eq!(editor.buffer.lines, vec!["two", "lines"]);
+
+        run_command(&mut editor, &format!("e {}", first.display()));
+        assert_eq!(editor.buffer.lines, vec!["ne"]);
+        assert_eq!(editor.buffers.len(), 2);
+        run_command(&mut editor, "e");
+        assert_eq!(
+            editor.status_message.as_deref(),
+            Some("No write since last change (add ! to override)")
+        );
+        run_command(&mut editor, "e!");
+        assert_eq!(editor.buffer.lines, vec!["one"]);
+        assert_eq!(editor.buffers.len(), 2);
+    }
+
+    #[test]
+    fn test_edit_keeps_indent_options_the_user_set() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.json").display().to_string();
+        let mut editor = editor_with(&["text"]);
+        run_command(&mut editor, "set ts=8");
+        run_command(&mut editor, &format!("e {}", path));
+        assert_eq!(editor.tab_width, 8);
+        assert!(editor.expand_tab);
+    }
+
+    #[test]
+    fn test_write_asks_before_overwriting_a_changed_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("shared.txt");
+        std::fs::write(&path, "one\n").unwrap();
+        let mut editor = Editor::with_buffer(Buffer::from_file(Some(path.display().to_string())).unwrap());
+        run_command(&mut editor, "checktime");
+        assert_eq!(editor.status_message.as_deref(), Some("File unchanged on disk"));
+
+        std::fs::write(&path, "theirs\n").unwrap();
+        let touched = editor.buffer.mtime.unwrap() + Duration::from_secs(5);
+        std::fs::File::options().write(true).open(&path).unwrap().set_modified(touched).unwrap();
+        run_command(&mut editor, "checktime");
+        assert_eq!(editor.status_message.as_deref(), Some("File changed on disk — reload with :e!"));
+
+        editor.buffer.insert_char(0, 0, '!').unwrap();
+        run_command(&mut editor, "w");
+        assert_eq!(editor.status_message.as_deref(), Some("File changed on disk — overwrite? (:w! to force)"));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "theirs\n");
+        run_command(&mut editor, "w!");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "!one\n");
+
+        std::fs::remove_file(&path).unwrap();
+        run_command(&mut editor, "checktime");
+        assert_eq!(editor.status_message.as_deref(), Some("File deleted on disk"));
+    }
+
+    #[test]
+    fn test_edit_invalid_utf8_file_opens_read_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("latin1.txt").display().to_string();
+        std::fs::write(&path, b"caf\xe9\n").unwrap();
+        let mut editor = editor_with(&["text"]);
+        run_command(&mut editor, &format!("e {}", path));
+        assert_eq!(editor.buffer.lines, vec!["caf\u{fffd}"]);
+        assert!(editor.read_only);
+        assert_eq!(
+            editor.status_message,
+            Some(format!("\"{}\" 1 lines [invalid UTF-8, read-only]", path))
+        );
+    }
+
+    #[test]
+    fn test_invalid_utf8_in_a_second_buffer_is_not_saved() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("latin1.txt").display().to_string();
+        std::fs::write(&path, b"caf\xe9\n").unwrap();
+        let mut editor = editor_with(&["text"]);
+        editor.add_buffer(Buffer::from_file(Some(path.clone())).unwrap());
+        run_command(&mut editor, "bn");
+        run_command(&mut editor, "set noro");
+        run_command(&mut editor, "w");
+        assert_eq!(
+            editor.status_message.as_deref(),
+            Some("File is not valid UTF-8 — write replaced chars? (:w! to force)")
+        );
+        assert_eq!(std::fs::read(&path).unwrap(), b"caf\xe9\n");
+    }
+
+    #[test]
+    fn test_read_only_belongs_to_each_buffer() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("latin1.txt").display().to_string();
+        std::fs::write(&path, b"caf\xe9\n").unwrap();
+        let mut editor = Editor::with_buffer(named_buffer("a.txt", &["alpha"]));
+        run_command(&mut editor, &format!("e {}", path));
+        assert!(editor.read_only);
+        run_command(&mut editor, "bp");
+        assert!(!editor.read_only);
+
+        editor.add_buffer(named_buffer("c.txt", &["x"]));
+        run_command(&mut editor, "set ro");
+        run_command(&mut editor, "bn");
+        assert!(editor.read_only);
+        run_command(&mut editor, "bn");
+        assert_eq!(editor.buffer.display_name(), "c.txt");
+        assert!(!editor.read_only);
+        run_command(&mut editor, "bn");
+        assert!(editor.read_only);
+    }
+
+    #[test]
+    fn test_edit_missing_file_is_created_on_save() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("new.txt").display().to_string();
+        let mut editor = editor_with(&["scratch"]);
+        run_command(&mut editor, &format!("edit {}", path));
+        assert_eq!(editor.buffer.lines, vec![""]);
+        assert_eq!(editor.status_message, Some(format!("\"{}\" [New]", path)));
+
+        editor.buffer.insert_char(0, 0, 'x').unwrap();
+        run_command(&mut editor, "w");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "x");
+
+        let mut unnamed = editor_with(&["text"]);
+        run_command(&mut unnamed, "e");
+        assert_eq!(unnamed.status_message.as_deref(), Some("No file name"));
+        assert_eq!(parse_command("e ~/x"), Some(Actions::EditFile(Some("~/x".to_string()), false)));
+    }
+
+    #[test]
+    fn test_write_to_another_path_keeps_the_buffer_name() {
+        let dir = tempfile::tempdir().unwrap();
+        let original = dir.path().join("notes.txt").display().to_string();
+        let other = dir.path().join("other.txt").display().to_string();
+        std::fs::write(&original, "old").unwrap();
+        let mut editor = Editor::with_buffer(Buffer::from_file(Some(original.clone())).unwrap());
+        editor.buffer.insert_char(0, 0, '!').unwrap();
+
+        run_command(&mut editor, &format!("w {}", other));
+        assert_eq!(std::fs::read_to_string(&other).unwrap(), "!old");
+        assert_eq!(editor.buffer.file.as_deref(), Some(original.as_str()));
+        assert!(editor.buffer.modified);
+        assert_eq!(editor.status_message, Some(format!("\"{}\" [New] 1L, 4B written", other)));
+        assert_eq!(std::fs::read_to_string(&original).unwrap(), "old");
+
+        run_command(&mut editor, &format!("w {}", original));
+        assert_eq!(std::fs::read_to_string(&original).unwrap(), "!old");
+        assert!(!editor.buffer.modified);
+    }
+
+    #[test]
+    fn test_read_file_below_the_cursor() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("part.txt").display().to_string();
+        std::fs::write(&path, "  one\ntwo\n").unwrap();
+        let mut editor = editor_with(&["first", "last"]);
+
+        run_command(&mut editor, &format!("r {}", path));
+        assert_eq!(editor.buffer.lines, vec!["first", "  one", "two", "last"]);
+        assert_eq!((editor.cy, editor.cx), (1, 2));
+        assert!(editor.buffer.modified);
+        assert_eq!(editor.status_message, Some(format!("\"{}\" 2L, 10B", path)));
+
+        press_keys(&mut editor, "u");
+        assert_eq!(editor.buffer.lines, vec!["first", "last"]);
+    }
+
+    #[test]
+    fn test_read_missing_file_reports_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("missing.txt").display().to_string();
+        let mut editor = editor_with(&["first"]);
+        run_command(&mut editor, &format!("read {}", path));
+        assert_eq!(editor.buffer.lines, vec!["first"]);
+        assert!(!editor.buffer.modified);
+        assert!(editor.status_message.as_deref().unwrap().starts_with(&format!("Can't open file {}", path)));
+
+        run_command(&mut editor, "r");
+        assert_eq!(editor.status_message.as_deref(), Some("No file name"));
+    }
+
+    #[test]
+    fn test_read_command_output_needs_allow_shell() {
+        let mut editor = editor_with(&["first"]);
+        run_command(&mut editor, "r !echo hi");
+        assert_eq!(editor.buffer.lines, vec!["first"]);
+        assert_eq!(
+            editor.status_message.as_deref(),
+            Some("Shell commands are off; set allow_shell in config.toml")
+        );
+
+        editor.allow_shell = true;
+        run_command(&mut editor, "r !printf 'a\\nb\\n'");
+        assert_eq!(editor.buffer.lines, vec!["first", "a", "b"]);
+        run_command(&mut editor, "r !exit 3");
+        assert_eq!(editor.buffer.lines, vec!["first", "a", "b"]);
+        assert!(editor.status_message.unwrap().starts_with("exit 3 failed"));
+    }
+
+    #[test]
+    fn test_find_char_motions_and_repeat() {
+        let mut editor = editor_with(&["fn call(a, b, c);"]);
+        press_keys(&mut editor, "f(");
+        assert_eq!(editor.cx, 7);
+        press_keys(&mut editor, "t,");
+        assert_eq!(editor.cx, 8);
+        press_keys(&mut editor, ";");
+        assert_eq!(editor.cx, 11);
+        press_keys(&mut editor, ",");
+        assert_eq!(editor.cx, 10);
+        press_keys(&mut editor, "fz");
+        assert_eq!(editor.cx, 10);
+        press_keys(&mut editor, "F ");
+        assert_eq!(editor.cx, 2);
+        press_keys(&mut editor, "2f,");
+        assert_eq!(editor.cx, 12);
+        press_keys(&mut editor, "Tl");
+        assert_eq!(editor.cx, 7);
+        assert_eq!(editor.last_find, Some(CharFind { target: 'l', forward: false, till: true }));
+
+        press_keys(&mut editor, "f");
+        assert_eq!(editor.pending_indicator(), "f");
+        press(&mut editor, key(KeyCode::Esc, KeyModifiers::NONE));
+        assert_eq!(editor.pending_indicator(), "");
+        assert_eq!(editor.cx, 7);
+    }
+
+    #[test]
+    fn test_find_char_extends_visual_selection() {
+        let mut editor = editor_with(&["one two three"]);
+        press_keys(&mut editor, "vt d");
+        assert_eq!(editor.buffer.lines, vec![" two three"]);
+    }
+
+    #[test]
+    fn test_delete_to_found_char() {
+        let mut editor = editor_with(&["call(a, b), rest"]);
+        editor.cx = 5;
+        press_keys(&mut editor, "df)");
+        assert_eq!(editor.buffer.lines, vec!["call(, rest"]);
+        assert_eq!(editor.register, vec!["a, b)"]);
+        assert!(!editor.register_linewise);
+
+        editor.cx = 0;
+        press_keys(&mut editor, "dt,");
+        assert_eq!(editor.buffer.lines, vec![", rest"]);
+        assert_eq!(editor.cx, 0);
+
+        press_keys(&mut editor, "$dF ");
+        assert_eq!(editor.buffer.lines, vec![",t"]);
+
+        press_keys(&mut editor, "0dfz");
+        assert_eq!(editor.buffer.lines, vec![",t"]);
+        assert_eq!(editor.register, vec![" res"]);
+        assert_eq!(editor.pending_indicator(), "");
+    }
+
+    #[test]
+    fn test_pending_indicator_shows_operators() {
+        let mut editor = editor_with(&["one two"]);
+        press_keys(&mut editor, "d");
+        assert_eq!(editor.pending_indicator(), "d");
+        press_keys(&mut editor, "i");
+        assert_eq!(editor.pending_indicator(), "di");
+        press(&mut editor, key(KeyCode::Esc, KeyModifiers::NONE));
+        press_keys(&mut editor, "2df");
+        assert_eq!(editor.pending_indicator(), "2df");
+        press(&mut editor, key(KeyCode::Esc, KeyModifiers::NONE));
+        assert_eq!(editor.pending_indicator(), "");
+        assert_eq!(editor.buffer.lines, vec!["one two"]);
+    }
+
+    #[test]
+    fn test_change_till_found_char_with_count() {
+        let mut editor = editor_with(&["a, b, c"]);
+        press_keys(&mut editor, "2ct,x");
+        assert_eq!(editor.buffer.lines, vec!["x, c"]);
+        assert_eq!(editor.mode, Mode::Insert);
+
+        press(&mut editor, key(KeyCode::Esc, KeyModifiers::NONE));
+        press_keys(&mut editor, "cfz");
+        assert_eq!(editor.mode, Mode::Normal);
+        assert_eq!(editor.buffer.lines, vec!["x, c"]);
+    }
+
+    #[test]
+    fn test_jump_list_goes_back_and_forward() {
+        let lines: Vec<String> = (0..20).map(|n| format!("line {}", n)).collect();
+        let mut editor = editor_with(&lines.iter().map(String::as_str).collect::<Vec<_>>());
+        let ctrl_o = || key(KeyCode::Char('o'), KeyModifiers::CONTROL);
+        let tab = || key(KeyCode::Tab, KeyModifiers::NONE);
+        editor.cx = 3;
+        press_keys(&mut editor, "Gkkjj");
+        run_command(&mut editor, "5");
+        press_keys(&mut editor, "gg");
+        assert_eq!(editor.jumps, vec![(0, 3), (19, 3), (4, 0)]);
+
+        press(&mut editor, ctrl_o());
+        assert_eq!((editor.cy, editor.cx), (4, 0));
+        // leaving line 0 again replaced its older entry
+        assert_eq!(editor.jumps, vec![(19, 3), (4, 0), (0, 0)]);
+        press(&mut editor, ctrl_o());
+        assert_eq!((editor.cy, editor.cx), (19, 3));
+        press(&mut editor, ctrl_o());
+        assert_eq!((editor.cy, editor.cx), (19, 3));
+
+        press(&mut editor, tab());
+        assert_eq!(editor.cy, 4);
+        press(&mut editor, tab());
+        press(&mut editor, tab());
+        assert_eq!(editor.cy, 0);
+
+        press_keys(&mut editor, "2");
+        press(&mut editor, ctrl_o());
+        assert_eq!(editor.cy, 19);
+    }
+
+    #[test]
+    fn test_jump_list_clamps_after_deleting_lines() {
+        let mut editor = editor_with(&["a", "b", "c", "long line"]);
+        press_keys(&mut editor, "G$gg");
+        editor.buffer.delete_lines(1, 3).unwrap();
+        editor.buffer.insert_lines(1, &["xy".to_string()]).unwrap();
+        press(&mut editor, key(KeyCode::Char('o'), KeyModifiers::CONTROL));
+        assert_eq!((editor.cy, editor.cx), (1, 1));
+
+        for line in 0..MAX_JUMPS + 10 {
+            editor.push_jump((line, 0));
+        }
+        assert_eq!(editor.jumps.len(), MAX_JUMPS);
+        assert_eq!(editor.jumps[0], (10, 0));
+    }
+
+    #[test]
+    fn test_write_reports_lines_and_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dos.txt").display().to_string();
+        std::fs::write(&path, "one\r\ntwo\r\n").unwrap();
+        let mut editor = Editor::with_buffer(Buffer::from_file(Some(path.clone())).unwrap());
+        run_command(&mut editor, "w");
+        assert_eq!(editor.status_message, Some(format!("\"{}\" 2L, 10B written", path)));
+
+        let mut editor = editor_with(&["a", "b"]);
+        let new = dir.path().join("new.txt").display().to_string();
+        editor.apply_action(Actions::SaveAs(new.clone()));
+        assert_eq!(editor.status_message, Some(format!("\"{}\" [New] 2L, 3B written", new)));
+    }
+
+    #[test]
+    fn test_colon_from_visual_mode_applies_to_the_selection() {
+        let mut editor = editor_with(&["x1", "x2", "x3", "x4"]);
+        editor.cy = 1;
+        press_keys(&mut editor, "vj:");
+        assert_eq!(editor.mode, Mode::Command);
+        assert_eq!(editor.command_line, "'<,'>");
+        press_keys(&mut editor, "s/x/y/");
+        press(&mut editor, key(KeyCode::Enter, KeyModifiers::NONE));
+        assert_eq!(editor.buffer.lines, vec!["x1", "y2", "y3", "x4"]);
+        assert_eq!(editor.command_range, None);
+
+        assert_eq!(editor.cy, 2);
+        press_keys(&mut editor, "vk:%s/y/z/");
+        press(&mut editor, key(KeyCode::Enter, KeyModifiers::NONE));
+        assert_eq!(editor.buffer.lines, vec!["x1", "z2", "z3", "x4"]);
+
+        editor.cy = 1;
+        press_keys(&mut editor, "vj:>");
+        press(&mut editor, key(KeyCode::Enter, KeyModifiers::NONE));
+        assert_eq!(editor.buffer.lines, vec!["x1", "\tz2", "\tz3", "x4"]);
+
+        // with the range taken off the command line, it is the cursor line again
+        editor.cy = 0;
+        press_keys(&mut editor, "v:");
+        for _ in 0..5 {
+            press(&mut editor, key(KeyCode::Backspace, KeyModifiers::NONE));
+        }
+        press_keys(&mut editor, "%s/x/w/");
+        press(&mut editor, key(KeyCode::Enter, KeyModifiers::NONE));
+        assert_eq!(editor.buffer.lines, vec!["w1", "\tz2", "\tz3", "w4"]);
+    }
+
+    #[test]
+    fn test_commands_parse_by_name_and_argument() {
+        assert_eq!(parse_command(" wq "), Some(Actions::WriteQuit));
+        assert_eq!(parse_command("q now"), None);
+        assert_eq!(parse_command(">"), Some(Actions::ShiftLines(true)));
+        assert_eq!(parse_command("syntax off"), Some(Actions::SetSyntax(false)));
+        assert_eq!(parse_command("edit! notes.txt"), Some(Actions::EditFile(Some("notes.txt".to_string()), true)));
+        assert_eq!(parse_command("r !date"), Some(Actions::ReadCommand("date".to_string())));
+        assert_eq!(parse_command("r!date"), Some(Actions::ReadCommand("date".to_string())));
+        assert_eq!(parse_command("read! ls -l"), Some(Actions::ReadCommand("ls -l".to_string())));
+        assert_eq!(parse_command("12"), Some(Actions::GotoLine(12)));
+        assert_eq!(parse_command("wfoo"), None);
+    }
+
+    #[test]
+    fn test_every_completed_command_parses() {
+        for (name, argument) in command_names() {
+            let command = match argument {
+                Argument::Text if name.ends_with('s') => format!("{}/a/b/", name),
+                Argument::Text if name == "set" => "set nu".to_string(),
+                Argument::Text => "syntax on".to_string(),
+                _ => name.to_string(),
+            };
+            assert!(parse_command(&command).is_some(), "{} does not parse", command);
+        }
+    }
+
+    #[test]
+    fn test_typing_and_moving_over_multibyte_chars() {
+        let mut editor = editor_with(&[""]);
+        editor.apply_action(Actions::EnterMode(Mode::Insert));
+        type_text(&mut editor, "café");
+        assert_eq!(editor.cx, 4);
+        editor.apply_action(Actions::Backspace);
+        assert_eq!(editor.buffer.lines[0], "caf");
+        type_text(&mut editor, "é au lait");
+        editor.apply_action(Actions::EnterMode(Mode::Normal));
+
+        editor.cx = 0;
+        for _ in 0..4 {
+            editor.apply_action(Actions::MoveRight);
+        }
+        assert_eq!(editor.cx, 4);
+        editor.apply_action(Actions::MoveLeft);
+        editor.apply_action(Actions::DeleteChar);
+        assert_eq!(editor.buffer.lines[0], "caf au lait");
+
+        let frame = editor.compose_frame(20, 3);
+        assert!(frame.row_text(0).starts_with("1 caf au lait"));
+    }
+
+    #[test]
+    fn test_line_number_gutter() {
+        let lines: Vec<String> = (1..=12).map(|i| format!("line {}", i)).collect();
+        let mut editor = Editor::with_buffer(Buffer { lines, ..Default::default() });
+        editor.cy = 9;
+        editor.cx = 2;
+        let frame = editor.compose_frame(50, 13);
+        assert_eq!(frame.row_text(0).trim_end(), " 1 line 1");
+        assert_eq!(frame.row_text(11).trim_end(), "12 line 12");
+        assert_eq!(frame.get(0, 11).unwrap().fg, Some(Color::DarkGrey));
+        assert_eq!(editor.screen_cursor(&frame), (5, 9));
+        assert!(frame.row_text(12).contains("Ln 10 Col 3"));
+
+        editor.show_line_numbers = false;
+        let frame = editor.compose_frame(50, 13);
+        assert_eq!(frame.row_text(0).trim_end(), "line 1");
+        assert_eq!(editor.screen_cursor(&frame), (2, 9));
+    }
+
+    #[test]
+    fn test_word_motions_move_cursor() {
+        let mut editor = editor_with(&["let x = 1;", "next"]);
+        editor.apply_action(Actions::MoveWordForward);
+        assert_eq!((editor.cx, editor.cy), (4, 0));
+        editor.apply_action(Actions::MoveWordEnd);
+        assert_eq!((editor.cx, editor.cy), (6, 0));
+        editor.cx = 9;
+        editor.apply_action(Actions::MoveWordForward);
+        assert_eq!((editor.cx, editor.cy), (0, 1));
+        editor.apply_action(Actions::MoveWordBackward);
+        assert_eq!((editor.cx, editor.cy), (9, 0));
+    }
+
+    #[test]
+    fn test_line_jump_motions() {
+        let mut editor = editor_with(&["\t  héllo wörld"]);
+        editor.cx = 6;
+        editor.apply_action(Actions::MoveLineEnd);
+        assert_eq!(editor.cx, 13);
+        editor.apply_action(Actions::MoveFirstNonBlank);
+        assert_eq!(editor.cx, 3);
+        editor.apply_action(Actions::MoveLineStart);
+        assert_eq!(editor.cx, 0);
+
+        editor.mode = Mode::Insert;
+        editor.apply_action(Actions::MoveLineEnd);
+        assert_eq!(editor.cx, 14);
+    }
+
+    #[test]
+    fn test_gg_and_g_jump_to_first_and_last_line() {
+        let mut editor = editor_with(&["first line", "mid", "last"]);
+        editor.cy = 1;
+        editor.cx = 2;
+        press(&mut editor, key(KeyCode::Char('G'), KeyModifiers::SHIFT));
+        assert_eq!((editor.cx, editor.cy), (2, 2));
+
+        editor.cx = 3;
+        press(&mut editor, key(KeyCode::Char('g'), KeyModifiers::NONE));
+        assert!(editor.pending_g);
+        assert_eq!(editor.pending_indicator(), "g");
+        press(&mut editor, key(KeyCode::Char('g'), KeyModifiers::NONE));
+        assert!(!editor.pending_g);
+        assert_eq!((editor.cx, editor.cy), (3, 0));
+    }
+
+    #[test]
+    fn test_z_commands_scroll_the_cursor_line() {
+        let lines: Vec<String> = (1..=30).map(|n| n.to_string()).collect();
+        let mut editor = editor_with(&lines.iter().map(String::as_str).collect::<Vec<_>>());
+        editor.cy = 15;
+        editor.compose_frame(40, 11);
+
+        press_keys(&mut editor, "zt");
+        editor.compose_frame(40, 11);
+        assert_eq!(editor.row_offset, 15);
+        press_keys(&mut editor, "zz");
+        editor.compose_frame(40, 11);
+        assert_eq!(editor.row_offset, 10);
+        press_keys(&mut editor, "z");
+        assert_eq!(editor.pending_indicator(), "z");
+        press_keys(&mut editor, "b");
+        editor.compose_frame(40, 11);
+        assert_eq!(editor.row_offset, 6);
+        assert_eq!(editor.cy, 15);
+
+        editor.cy = 2;
+        press_keys(&mut editor, "zb");
+        editor.compose_frame(40, 11);
+        assert_eq!(editor.row_offset, 0);
+        press_keys(&mut editor, "zx");
+        assert!(!editor.pending_z);
+        assert_eq!(editor.cy, 2);
+    }
+
+    #[test]
+    fn test_scroll_cursor_to_clamps() {
+        assert_eq!(scroll_cursor_to(ScrollPosition::Middle, 3, 10, 30), 0);
+        assert_eq!(scroll_cursor_to(ScrollPosition::Bottom, 3, 10, 30), 0);
+        assert_eq!(scroll_cursor_to(ScrollPosition::Top, 29, 10, 30), 29);
+        assert_eq!(scroll_cursor_to(ScrollPosition::Top, 5, 10, 0), 0);
+    }
+
+    #[test]
+    fn test_percent_jumps_between_brackets() {
+        let mut editor = editor_with(&["fn main() {", "    call(a[0]);", "}"]);
+        editor.cx = 3;
+        press_keys(&mut editor, "%");
+        assert_eq!((editor.cy, editor.cx), (0, 8));
+        press_keys(&mut editor, "%");
+        assert_eq!((editor.cy, editor.cx), (0, 7));
+        editor.cx = 10;
+        press_keys(&mut editor, "%");
+        assert_eq!((editor.cy, editor.cx), (2, 0));
+        press_keys(&mut editor, "%");
+        assert_eq!((editor.cy, editor.cx), (0, 10));
+
+        let mut editor = editor_with(&["(open"]);
+        press_keys(&mut editor, "%");
+        assert_eq!((editor.cy, editor.cx), (0, 0));
+    }
+
+    #[test]
+    fn test_gj_and_gk_move_by_display_row() {
+        let mut editor = editor_with(&["abcdefghij", "xy", "0123456789"]);
+        editor.cx = 1;
+        press_keys(&mut editor, "gj");
+        assert_eq!((editor.cy, editor.cx), (1, 1));
+        press_keys(&mut editor, "2gk");
+        assert_eq!((editor.cy, editor.cx), (0, 1));
+
+        // "abcdefghij" at width 4 is drawn as "abcd", "efgh", "ij"
+        editor.move_display_line(4, true);
+        assert_eq!((editor.cy, editor.cx), (0, 5));
+        editor.move_display_line(4, true);
+        assert_eq!((editor.cy, editor.cx), (0, 9));
+        editor.move_display_line(4, true);
+        assert_eq!((editor.cy, editor.cx), (1, 1));
+        editor.move_display_line(4, false);
+        assert_eq!((editor.cy, editor.cx), (0, 9));
+        editor.cx = 7;
+        editor.move_display_line(4, false);
+        assert_eq!((editor.cy, editor.cx), (0, 3));
+        editor.move_display_line(4, false);
+        assert_eq!((editor.cy, editor.cx), (0, 3));
+
+        (editor.cy, editor.cx) = (2, 1);
+        editor.move_display_line(4, false);
+        assert_eq!((editor.cy, editor.cx), (1, 1));
+        editor.move_display_line(4, false);
+        assert_eq!((editor.cy, editor.cx), (0, 9));
+    }
+
+    #[test]
+    fn test_other_key_cancels_pending_g() {
+        let mut editor = editor_with(&["a", "b", "c"]);
+        editor.cy = 2;
+        press(&mut editor, key(KeyCode::Char('g'), KeyModifiers::NONE));
+        press(&mut editor, key(KeyCode::Char('w'), KeyModifiers::NONE));
+        assert!(!editor.pending_g);
+        press(&mut editor, key(KeyCode::Char('g'), KeyModifiers::NONE));
+        assert_eq!(editor.cy, 2);
+    }
+
+    #[test]
+    fn test_visual_delete_across_lines() {
+        let mut editor = editor_with(&["first line", "middle", "last line"]);
+        editor.cx = 6;
+        press(&mut editor, key(KeyCode::Char('v'), KeyModifiers::NONE));
+        assert_eq!(editor.mode, Mode::Visual);
+        press(&mut editor, key(KeyCode::Char('j'), KeyModifiers::NONE));
+        press(&mut editor, key(KeyCode::Char('j'), KeyModifiers::NONE));
+        editor.cx = 4;
+        press(&mut editor, key(KeyCode::Char('d'), KeyModifiers::NONE));
+
+        assert_eq!(editor.mode, Mode::Normal);
+        assert_eq!(editor.buffer.lines, vec!["first line"]);
+        assert_eq!((editor.cx, editor.cy), (6, 0));
+        assert_eq!(editor.register, vec!["line", "middle", "last "]);
+    }
+
+    #[test]
+    fn test_visual_selection_backwards_and_yank() {
+        let mut editor = editor_with(&["hello world"]);
+        editor.cx = 4;
+        press(&mut editor, key(KeyCode::Char('v'), KeyModifiers::NONE));
+        press(&mut editor, key(KeyCode::Char('0'), KeyModifiers::NONE));
+        press(&mut editor, key(KeyCode::Char('y'), KeyModifiers::NONE));
+        assert_eq!(editor.register, vec!["hello"]);
+        assert_eq!(editor.buffer.lines, vec!["hello world"]);
+        assert_eq!((editor.cx, editor.mode), (0, Mode::Normal));
+    }
+
+    #[test]
+    fn test_visual_escape_leaves_buffer_alone() {
+        let mut editor = editor_with(&["abc"]);
+        press(&mut editor, key(KeyCode::Char('v'), KeyModifiers::NONE));
+        press(&mut editor, key(KeyCode::Char('l'), KeyModifiers::NONE));
+        press(&mut editor, key(KeyCode::Char('i'), KeyModifiers::NONE));
+        assert_eq!(editor.mode, Mode::Visual);
+        press(&mut editor, key(KeyCode::Esc, KeyModifiers::NONE));
+        assert_eq!(editor.mode, Mode::Normal);
+        assert_eq!(editor.buffer.lines, vec!["abc"]);
+        assert!(!editor.buffer.has_unsaved_changes());
+    }
+
+    #[test]
+    fn test_visual_selection_is_highlighted() {
+        let mut editor = editor_with(&["abcdef"]);
+        editor.show_line_numbers = false;
+        editor.cx = 1;
+        press(&mut editor, key(KeyCode::Char('v'), KeyModifiers::NONE));
+        press(&mut editor, key(KeyCode::Char('l'), KeyModifiers::NONE));
+        let frame = editor.compose_frame(20, 3);
+        assert_eq!(frame.get(0, 0).unwrap().bg, None);
+        assert_eq!(frame.get(1, 0).unwrap().bg, Some(Color::Grey));
+        assert_eq!(frame.get(2, 0).unwrap().bg, Some(Color::Grey));
+        assert_eq!(frame.get(3, 0).unwrap().bg, None);
+    }
+
+    #[test]
+    fn test_search_matches_are_highlighted_until_noh() {
+        let mut editor = editor_with(&["foo bar", "x foo foobar"]);
+        editor.show_line_numbers = false;
+        let highlighted = |editor: &mut Editor| {
+            let frame = editor.compose_frame(20, 4);
+            (0..2u16)
+                .flat_map(|y| (0..12u16).map(move |x| (x, y)))
+                .filter(|&(x, y)| frame.get(x, y).unwrap().bg == Some(Color::Yellow))
+                .collect::<Vec<_>>()
+        };
+        assert!(highlighted(&mut editor).is_empty());
+
+        press_keys(&mut editor, "*");
+        assert_eq!(highlighted(&mut editor), vec![(0, 0), (1, 0), (2, 0), (2, 1), (3, 1), (4, 1)]);
+        assert!(editor.search_highlight);
+
+        run_command(&mut editor, "noh");
+        assert!(!editor.search_highlight);
+        assert!(highlighted(&mut editor).is_empty());
+        press_keys(&mut editor, "n");
+        assert_eq!((editor.cy, editor.cx), (0, 0));
+        assert_eq!(highlighted(&mut editor).len(), 6);
+
+        run_command(&mut editor, "set nohlsearch");
+        assert!(editor.search_highlight);
+        assert!(highlighted(&mut editor).is_empty());
+    }
+
+    #[test]
+    fn test_n_repeats_the_last_search_either_way() {
+        let mut editor = editor_with(&["a x", "x", "b x"]);
+        press_keys(&mut editor, "n");
+        assert_eq!(editor.status_message.as_deref(), Some("No previous search"));
+        editor.cx = 2;
+        press_keys(&mut editor, "#");
+        assert_eq!((editor.cy, editor.cx), (2, 2));
+        press_keys(&mut editor, "n");
+        assert_eq!((editor.cy, editor.cx), (1, 0));
+        press_keys(&mut editor, "N");
+        assert_eq!((editor.cy, editor.cx), (2, 2));
+    }
+
+    #[test]
+    fn test_dd_then_p_moves_a_line_down() {
+        let mut editor = editor_with(&["one", "two", "three"]);
+        press(&mut editor, key(KeyCode::Char('d'), KeyModifiers::NONE));
+        assert_eq!(editor.pending_operator, Some('d'));
+        press(&mut editor, key(KeyCode::Char('d'), KeyModifiers::NONE));
+        assert_eq!(editor.buffer.lines, vec!["two", "three"]);
+        assert_eq!(editor.register, vec!["one"]);
+        assert!(editor.register_linewise);
+
+        press(&mut editor, key(KeyCode::Char('p'), KeyModifiers::NONE));
+        assert_eq!(editor.buffer.lines, vec!["two", "one", "three"]);
+        assert_eq!(editor.cy, 1);
+    }
+
+    #[test]
+    fn test_counted_dd_near_the_end_takes_the_remaining_lines() {
+        let mut editor = editor_with(&["one", "two", "three", "four"]);
+        editor.cy = 2;
+        press_keys(&mut editor, "3dd");
+        assert_eq!(editor.buffer.lines, vec!["one", "two"]);
+        assert_eq!(editor.register, vec!["three", "four"]);
+        assert!(editor.register_linewise);
+        assert_eq!(editor.status_message.as_deref(), Some("2 lines deleted"));
+        assert_eq!(editor.cy, 1);
+
+        press_keys(&mut editor, "p");
+        assert_eq!(editor.buffer.lines, vec!["one", "two", "three", "four"]);
+        editor.apply_action(Actions::Undo);
+        editor.apply_action(Actions::Undo);
+        assert_eq!(editor.buffer.lines, vec!["one", "two", "three", "four"]);
+    }
+
+    #[test]
+    fn test_counted_yy_yanks_several_lines() {
+        let mut editor = editor_with(&["a", "b", "c"]);
+        press_keys(&mut editor, "2yy");
+        assert_eq!(editor.register, vec!["a", "b"]);
+        assert_eq!(editor.buffer.lines, vec!["a", "b", "c"]);
+        editor.cy = 2;
+        press_keys(&mut editor, "5yyP");
+        assert_eq!(editor.buffer.lines, vec!["a", "b", "c", "c"]);
+    }
+
+    #[test]
+    fn test_yy_and_paste_before() {
+        let mut editor = editor_with(&["a", "  b"]);
+        editor.cy = 1;
+        press(&mut editor, key(KeyCode::Char('y'), KeyModifiers::NONE));
+        press(&mut editor, key(KeyCode::Char('y'), KeyModifiers::NONE));
+        editor.cy = 0;
+        press(&mut editor, key(KeyCode::Char('P'), KeyModifiers::SHIFT));
+        assert_eq!(editor.buffer.lines, vec!["  b", "a", "  b"]);
+        assert_eq!((editor.cx, editor.cy), (2, 0));
+    }
+
+    #[test]
+    fn test_operator_cancelled_by_other_key() {
+        let mut editor = editor_with(&["a", "b"]);
+        press(&mut editor, key(KeyCode::Char('d'), KeyModifiers::NONE));
+        press(&mut editor, key(KeyCode::Char('j'), KeyModifiers::NONE));
+        assert_eq!(editor.pending_operator, None);
+        assert_eq!(editor.buffer.lines, vec!["a", "b"]);
+        assert_eq!(editor.cy, 0);
+    }
+
+    #[test]
+    fn test_dw_and_d_dollar_delete_into_the_register() {
+        let mut editor = editor_with(&["let value = 1;", "next"]);
+        editor.cx = 4;
+        press_keys(&mut editor, "dw");
+        assert_eq!(editor.buffer.lines, vec!["let = 1;", "next"]);
+        assert_eq!(editor.register, vec!["value "]);
+        assert!(!editor.register_linewise);
+
+        press_keys(&mut editor, "d$");
+        assert_eq!(editor.buffer.lines, vec!["let ", "next"]);
+        assert_eq!(editor.register, vec!["= 1;"]);
+        assert_eq!(editor.cx, 3);
+
+        press_keys(&mut editor, "dw");
+        assert_eq!(editor.buffer.lines, vec!["let", "next"]);
+        press_keys(&mut editor, "P");
+        assert_eq!(editor.buffer.lines, vec!["le t", "next"]);
+    }
+
+    #[test]
+    fn test_dg_deletes_to_the_end_of_the_buffer() {
+        let mut editor = editor_with(&["keep", "  drop", "drop too"]);
+        editor.cy = 1;
+        press(&mut editor, key(KeyCode::Char('d'), KeyModifiers::NONE));
+        press(&mut editor, key(KeyCode::Char('G'), KeyModifiers::SHIFT));
+        assert_eq!(editor.buffer.lines, vec!["keep"]);
+        assert_eq!(editor.register, vec!["  drop", "drop too"]);
+        assert!(editor.register_linewise);
+        assert_eq!((editor.cy, editor.cx), (0, 0));
+
+        press_keys(&mut editor, "dxl");
+        assert_eq!(editor.buffer.lines, vec!["keep"]);
+        assert_eq!((editor.cx, editor.pending_operator), (1, None));
+    }
+
+    #[test]
+    fn test_diw_and_ciw_work_on_the_word_or_blanks_under_the_cursor() {
+        let mut editor = editor_with(&["call(some_arg,  next)"]);
+        editor.cx = 7;
+        press_keys(&mut editor, "diw");
+        assert_eq!(editor.buffer.lines, vec!["call(,  next)"]);
+        assert_eq!(editor.register, vec!["some_arg"]);
+        assert_eq!(editor.cx, 5);
+
+        editor.cx = 7;
+        press_keys(&mut editor, "diw");
+        assert_eq!(editor.buffer.lines, vec!["call(,next)"]);
+
+        editor.cx = 1;
+        press_keys(&mut editor, "ciwrun");
+        press(&mut editor, key(KeyCode::Esc, KeyModifiers::NONE));
+        assert_eq!(editor.buffer.lines, vec!["run(,next)"]);
+        assert_eq!(editor.register, vec!["call"]);
+
+        editor.cx = 6;
+        press_keys(&mut editor, ".");
+        assert_eq!(editor.buffer.lines, vec!["run(,run)"]);
+
+        press_keys(&mut editor, "dix");
+        assert_eq!(editor.buffer.lines, vec!["run(,run)"]);
+        assert!(!editor.pending_object);
+    }
+
+    #[test]
+    fn test_c_dollar_changes_to_the_end_of_the_line() {
+        let mut editor = editor_with(&["keep drop"]);
+        editor.cx = 5;
+        press_keys(&mut editor, "c$go");
+        assert_eq!(editor.buffer.lines, vec!["keep go"]);
+        assert_eq!(editor.mode, Mode::Insert);
+    }
+
+    #[test]
+    fn test_charwise_paste_after_cursor() {
+        let mut editor = editor_with(&["hello world"]);
+        editor.cx = 6;
+        editor.apply_action(Actions::EnterMode(Mode::Visual));
+        editor.apply_action(Actions::MoveWordEnd);
+        editor.apply_action(Actions::YankSelection);
+        editor.cx = 4;
+        editor.apply_action(Actions::Paste);
+        assert_eq!(editor.buffer.lines, vec!["helloworld world"]);
+        assert_eq!(editor.cx, 9);
+    }
+
+    #[test]
+    fn test_tabs_render_as_spaces_and_move_the_cursor() {
+        let mut editor = editor_with(&["\tx\ty"]);
+        editor.show_line_numbers = false;
+        editor.cx = 3;
+        let frame = editor.compose_frame(50, 3);
+        assert_eq!(frame.row_text(0).trim_end(), "    x   y");
+        assert_eq!(editor.screen_cursor(&frame), (8, 0));
+        assert!(frame.row_text(2).contains("Col 9"));
+
+        editor.tab_width = 2;
+        let frame = editor.compose_frame(50, 3);
+        assert_eq!(frame.row_text(0).trim_end(), "  x y");
+        assert_eq!(editor.screen_cursor(&frame), (4, 0));
+    }
+
+    #[test]
+    fn test_wrapped_lines_take_several_rows() {
+        let mut editor = editor_with(&["abcdefghijklmnopq", "short", "end"]);
+        editor.wrap = true;
+        editor.cx = 9;
+        // a 2-column gutter leaves 8 columns of text
+        let frame = editor.compose_frame(10, 6);
+        assert_eq!(frame.row_text(0), "1 abcdefgh");
+        assert_eq!(frame.row_text(1), "  ijklmnop");
+        assert_eq!(frame.row_text(2), "  q       ");
+        assert_eq!(frame.row_text(3), "2 short   ");
+        assert_eq!(frame.row_text(4), "3 end     ");
+        assert_eq!(editor.screen_cursor(&frame), (3, 1));
+        editor.last_frame = Some(frame);
+
+        editor.apply_action(Actions::MouseClick(3, 2));
+        assert_eq!((editor.cy, editor.cx), (0, 16));
+        editor.apply_action(Actions::MouseClick(9, 3));
+        assert_eq!((editor.cy, editor.cx), (1, 4));
+        editor.apply_action(Actions::MoveDisplayLineUp);
+        assert_eq!((editor.cy, editor.cx), (0, 16));
+        editor.apply_action(Actions::MoveDisplayLineUp);
+        assert_eq!((editor.cy, editor.cx), (0, 8));
+
+        // the cursor row stays on screen even when wrapped lines fill it
+        editor.cy = 2;
+        editor.cx = 0;
+        let frame = editor.compose_frame(10, 4);
+        assert_eq!(editor.row_offset, 1);
+        assert_eq!(frame.row_text(1), "3 end     ");
+        assert_eq!(editor.screen_cursor(&frame), (2, 1));
+    }
+
+    #[test]
+    fn test_render_cache_draws_again_only_the_edited_line() {
+        let mut editor = editor_with(&["fn a() {}", "\tlet b = 1;", "}"]);
+        let cached = |editor: &Editor, line: &str| editor.render_cache.lines.get(line).cloned();
+        editor.compose_frame(40, 5);
+        let before: Vec<_> = editor.buffer.lines.iter().map(|line| cached(&editor, line).unwrap()).collect();
+        assert_eq!(before[1].chars[0], ("    ".to_string(), false));
+
+        editor.cy = 1;
+        editor.apply_action(Actions::AppendLineEnd);
+        editor.apply_action(Actions::PrintChar('2'));
+        editor.apply_action(Actions::EnterMode(Mode::Normal));
+        let frame = editor.compose_frame(40, 5);
+        assert_eq!(frame.row_text(1).trim_end(), "2     let b = 1;2");
+        assert!(Rc::ptr_eq(&before[0], &cached(&editor, "fn a() {}").unwrap()));
+        assert!(Rc::ptr_eq(&before[2], &cached(&editor, "}").unwrap()));
+        assert!(cached(&editor, "\tlet b = 1;").is_none());
+        assert!(cached(&editor, "\tlet b = 1;2").is_some());
+
+        // other settings draw every line again
+        editor.tab_width = 8;
+        editor.compose_frame(40, 5);
+        assert!(!Rc::ptr_eq(&before[0], &cached(&editor, "fn a() {}").unwrap()));
+    }
+
+    #[test]
+    fn test_layout_maps_chars_to_display_rows() {
+        let layout = LineLayout::new("ab\tcd日", 0, 2, Some(3), 4);
+        assert_eq!(layout.starts, vec![0, 2, 4]);
+        assert_eq!(layout.place(1), (0, 1));
+        assert_eq!(layout.place(2), (1, 0));
+        assert_eq!(layout.place(3), (1, 2));
+        assert_eq!(layout.place(4), (2, 0));
+        assert_eq!(layout.place(5), (2, 1));
+        assert_eq!(layout.cells(3..6).collect::<Vec<_>>(), vec![(2, 3), (0, 4), (1, 4), (2, 4)]);
+
+        let unwrapped = LineLayout::new("ab\tcd", 0, 0, None, 4);
+        assert_eq!(unwrapped.starts, vec![0]);
+        assert_eq!(unwrapped.place(3), (0, 4));
+    }
+
+    #[test]
+    fn test_tab_width_follows_the_file_type() {
+        let for_file = |name: &str| Editor::with_buffer(Buffer { file: Some(name.to_string()), ..Default::default() });
+        assert_eq!(for_file("app.json").tab_width, 2);
+        assert_eq!(for_file("main.go").tab_width, 4);
+        assert_eq!(for_file("notes.txt").tab_width, DEFAULT_TAB_WIDTH);
+
+        let mut editor = for_file("app.json");
+        editor.run_startup_commands(&["set tabwidth=8".to_string()]);
+        assert_eq!(editor.tab_width, 8);
+    }
+
+    #[test]
+    fn test_startinsert_starts_editing_straight_away() {
+        let mut editor = editor_with(&["notes"]);
+        editor.run_startup_commands(&["startinsert".to_string()]);
+        assert_eq!(editor.mode, Mode::Insert);
+        press_keys(&mut editor, "Qq");
+        assert_eq!(editor.buffer.lines, vec!["Qqnotes"]);
+        assert!(!editor.quit);
+
+        press(&mut editor, key(KeyCode::Esc, KeyModifiers::NONE));
+        run_command(&mut editor, "q!");
+        assert!(editor.quit);
+    }
+
+    #[test]
+    fn test_draw_chars_shows_whitespace_with_list() {
+        let line = "\ta b \t ";
+        let cols = buffer::visual_cols(line, 4);
+        let drawn = |list| draw_chars(line, &cols, list).into_iter().map(|(text, _)| text).collect::<String>();
+        assert_eq!(drawn(false), "    a b      ");
+        assert_eq!(drawn(true), "→   a b·→   ·");
+        let markers: Vec<bool> = draw_chars(line, &cols, true).into_iter().map(|(_, marker)| marker).collect();
+        assert_eq!(markers, vec![true, false, false, false, true, true, true]);
+        assert!(draw_chars("  ", &buffer::visual_cols("  ", 4), true).iter().all(|(text, _)| text == "·"));
+    }
+
+    #[test]
+    fn test_list_keeps_the_cursor_column() {
+        let mut editor = editor_with(&["\tx  "]);
+        editor.show_line_numbers = false;
+        editor.list = true;
+        editor.cx = 1;
+        let frame = editor.compose_frame(20, 3);
+        assert_eq!(frame.row_text(0).trim_end(), "→   x··");
+        assert_eq!(frame.get(5, 0).unwrap().fg, Some(Color::DarkGrey));
+        assert_eq!(editor.screen_cursor(&frame), (4, 0));
+        assert_eq!(editor.buffer.lines, vec!["\tx  "]);
+    }
+
+    #[test]
+    fn test_wide_characters_move_the_cursor_two_cells() {
+        let mut editor = editor_with(&["ab日本語cd"]);
+        editor.show_line_numbers = false;
+        editor.cx = 2;
+        editor.apply_action(Actions::MoveRight);
+        assert_eq!(editor.cx, 3);
+        let frame = editor.compose_frame(50, 3);
+        assert_eq!(frame.row_text(0).trim_end(), "ab日本語cd");
+        assert_eq!(editor.screen_cursor(&frame), (4, 0));
+        assert!(frame.row_text(2).contains("Col 5"));
+
+        editor.apply_action(Actions::MoveLineEnd);
+        let frame = editor.compose_frame(50, 3);
+        assert_eq!(editor.cx, 6);
+        assert_eq!(editor.screen_cursor(&frame), (9, 0));
+        assert!(frame.row_text(2).contains("Col 10"));
+    }
+
+    fn mouse(kind: MouseEventKind, column: u16, row: u16) -> Event {
+        Event::Mouse(MouseEvent { kind, column, row, modifiers: KeyModifiers::NONE })
+    }
+
+    #[test]
+    fn test_click_moves_cursor_through_gutter_tabs_and_scroll() {
+        let lines: Vec<String> = (0..20).map(|n| format!("\t{}x", n)).collect();
+        let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+        let mut editor = editor_with(&lines);
+        editor.row_offset = 5;
+        editor.cy = 5;
+        editor.draw(&mut Vec::new(), 40, 6).unwrap();
+
+        press(&mut editor, mouse(MouseEventKind::Down(MouseButton::Left), 3 + 4, 2));
+        assert_eq!((editor.cy, editor.cx), (7, 1));
+        press(&mut editor, mouse(MouseEventKind::Down(MouseButton::Left), 4, 1));
+        assert_eq!((editor.cy, editor.cx), (6, 0));
+        press(&mut editor, mouse(MouseEventKind::Down(MouseButton::Left), 30, 0));
+        assert_eq!((editor.cy, editor.cx), (5, 2));
+
+        // the status line isn't text
+        press(&mut editor, mouse(MouseEventKind::Down(MouseButton::Left), 3, 5));
+        assert_eq!(editor.cy, 5);
+
+        editor.mouse = false;
+        press(&mut editor, mouse(MouseEventKind::Down(MouseButton::Left), 3, 2));
+        assert_eq!(editor.cy, 5);
+    }
+
+    #[test]
+    fn test_scroll_wheel_moves_view_and_keeps_cursor_on_screen() {
+        let lines: Vec<String> = (0..20).map(|n| n.to_string()).collect();
+        let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+        let mut editor = editor_with(&lines);
+        editor.draw(&mut Vec::new(), 40, 6).unwrap();
+
+        press(&mut editor, mouse(MouseEventKind::ScrollDown, 0, 0));
+        assert_eq!((editor.row_offset, editor.cy), (3, 3));
+        editor.cy = 7;
+        press(&mut editor, mouse(MouseEventKind::ScrollUp, 0, 0));
+        assert_eq!((editor.row_offset, editor.cy), (0, 4));
+        press(&mut editor, mouse(MouseEventKind::ScrollUp, 0, 0));
+        assert_eq!(editor.row_offset, 0);
+    }
+
+    #[test]
+    fn test_q_with_unsaved_changes_needs_a_second_press() {
+        let mut editor = editor_with(&["text"]);
+        editor.apply_action(Actions::DeleteChar);
+        press(&mut editor, key(KeyCode::Char('q'), KeyModifiers::NONE));
+        assert!(!editor.quit);
+        assert_eq!(
+            editor.status_message.as_deref(),
+            Some("Unsaved changes — press q again to quit or :w to save")
+        );
+        press(&mut editor, key(KeyCode::Char('q'), KeyModifiers::NONE));
+        assert!(editor.quit);
+    }
+
+    #[test]
+    fn test_other_key_resets_quit_confirmation() {
+        let mut editor = editor_with(&["text"]);
+        editor.apply_action(Actions::DeleteChar);
+        press(&mut editor, key(KeyCode::Char('q'), KeyModifiers::NONE));
+        press(&mut editor, key(KeyCode::Char('l'), KeyModifiers::NONE));
+        press(&mut editor, key(KeyCode::Char('q'), KeyModifiers::NONE));
+        assert!(!editor.quit);
+
+        let mut clean = editor_with(&["text"]);
+        press(&mut clean, key(KeyCode::Char('q'), KeyModifiers::NONE));
+        assert!(clean.quit);
+    }
+
+    #[test]
+    fn test_recorded_insert_plays_back_with_a_count() {
+        let mut editor = editor_with(&["a", "b", "c", "d"]);
+        press_keys(&mut editor, "QwI-");
+        assert_eq!(editor.macro_register, Some('w'));
+        press(&mut editor, key(KeyCode::Esc, KeyModifiers::NONE));
+        press_keys(&mut editor, "jQ");
+        assert_eq!(editor.macro_register, None);
+        assert_eq!(editor.macros[&'w'].len(), 4);
+        assert_eq!(editor.buffer.lines, vec!["-a", "b", "c", "d"]);
+
+        press_keys(&mut editor, "@w");
+        assert_eq!(editor.buffer.lines, vec!["-a", "-b", "c", "d"]);
+        press_keys(&mut editor, "2@w");
+        assert_eq!(editor.buffer.lines, vec!["-a", "-b", "-c", "-d"]);
+        editor.apply_action(Actions::Undo);
+        assert_eq!(editor.buffer.lines, vec!["-a", "-b", "-c", "d"]);
+
+        editor.cy = 3;
+        press_keys(&mut editor, "@@");
+        assert_eq!(editor.buffer.lines, vec!["-a", "-b", "-c", "-d"]);
+        press_keys(&mut editor, "@z");
+        assert_eq!(editor.status_message.as_deref(), Some("Nothing recorded in register z"));
+    }
+
+    #[test]
+    fn test_macro_cannot_play_itself() {
+        let mut editor = editor_with(&["abcdef"]);
+        press_keys(&mut editor, "QaxQ");
+        editor.macros.get_mut(&'a').unwrap().extend(
+            "@a".chars().map(|c| key(KeyCode::Char(c), KeyModifiers::NONE)),
+        );
+        press_keys(&mut editor, "@a");
+        assert_eq!(editor.buffer.lines, vec!["cdef"]);
+    }
+
+    #[test]
+    fn test_o_opens_line_below_in_insert_mode() {
+        let mut editor = editor_with(&["one", "two"]);
+        editor.cx = 2;
+        press(&mut editor, key(KeyCode::Char('o'), KeyModifiers::NONE));
+        assert_eq!(editor.buffer.lines, vec!["one", "", "two"]);
+        assert_eq!((editor.cx, editor.cy, editor.mode), (0, 1, Mode::Insert));
+
+        type_text(&mut editor, "new");
+        press(&mut editor, key(KeyCode::Esc, KeyModifiers::NONE));
+        editor.apply_action(Actions::Undo);
+        assert_eq!(editor.buffer.lines, vec!["one", "two"]);
+    }
+
+    #[test]
+    fn test_new_lines_keep_the_indentation() {
+        let mut editor = editor_with(&["    if x {", "\t"]);
+        editor.apply_action(Actions::AppendLineEnd);
+        editor.apply_action(Actions::NewLine);
+        assert_eq!(editor.buffer.lines, vec!["    if x {", "    ", "\t"]);
+        assert_eq!(editor.cx, 4);
+        editor.apply_action(Actions::Backspace);
+        assert_eq!(editor.buffer.lines[1], "   ");
+        editor.apply_action(Actions::EnterMode(Mode::Normal));
+
+        editor.cy = 2;
+        press_keys(&mut editor, "o");
+        assert_eq!(editor.buffer.lines[3], "\t");
+        assert_eq!((editor.cy, editor.cx), (3, 1));
+        editor.apply_action(Actions::EnterMode(Mode::Normal));
+
+        editor.auto_indent = false;
+        editor.cy = 0;
+        press_keys(&mut editor, "O");
+        assert_eq!(editor.buffer.lines[0], "");
+    }
+
+    #[test]
+    fn test_enter_inside_an_indented_line_drops_the_moved_text_spaces() {
+        let mut editor = editor_with(&["  let a = 1;   let b = 2;"]);
+        editor.apply_action(Actions::EnterMode(Mode::Insert));
+        editor.cx = 12;
+        editor.apply_action(Actions::NewLine);
+        assert_eq!(editor.buffer.lines, vec!["  let a = 1;", "  let b = 2;"]);
+        assert_eq!((editor.cy, editor.cx), (1, 2));
+    }
+
+    #[test]
+    fn test_capital_o_opens_line_above() {
+        let mut editor = editor_with(&["one", "two"]);
+        editor.cy = 1;
+        press(&mut editor, key(KeyCode::Char('O'), KeyModifiers::SHIFT));
+        assert_eq!(editor.buffer.lines, vec!["one", "", "two"]);
+        assert_eq!((editor.cx, editor.cy, editor.mode), (0, 1, Mode::Insert));
+
+        editor.mode = Mode::Normal;
+        editor.cy = 0;
+        press(&mut editor, key(KeyCode::Char('O'), KeyModifiers::SHIFT));
+        assert_eq!(editor.buffer.len(), 4);
+        assert_eq!(editor.cy, 0);
+    }
+
+    #[test]
+    fn test_append_commands_enter_insert_mode() {
+        let mut editor = editor_with(&["  word", ""]);
+        editor.cx = 3;
+        press(&mut editor, key(KeyCode::Char('a'), KeyModifiers::NONE));
+        assert_eq!((editor.cx, editor.mode), (4, Mode::Insert));
+
+        editor.mode = Mode::Normal;
+        press(&mut editor, key(KeyCode::Char('A'), KeyModifiers::SHIFT));
+        assert_eq!((editor.cx, editor.mode), (6, Mode::Insert));
+
+        editor.mode = Mode::Normal;
+        press(&mut editor, key(KeyCode::Char('I'), KeyModifiers::SHIFT));
+        assert_eq!((editor.cx, editor.mode), (2, Mode::Insert));
+
+        editor.mode = Mode::Normal;
+        editor.cy = 1;
+        press(&mut editor, key(KeyCode::Char('a'), KeyModifiers::NONE));
+        assert_eq!((editor.cx, editor.mode), (0, Mode::Insert));
+    }
+
+    #[test]
+    fn test_huge_counts_are_capped() {
+        let mut editor = editor_with(&["a", "b", "c"]);
+        press_keys(&mut editor, "99999999999999999999");
+        assert_eq!(editor.pending_count, Some(MAX_COUNT));
+        press_keys(&mut editor, "j");
+        assert_eq!(editor.cy, 2);
+
+        press_keys(&mut editor, "gg99999999999999999999yy");
+        assert_eq!(editor.register, vec!["a", "b", "c"]);
+        press_keys(&mut editor, "99999999999999999999>>");
+        assert_eq!(editor.buffer.lines, vec!["\ta", "\tb", "\tc"]);
+        press_keys(&mut editor, "99999999999999999999dd");
+        assert_eq!(editor.buffer.lines, vec![""]);
+    }
+
+    #[test]
+    fn test_count_prefix_repeats_motions() {
+        let mut editor = editor_with(&["0123456789", "b", "c", "d", "e", "f"]);
+        for c in "5j".chars() {
+            press(&mut editor, key(KeyCode::Char(c), KeyModifiers::NONE));
+        }
+        assert_eq!(editor.cy, 5);
+        for c in "3k".chars() {
+            press(&mut editor, key(KeyCode::Char(c), KeyModifiers::NONE));
+        }
+        assert_eq!(editor.cy, 2);
+
+        editor.cy = 0;
+        for c in "10l".chars() {
+            press(&mut editor, key(KeyCode::Char(c), KeyModifiers::NONE));
+        }
+        assert_eq!(editor.cx, 9);
+        press(&mut editor, key(KeyCode::Char('4'), KeyModifiers::NONE));
+        assert_eq!(editor.pending_indicator(), "4");
+        press(&mut editor, key(KeyCode::Char('h'), KeyModifiers::NONE));
+        assert_eq!(editor.cx, 5);
+        assert_eq!(editor.pending_count, None);
+    }
+
+    #[test]
+    fn test_ctrl_g_shows_file_info() {
+        let mut editor = editor_with(&["a", "b", "c", "d"]);
+        editor.cy = 1;
+        press(&mut editor, key(KeyCode::Char('g'), KeyModifiers::CONTROL));
+        assert_eq!(editor.status_message.as_deref(), Some("\"[No Name]\" 4 lines --50%--"));
+
+        editor.buffer.file = Some("notes.txt".to_string());
+        editor.apply_action(Actions::DeleteChar);
+        press(&mut editor, key(KeyCode::Char('g'), KeyModifiers::CONTROL));
+        assert_eq!(editor.status_message.as_deref(), Some("\"notes.txt\" [Modified] 4 lines --50%--"));
+    }
+
+    #[test]
+    fn test_g_ctrl_g_reports_stats_without_moving() {
+        let mut editor = editor_with(&["one two", "three"]);
+        (editor.cy, editor.cx) = (1, 2);
+        press_keys(&mut editor, "g");
+        press(&mut editor, key(KeyCode::Char('g'), KeyModifiers::CONTROL));
+        assert_eq!(
+            editor.status_message.as_deref(),
+            Some("Line 2 of 2; Col 3; Char 11 of 13; 3 words")
+        );
+        assert_eq!((editor.cy, editor.cx), (1, 2));
+    }
+
+    #[test]
+    fn test_count_prefix_with_dd_and_leading_zero() {
+        let mut editor = editor_with(&["a", "b", "c", "d"]);
+        editor.cx = 0;
+        for c in "2dd".chars() {
+            press(&mut editor, key(KeyCode::Char(c), KeyModifiers::NONE));
+        }
+        assert_eq!(editor.buffer.lines, vec!["c", "d"]);
+        editor.apply_action(Actions::Undo);
+        assert_eq!(editor.buffer.lines, vec!["a", "b", "c", "d"]);
+
+        let mut editor = editor_with(&["abc"]);
+        editor.cx = 2;
+        press(&mut editor, key(KeyCode::Char('0'), KeyModifiers::NONE));
+        assert_eq!((editor.cx, editor.pending_count), (0, None));
+    }
+
+    #[test]
+    fn test_syntax_colours_follow_the_gutter_and_can_be_turned_off() {
+        let mut editor = editor_with(&["\tfn x"]);
+        editor.buffer.file = Some("main.rs".to_string());
+        let gutter = editor.gutter_width() as u16;
+        let frame = editor.compose_frame(40, 3);
+        let keyword = Some(syntax::TokenKind::Keyword.color());
+        assert_eq!(frame.get(gutter + 4, 0).unwrap().fg, keyword);
+        assert_eq!(frame.get(gutter + 5, 0).unwrap().fg, keyword);
+        assert_eq!(frame.get(gutter + 7, 0).unwrap().fg, None);
+
+        run_command(&mut editor, "syntax off");
+        let frame = editor.compose_frame(40, 3);
+        assert_eq!(frame.get(gutter + 4, 0).unwrap().fg, None);
+    }
+
+    /// A clipboard that keeps its text where the test can see it.
+    struct MockClipboard(std::rc::Rc<std::cell::RefCell<Option<String>>>);
+
+    impl Clipboard for MockClipboard {
+        fn get_text(&mut self) -> Option<String> {
+            self.0.borrow().clone()
+        }
+
+        fn set_text(&mut self, text: &str) -> bool {
+            *self.0.borrow_mut() = Some(text.to_string());
+            true
+        }
+    }
+
+    fn press_keys(editor: &mut Editor, keys: &str) {
+        for c in keys.chars() {
+            press(editor, key(KeyCode::Char(c), KeyModifiers::NONE));
+        }
+    }
+
+    #[test]
+    fn test_plus_register_yanks_to_and_pastes_from_clipboard() {
+        let shared = std::rc::Rc::new(std::cell::RefCell::new(None));
+        let mut editor = editor_with(&["first", "second"]);
+        editor.clipboard = Some(Box::new(MockClipboard(shared.clone())));
+
+        press_keys(&mut editor, "\"+yy");
+        assert_eq!(shared.borrow().as_deref(), Some("first\n"));
+        assert_eq!(editor.register_name, None);
+
+        *shared.borrow_mut() = Some("from outside".to_string());
+        press_keys(&mut editor, "yyj\"+p");
+        assert_eq!(editor.buffer.lines, vec!["first", "sfrom outsideecond"]);
+
+        press_keys(&mut editor, "p");
+        assert_eq!(editor.buffer.lines, vec!["first", "sfrom outsideecond", "first"]);
+    }
+
+    #[test]
+    fn test_clipboard_register_falls_back_without_a_clipboard() {
+        let mut editor = editor_with(&["only"]);
+        press_keys(&mut editor, "\"+yy\"+p");
+        assert_eq!(editor.buffer.lines, vec!["only", "only"]);
+    }
+
+    #[test]
+    fn test_r_replaces_one_character() {
+        let mut editor = editor_with(&["cat", ""]);
+        editor.cx = 1;
+        press_keys(&mut editor, "ru");
+        assert_eq!(editor.buffer.lines, vec!["cut", ""]);
+        assert_eq!((editor.cx, editor.mode), (1, Mode::Normal));
+
+        editor.cy = 1;
+        press_keys(&mut editor, "rx");
+        assert_eq!(editor.buffer.lines, vec!["cut", ""]);
+        assert!(!editor.awaiting_replace);
+    }
+
+    #[test]
+    fn test_replace_mode_overwrites_and_backspace_restores() {
+        let mut editor = editor_with(&["abc"]);
+        editor.cx = 1;
+        press(&mut editor, key(KeyCode::Char('R'), KeyModifiers::SHIFT));
+        assert_eq!(editor.mode, Mode::Replace);
+        press_keys(&mut editor, "XYZ");
+        assert_eq!(editor.buffer.lines, vec!["aXYZ"]);
+
+        press(&mut editor, key(KeyCode::Backspace, KeyModifiers::NONE));
+        press(&mut editor, key(KeyCode::Backspace, KeyModifiers::NONE));
+        assert_eq!(editor.buffer.lines, vec!["aXc"]);
+        assert_eq!(editor.cx, 2);
+
+        press(&mut editor, key(KeyCode::Char('s'), KeyModifiers::CONTROL));
+        assert_eq!(editor.buffer.lines, vec!["aXc"]);
+        assert_eq!(editor.mode, Mode::Replace);
+
+        press(&mut editor, key(KeyCode::Esc, KeyModifiers::NONE));
+        editor.apply_action(Actions::Undo);
+        assert_eq!(editor.buffer.lines, vec!["abc"]);
+    }
+
+    #[test]
+    fn test_earlier_and_later_commands() {
+        let mut editor = editor_with(&["a"]);
+        for c in ['x', 'y', 'z'] {
+            editor.apply_action(Actions::AppendLineEnd);
+            editor.apply_action(Actions::PrintChar(c));
+            editor.apply_action(Actions::EnterMode(Mode::Normal));
+        }
+        run_command(&mut editor, "earlier 2");
+        assert_eq!(editor.buffer.lines, vec!["ax"]);
+        assert_eq!(editor.status_message.as_deref(), Some("Undid 2 changes"));
+        run_command(&mut editor, "earlier 1h");
+        assert_eq!(editor.buffer.lines, vec!["a"]);
+        assert!(!editor.buffer.modified);
+        run_command(&mut editor, "later 1m");
+        assert_eq!(editor.buffer.lines, vec!["axyz"]);
+        assert!(editor.buffer.modified);
+        run_command(&mut editor, "later");
+        assert_eq!(editor.status_message.as_deref(), Some("Already at newest change"));
+
+        assert_eq!(parse_command("earlier 10s"), Some(Actions::Earlier(UndoSpan::Time(Duration::from_secs(10)))));
+        assert_eq!(parse_command("later 5m"), Some(Actions::Later(UndoSpan::Time(Duration::from_secs(300)))));
+        assert_eq!(parse_command("earlier 2d"), None);
+    }
+
+    #[test]
+    fn test_colon_number_jumps_to_line() {
+        let mut editor = editor_with(&["one", "  two", "three"]);
+        run_command(&mut editor, "2");
+        assert_eq!((editor.cy, editor.cx), (1, 2));
+        assert_eq!(editor.status_message, None);
+
+        run_command(&mut editor, "99");
+        assert_eq!(editor.cy, 2);
+        assert_eq!(editor.status_message.as_deref(), Some("Only 3 lines, jumped to the last"));
+
+        run_command(&mut editor, "0");
+        assert_eq!(editor.cy, 0);
+        assert_eq!(parse_command("-1"), None);
+    }
+
+    fn named_buffer(name: &str, lines: &[&str]) -> Buffer {
+        Buffer {
+            file: Some(name.to_string()),
+            lines: lines.iter().map(|s| s.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_bn_and_bp_keep_each_buffers_cursor_and_changes() {
+        let mut editor = Editor::with_buffer(named_buffer("a.txt", &["alpha", "beta"]));
+        editor.add_buffer(named_buffer("b.txt", &["one"]));
+        editor.add_buffer(named_buffer("c.txt", &["x"]));
+        press_keys(&mut editor, "jx");
+
+        run_command(&mut editor, "bn");
+        assert_eq!(editor.buffer.display_name(), "b.txt");
+        assert_eq!((editor.cy, editor.cx), (0, 0));
+        assert_eq!(editor.status_message.as_deref(), Some("\"b.txt\" 1 lines"));
+        let frame = editor.compose_frame(40, 4);
+        assert!(frame.row_text(3).starts_with("NORMAL > b.txt >"));
+
+        run_command(&mut editor, "bp");
+        run_command(&mut editor, "bp");
+        assert_eq!(editor.buffer.display_name(), "c.txt");
+        run_command(&mut editor, "bn");
+        assert_eq!(editor.buffer.lines, vec!["alpha", "eta"]);
+        assert_eq!((editor.cy, editor.cx), (1, 0));
+        assert!(editor.buffer.modified);
+
+        editor.apply_action(Actions::Undo);
+        assert_eq!(editor.buffer.lines, vec!["alpha", "beta"]);
+    }
+
+    #[test]
+    fn test_ls_lists_buffers_and_quit_checks_all_of_them() {
+        let mut editor = Editor::with_buffer(named_buffer("a.txt", &["alpha"]));
+        editor.add_buffer(named_buffer("b.txt", &["one"]));
+        press_keys(&mut editor, "x");
+        run_command(&mut editor, "bn");
+        run_command(&mut editor, "ls");
+        assert_eq!(editor.status_message.as_deref(), Some("1 \"a.txt\" +  2 %\"b.txt\""));
+
+        run_command(&mut editor, "q");
+        assert!(!editor.quit);
+        assert_eq!(editor.status_message.as_deref(), Some("No write since last change for buffer \"a.txt\""));
+    }
+
+    #[test]
+    fn test_wq_refuses_with_unsaved_hidden_buffer() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut editor = editor_for_file(&dir, "file.txt", "text");
+        editor.add_buffer(named_buffer("b.txt", &["one"]));
+        run_command(&mut editor, "bn");
+        press_keys(&mut editor, "x");
+        run_command(&mut editor, "bp");
+
+        run_command(&mut editor, "wq");
+        assert!(!editor.quit);
+        assert_eq!(editor.status_message.as_deref(), Some("No write since last change for buffer \"b.txt\""));
+    }
+
+    #[test]
+    fn test_bn_with_a_single_buffer() {
+        let mut editor = editor_with(&["only"]);
+        run_command(&mut editor, "bn");
+        assert_eq!(editor.buffer.lines, vec!["only"]);
+        assert_eq!(editor.status_message.as_deref(), Some("Only one buffer"));
+    }
+
+    #[test]
+    fn test_dot_repeats_x_and_counted_dd() {
+        let mut editor = editor_with(&["abcdef", "1", "2", "3", "4", "5"]);
+        press_keys(&mut editor, "x..");
+        assert_eq!(editor.buffer.lines[0], "def");
+
+        press_keys(&mut editor, "j2dd.");
+        assert_eq!(editor.buffer.lines, vec!["def", "5"]);
+
+        editor.apply_action(Actions::Undo);
+        assert_eq!(editor.buffer.lines, vec!["def", "3", "4", "5"]);
+    }
+
+    #[test]
+    fn test_dot_repeats_an_inserted_word() {
+        let mut editor = editor_with(&["a", "b"]);
+        press_keys(&mut editor, "iab");
+        press(&mut editor, key(KeyCode::Backspace, KeyModifiers::NONE));
+        press_keys(&mut editor, "new ");
+        press(&mut editor, key(KeyCode::Esc, KeyModifiers::NONE));
+        assert_eq!(editor.buffer.lines, vec!["anew a", "b"]);
+
+        press_keys(&mut editor, "j.");
+        assert_eq!(editor.buffer.lines, vec!["anew a", "anew b"]);
+        assert_eq!(editor.mode, Mode::Normal);
+
+        // moving around and `:` commands don't replace the change
+        press_keys(&mut editor, "k0");
+        run_command(&mut editor, "stripws");
+        press_keys(&mut editor, ".");
+        assert_eq!(editor.buffer.lines, vec!["anew anew a", "anew b"]);
+
+        editor.apply_action(Actions::Undo);
+        assert_eq!(editor.buffer.lines, vec!["anew a", "anew b"]);
+    }
+
+    #[test]
+    fn test_parse_substitute() {
+        let parsed = |command: &str| match parse_command(command) {
+            Some(Actions::Substitute(substitution)) => Some(substitution),
+            _ => None,
+        };
+        assert_eq!(
+            parsed("s/old/new/"),
+            Some(Substitution {
+                pattern: "old".to_string(),
+                replacement: "new".to_string(),
+                global: false,
+                whole_buffer: false,
+            })
+        );
+        let escaped = parsed("%s#a\\#b#c\\d#g").unwrap();
+        assert_eq!((escaped.pattern.as_str(), escaped.replacement.as_str()), ("a#b", "c\\d"));
+        assert!(escaped.global && escaped.whole_buffer);
+        assert_eq!(parsed("s/gone").unwrap().replacement, "");
+        assert_eq!(parsed("s//x/"), None);
+        assert_eq!(parsed("s/a/b/q"), None);
+        assert_eq!(parsed("s/a/b/g/"), None);
+        assert_eq!(parse_command("stripws"), Some(Actions::StripWhitespace));
+        assert_eq!(parse_command("strip"), Some(Actions::StripWhitespace));
+    }
+
+    #[test]
+    fn test_parse_set_options() {
+        assert_eq!(parse_command("set number"), Some(Actions::Set(Setting::Number(true))));
+        assert_eq!(parse_command("set nonu"), Some(Actions::Set(Setting::Number(false))));
+        assert_eq!(parse_command("set nowrap"), Some(Actions::Set(Setting::Wrap(false))));
+        assert_eq!(parse_command("set list"), Some(Actions::Set(Setting::List(true))));
+        assert_eq!(parse_command("set noautoindent"), Some(Actions::Set(Setting::AutoIndent(false))));
+        assert_eq!(parse_command("set autopairs"), Some(Actions::Set(Setting::AutoPairs(true))));
+        assert_eq!(parse_command("set nohls"), Some(Actions::Set(Setting::HlSearch(false))));
+        assert_eq!(parse_command("set readonly"), Some(Actions::Set(Setting::ReadOnly(true))));
+        assert_eq!(parse_command("set tabwidth=2"), Some(Actions::Set(Setting::TabWidth(2))));
+        assert_eq!(parse_command("set ts = 8"), Some(Actions::Set(Setting::TabWidth(8))));
+        assert_eq!(parse_command("set tabwidth=0"), None);
+        assert_eq!(parse_command("set et"), Some(Actions::Set(Setting::ExpandTab(true))));
+        assert_eq!(parse_command("set sw=0"), Some(Actions::Set(Setting::ShiftWidth(0))));
+        assert_eq!(parse_command("set shiftwidth=x"), None);
+        assert_eq!(parse_command("set number=2"), None);
+        assert_eq!(parse_command("set bogus"), None);
+    }
+
+    #[test]
+    fn test_substitute_on_line_and_buffer() {
+        let mut editor = editor_with(&["foo foo", "  foo bar", "baz"]);
+        run_command(&mut editor, "s/foo/qux/");
+        assert_eq!(editor.buffer.lines, vec!["qux foo", "  foo bar", "baz"]);
+        assert_eq!(editor.status_message.as_deref(), Some("1 substitutions on 1 lines"));
+
+        run_command(&mut editor, "%s/foo/x/g");
+        assert_eq!(editor.buffer.lines, vec!["qux x", "  x bar", "baz"]);
+        assert_eq!(editor.status_message.as_deref(), Some("2 substitutions on 2 lines"));
+        assert_eq!((editor.cy, editor.cx), (1, 2));
+
+        editor.buffer.modified = false;
+        run_command(&mut editor, "%s/nope/x/g");
+        assert_eq!(editor.status_message.as_deref(), Some("Pattern not found: nope"));
+        assert!(!editor.buffer.modified);
+
+        editor.apply_action(Actions::Undo);
+        assert_eq!(editor.buffer.lines, vec!["qux foo", "  foo bar", "baz"]);
+    }
+
+    #[test]
+    fn test_tilde_toggles_case_and_advances() {
+        let mut editor = editor_with(&["aB-é", "", "ßx"]);
+        press_keys(&mut editor, "~~~~");
+        assert_eq!(editor.buffer.lines[0], "Ab-É");
+        assert_eq!(editor.cx, 3);
+
+        editor.cy = 1;
+        press_keys(&mut editor, "~");
+        assert_eq!(editor.buffer.lines[1], "");
+        assert_eq!(editor.cx, 0);
+
+        editor.cy = 2;
+        press_keys(&mut editor, "2~");
+        assert_eq!(editor.buffer.lines[2], "ßX");
+        assert_eq!(editor.cx, 1);
+
+        editor.apply_action(Actions::Undo);
+        assert_eq!(editor.buffer.lines[2], "ßx");
+    }
+
+    #[test]
+    fn test_positions_are_saved_and_restored() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = dir.path().join("positions.json");
+        let mut editor = editor_for_file(&dir, "notes.txt", "one\ntwo\nthree");
+        editor.positions = Some(Positions::load(&state));
+        press_keys(&mut editor, "jjl");
+        editor.apply_action(Actions::Save);
+
+        let file = dir.path().join("notes.txt").to_string_lossy().into_owned();
+        let mut reopened = Editor::with_buffer(Buffer::from_file(Some(file.clone())).unwrap());
+        reopened.positions = Some(Positions::load(&state));
+        reopened.restore_positions();
+        assert_eq!((reopened.cy, reopened.cx), (2, 1));
+
+        std::fs::write(&file, "one").unwrap();
+        let mut shrunk = Editor::with_buffer(Buffer::from_file(Some(file)).unwrap());
+        shrunk.positions = Some(Positions::load(&state));
+        shrunk.restore_positions();
+        assert_eq!((shrunk.cy, shrunk.cx), (0, 0));
+    }
+
+    #[test]
+    fn test_status_message_clears_after_the_timeout() {
+        let mut editor = editor_with(&["text"]);
+        editor.status_message = Some("Saved.".to_string());
+        let start = Instant::now();
+        editor.expire_status_message(start);
+        editor.expire_status_message(start + Duration::from_secs(1));
+        assert_eq!(editor.status_message.as_deref(), Some("Saved."));
+
+        // a new message restarts the clock
+        editor.status_message = Some("Line deleted".to_string());
+        editor.expire_status_message(start + Duration::from_secs(3));
+        editor.expire_status_message(start + Duration::from_secs(5));
+        assert!(editor.status_message.is_some());
+        editor.expire_status_message(start + Duration::from_secs(7));
+        assert_eq!(editor.status_message, None);
+        assert!(editor.compose_frame(40, 3).row_text(2).ends_with("Ln 1 Col 1  All"));
+
+        editor.status_timeout = None;
+        editor.status_message = Some("Saved.".to_string());
+        editor.expire_status_message(start);
+        editor.expire_status_message(start + Duration::from_secs(60));
+        assert!(editor.status_message.is_some());
+    }
+
+    #[test]
+    fn test_tick_reports_when_the_screen_changes() {
+        let mut editor = editor_with(&["text"]);
+        let start = Instant::now();
+        assert!(!editor.tick(start));
+        editor.status_message = Some("note".to_string());
+        assert!(!editor.tick(start));
+        assert!(!editor.tick(start + Duration::from_secs(1)));
+        assert!(editor.tick(start + Duration::from_secs(5)));
+        assert!(!editor.tick(start + Duration::from_secs(6)));
+    }
+
+    #[test]
+    fn test_swap_file_is_claimed_and_released() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.txt").display().to_string();
+        std::fs::write(&path, "text").unwrap();
+        let mut editor = Editor::with_buffer(Buffer::from_file(Some(path.clone())).unwrap());
+        editor.claim_swap();
+        assert!(!swap::swap_path(&path).exists());
+
+        editor.swap_file = true;
+        editor.claim_swap();
+        assert_eq!(swap::owner(&path).map(|owner| owner.pid), Some(process::id()));
+        editor.release_swaps();
+        assert!(!swap::swap_path(&path).exists());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_live_swap_file_asks_before_editing() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.txt").display().to_string();
+        std::fs::write(&path, "text").unwrap();
+        let other = std::os::unix::process::parent_id();
+        std::fs::write(swap::swap_path(&path), format!("{}\n0\n", other)).unwrap();
+        let mut editor = Editor::with_buffer(Buffer::from_file(Some(path.clone())).unwrap());
+        editor.swap_file = true;
+        editor.claim_swap();
+        assert_eq!(
+            editor.status_message.as_deref(),
+            Some("Swap file exists — (O)pen read-only, (E)dit anyway, (Q)uit")
+        );
+        assert_eq!(editor.handle_event(key(KeyCode::Char('d'), KeyModifiers::NONE)), None);
+        assert_eq!(editor.handle_event(key(KeyCode::Char('i'), KeyModifiers::NONE)), None);
+
+        press(&mut editor, key(KeyCode::Char('o'), KeyModifiers::NONE));
+        assert!(editor.read_only);
+        assert_eq!(swap::owner(&path).map(|owner| owner.pid), Some(other));
+        editor.release_swaps();
+        assert!(swap::swap_path(&path).exists());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_quit_at_swap_prompt_goes_back_to_the_previous_buffer() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = dir.path().join("first.txt").display().to_string();
+        let second = dir.path().join("second.txt").display().to_string();
+        std::fs::write(&first, "one").unwrap();
+        std::fs::write(&second, "two").unwrap();
+        std::fs::write(swap::swap_path(&second), format!("{}\n0\n", std::os::unix::process::parent_id())).unwrap();
+        let mut editor = Editor::with_buffer(Buffer::from_file(Some(first.clone())).unwrap());
+        editor.swap_file = true;
+        editor.claim_swap();
+        press_keys(&mut editor, "x");
+
+        run_command(&mut editor, &format!("e {}", second));
+        assert_eq!(
+            editor.status_message.as_deref(),
+            Some("Swap file exists — (O)pen read-only, (E)dit anyway, (Q) go back")
+        );
+        press(&mut editor, key(KeyCode::Char('q'), KeyModifiers::NONE));
+        assert!(!editor.quit);
+        assert_eq!(editor.buffer.lines, vec!["ne"]);
+        assert_eq!(editor.buffers.len(), 1);
+
+        editor.add_buffer(Buffer::from_file(Some(second.clone())).unwrap());
+        run_command(&mut editor, "bn");
+        press(&mut editor, key(KeyCode::Char('Q'), KeyModifiers::SHIFT));
+        assert!(!editor.quit);
+        assert_eq!(editor.buffer.display_name(), first);
+        assert_eq!(editor.buffers.len(), 2);
+        editor.release_swaps();
+    }
+
+    #[test]
+    fn test_stale_swap_file_can_be_deleted() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.txt").display().to_string();
+        std::fs::write(&path, "text").unwrap();
+        std::fs::write(swap::swap_path(&path), "not a pid").unwrap();
+        let mut editor = Editor::with_buffer(Buffer::from_file(Some(path.clone())).unwrap());
+        editor.swap_file = true;
+        editor.claim_swap();
+        assert!(editor.status_message.as_deref().unwrap().starts_with("Stale swap file from process 0"));
+
+        press(&mut editor, key(KeyCode::Char('d'), KeyModifiers::NONE));
+        assert_eq!(swap::owner(&path).map(|owner| owner.pid), Some(process::id()));
+        assert!(!editor.read_only);
+        press(&mut editor, key(KeyCode::Char('i'), KeyModifiers::NONE));
+        assert_eq!(editor.mode, Mode::Insert);
+        editor.release_swaps();
+        assert!(!swap::swap_path(&path).exists());
+    }
+
+    #[test]
+    fn test_autosave_writes_modified_buffer_after_the_interval() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut editor = editor_for_file(&dir, "notes.txt", "one");
+        editor.autosave_interval = Some(Duration::from_secs(30));
+        let start = Instant::now();
+        assert!(!editor.tick(start));
+
+        press_keys(&mut editor, "x");
+        assert!(!editor.tick(start + Duration::from_secs(10)));
+        assert!(editor.tick(start + Duration::from_secs(30)));
+        assert_eq!(std::fs::read_to_string(dir.path().join("notes.txt")).unwrap(), "ne");
+        assert_eq!(editor.status_message.as_deref(), Some("autosaved"));
+        assert!(!editor.buffer.modified);
+
+        press_keys(&mut editor, "x");
+        assert!(editor.buffer.modified);
+        assert!(!editor.tick(start + Duration::from_secs(40)));
+    }
+
+    #[test]
+    fn test_autosave_keeps_an_unnamed_buffer_under_the_vix_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let cwd = std::env::current_dir().unwrap();
+        let mut editor = editor_with(&["one"]);
+        let swap = dir.path().join(".vix/unnamed.swp");
+        editor.unnamed_autosave = Some(swap.clone());
+        editor.autosave_interval = Some(Duration::from_secs(30));
+        let start = Instant::now();
+        editor.tick(start);
+        press_keys(&mut editor, "x");
+        assert!(editor.tick(start + Duration::from_secs(30)));
+        assert_eq!(editor.status_message.as_deref(), Some("autosaved"));
+        assert_eq!(std::fs::read_to_string(&swap).unwrap(), "ne");
+        assert!(!cwd.join(".unnamed.recovery").exists());
+        assert!(editor.buffer.modified);
+        assert_eq!(editor.buffer.file, None);
+
+        editor.release_swaps();
+        assert!(!swap.exists());
+    }
+
+    #[test]
+    fn test_undo_after_an_autosave_marks_the_buffer_modified() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut editor = editor_for_file(&dir, "notes.txt", "one\n");
+        editor.autosave_interval = Some(Duration::from_secs(30));
+        let start = Instant::now();
+        editor.tick(start);
+        press_keys(&mut editor, "iX");
+        assert!(editor.tick(start + Duration::from_secs(30)));
+        assert!(!editor.buffer.modified);
+        press_keys(&mut editor, "Y");
+        press(&mut editor, key(KeyCode::Esc, KeyModifiers::NONE));
+        press_keys(&mut editor, "u");
+        assert_eq!(editor.buffer.lines, vec!["one"]);
+        assert!(editor.buffer.modified);
+        assert_eq!(std::fs::read_to_string(dir.path().join("notes.txt")).unwrap(), "Xone\n");
+    }
+
+    #[test]
+    fn test_autosave_reports_a_failed_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut editor = editor_for_file(&dir, "notes.txt", "one");
+        editor.buffer.file = Some(dir.path().join("missing/notes.txt").display().to_string());
+        editor.autosave_interval = Some(Duration::from_secs(30));
+        let start = Instant::now();
+        editor.tick(start);
+        press_keys(&mut editor, "x");
+        assert!(editor.tick(start + Duration::from_secs(30)));
+        assert!(editor.status_message.unwrap().starts_with("Autosave failed"));
+        assert!(editor.buffer.modified);
+    }
+
+    #[test]
+    fn test_read_only_refuses_edits_but_allows_movement() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut editor = editor_for_file(&dir, "notes.txt", "one\ntwo");
+        editor.read_only = true;
+        press_keys(&mut editor, "jxiddw");
+        assert_eq!(editor.buffer.lines, vec!["one", "two"]);
+        assert_eq!((editor.cy, editor.mode), (1, Mode::Normal));
+        assert_eq!(editor.status_message.as_deref(), Some("Read-only (use :w! to write anyway)"));
+        assert!(editor.compose_frame(60, 3).row_text(2).starts_with(&format!(
+            "NORMAL > {} [RO] >",
+            dir.path().join("notes.txt").display()
+        )));
+
+        editor.buffer.lines[0] = "changed".to_string();
+        run_command(&mut editor, "w");
+        assert_eq!(std::fs::read_to_string(dir.path().join("notes.txt")).unwrap(), "one\ntwo");
+        run_command(&mut editor, "w!");
+        assert_eq!(std::fs::read_to_string(dir.path().join("notes.txt")).unwrap(), "changed\ntwo");
+    }
+
+    #[test]
+    fn test_unknown_command_reports_error() {
+        let mut editor = editor_with(&["text"]);
+        run_command(&mut editor, "bogus");
+        assert_eq!(editor.buffer.lines, vec!["text"]);
+        assert_eq!(editor.status_message.as_deref(), Some("Not an editor command: bogus"));
+    }
+
+    #[test]
+    fn test_capital_j_joins_next_line() {
+        let mut editor = editor_with(&["let x =", "    1;"]);
+        press(&mut editor, key(KeyCode::Char('J'), KeyModifiers::SHIFT));
+        assert_eq!(editor.buffer.lines, vec!["let x = 1;"]);
+        assert_eq!((editor.cy, editor.cx), (0, 7));
+
+        press(&mut editor, key(KeyCode::Char('J'), KeyModifiers::SHIFT));
+        assert_eq!(editor.buffer.lines, vec!["let x = 1;"]);
+
+        editor.apply_action(Actions::Undo);
+        assert_eq!(editor.buffer.lines, vec!["let x =", "    1;"]);
     }
 }
-               
@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use log::debug;
+
+use crate::editor::{Actions, Mode};
+
+/// A key as the keymap sees it. Shift is folded into the character itself,
+/// so `G` is stored without a modifier.
+type Key = (KeyCode, KeyModifiers);
+
+/// Every remappable Normal-mode action: its name in the config file, its
+/// default key, and the action itself.
+fn default_bindings() -> Vec<(&'static str, Key, Actions)> {
+    let key = |c| (KeyCode::Char(c), KeyModifiers::NONE);
+    let ctrl = |c| (KeyCode::Char(c), KeyModifiers::CONTROL);
+    vec![
+        ("move_left", key('h'), Actions::MoveLeft),
+        ("move_down", key('j'), Actions::MoveDown),
+        ("move_up", key('k'), Actions::MoveUp),
+        ("move_right", key('l'), Actions::MoveRight),
+        ("word_forward", key('w'), Actions::MoveWordForward),
+        ("word_backward", key('b'), Actions::MoveWordBackward),
+        ("word_end", key('e'), Actions::MoveWordEnd),
+        ("line_start", key('0'), Actions::MoveLineStart),
+        ("line_end", key('$'), Actions::MoveLineEnd),
+        ("first_non_blank", key('^'), Actions::MoveFirstNonBlank),
+        ("goto_prefix", key('g'), Actions::PendingG),
+        ("goto_last_line", key('G'), Actions::GotoLastLine),
+        ("scroll_prefix", key('z'), Actions::PendingZ),
+        ("match_bracket", key('%'), Actions::MatchBracket),
+        ("find_char", key('f'), Actions::AwaitFind { forward: true, till: false }),
+        ("find_char_backward", key('F'), Actions::AwaitFind { forward: false, till: false }),
+        ("till_char", key('t'), Actions::AwaitFind { forward: true, till: true }),
+        ("till_char_backward", key('T'), Actions::AwaitFind { forward: false, till: true }),
+        ("repeat_find", key(';'), Actions::RepeatFind(false)),
+        ("repeat_find_reverse", key(','), Actions::RepeatFind(true)),
+        ("jump_back", ctrl('o'), Actions::JumpBack),
+        // terminals send Ctrl-i as Tab
+        ("jump_forward", (KeyCode::Tab, KeyModifiers::NONE), Actions::JumpForward),
+        ("insert", key('i'), Actions::EnterMode(Mode::Insert)),
+        ("append", key('a'), Actions::Append),
+        ("append_line_end", key('A'), Actions::AppendLineEnd),
+        ("insert_line_start", key('I'), Actions::InsertLineStart),
+        ("open_line_below", key('o'), Actions::OpenLineBelow),
+        ("open_line_above", key('O'), Actions::OpenLineAbove),
+        ("visual", key('v'), Actions::EnterMode(Mode::Visual)),
+        ("quit", key('q'), Actions::QuitKey),
+        ("record_macro", key('Q'), Actions::PendingMacro('Q')),
+        ("play_macro", key('@'), Actions::PendingMacro('@')),
+        ("delete_char", key('x'), Actions::DeleteChar),
+        ("join_lines", key('J'), Actions::JoinLine),
+        ("toggle_case", key('~'), Actions::ToggleCase),
+        ("replace_char", key('r'), Actions::AwaitReplace),
+        ("replace_mode", key('R'), Actions::EnterMode(Mode::Replace)),
+        ("delete_operator", key('d'), Actions::PendingOperator('d')),
+        ("yank_operator", key('y'), Actions::PendingOperator('y')),
+        ("change_operator", key('c'), Actions::PendingOperator('c')),
+        ("indent_operator", key('>'), Actions::PendingOperator('>')),
+        ("dedent_operator", key('<'), Actions::PendingOperator('<')),
+        ("register", key('"'), Actions::AwaitRegister),
+        ("paste", key('p'), Actions::Paste),
+        ("paste_before", key('P'), Actions::PasteBefore),
+        ("repeat", key('.'), Actions::RepeatChange),
+        ("undo", key('u'), Actions::Undo),
+        ("redo", ctrl('r'), Actions::Redo),
+        ("save", ctrl('s'), Actions::Save),
+        ("save_as", ctrl('S'), Actions::PromptSaveAs),
+        ("delete_line", ctrl('d'), Actions::DeleteLine),
+        ("command", key(':'), Actions::EnterMode(Mode::Command)),
+        ("search_word_forward", key('*'), Actions::SearchWordForward),
+        ("search_word_backward", key('#'), Actions::SearchWordBackward),
+        ("search_next", key('n'), Actions::SearchNext(false)),
+        ("search_previous", key('N'), Actions::SearchNext(true)),
+        ("file_info", ctrl('g'), Actions::FileInfo),
+        ("help", (KeyCode::F(1), KeyModifiers::NONE), Actions::ShowHelp),
+    ]
+}
+
+/// Parses a key as written in the config file: a single character such as
+/// `h`, or a bracketed name such as `<C-s>`, `<Space>`, `<Tab>` or `<F1>`.
+pub fn parse_key(text: &str) -> Option<Key> {
+    let mut chars = text.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Some((KeyCode::Char(c), KeyModifiers::NONE));
+    }
+    let name = text.strip_prefix('<')?.strip_suffix('>')?;
+    if let Some(rest) = name.strip_prefix("C-") {
+        let (code, modifiers) = parse_key(rest).or_else(|| parse_key(&format!("<{}>", rest)))?;
+        return Some((code, modifiers | KeyModifiers::CONTROL));
+    }
+    let code = match name.to_ascii_lowercase().as_str() {
+        "space" => KeyCode::Char(' '),
+        "tab" => KeyCode::Tab,
+        "enter" | "cr" => KeyCode::Enter,
+        "esc" => KeyCode::Esc,
+        "bs" | "backspace" => KeyCode::Backspace,
+        lower if let Some(n) = lower.strip_prefix('f').and_then(|n| n.parse().ok()) => KeyCode::F(n),
+        _ => return None,
+    };
+    Some((code, KeyModifiers::NONE))
+}
+
+/// How a key is written in the config file, the reverse of `parse_key`.
+pub fn key_name((code, modifiers): Key) -> String {
+    let name = match code {
+        KeyCode::Char(' ') => "Space".to_string(),
+        KeyCode::Char(c) if modifiers.is_empty() => return c.to_string(),
+        KeyCode::Char(c) => c.to_string(),
+        KeyCode::Tab => "Tab".to_string(),
+        KeyCode::Enter => "Enter".to_string(),
+        KeyCode::Esc => "Esc".to_string(),
+        KeyCode::Backspace => "BS".to_string(),
+        KeyCode::F(n) => format!("F{}", n),
+        other => format!("{:?}", other),
+    };
+    let ctrl = if modifiers.contains(KeyModifiers::CONTROL) { "C-" } else { "" };
+    format!("<{}{}>", ctrl, name)
+}
+
+#[derive(Debug, Clone)]
+pub struct Keymap {
+    bindings: HashMap<Key, Actions>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let bindings = default_bindings()
+            .into_iter()
+            .map(|(_, key, action)| (key, action))
+            .collect();
+        Self { bindings }
+    }
+}
+
+impl Keymap {
+    /// The action bound to a key press, if any.
+    pub fn lookup(&self, key: KeyEvent) -> Option<Actions> {
+        let mut modifiers = key.modifiers;
+        if matches!(key.code, KeyCode::Char(_)) {
+            modifiers.remove(KeyModifiers::SHIFT);
+        }
+        self.bindings.get(&(key.code, modifiers)).cloned()
+    }
+
+    /// Every action that has a key, by name, in the order they are listed
+    /// in the defaults.
+    pub fn bound_keys(&self) -> Vec<(&'static str, Key)> {
+        default_bindings()
+            .into_iter()
+            .filter_map(|(name, _, action)| {
+                let (key, _) = self.bindings.iter().find(|(_, bound)| **bound == action)?;
+                Some((name, *key))
+            })
+            .collect()
+    }
+
+    /// Moves the action called `name` onto `key`, replacing whatever `key`
+    /// did before. The action's old key is left unbound.
+    pub fn bind(&mut self, name: &str, key: &str) -> Result<(), String> {
+        let action = default_bindings()
+            .into_iter()
+            .find(|(known, _, _)| *known == name)
+            .map(|(_, _, action)| action)
+            .ok_or_else(|| format!("Unknown action: {}", name))?;
+        let key = parse_key(key).ok_or_else(|| format!("Invalid key for {}: {}", name, key))?;
+        debug!("Binding {:?} to {}", key, name);
+        self.bindings.retain(|_, bound| *bound != action);
+        self.bindings.insert(key, action);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(keymap: &Keymap, code: KeyCode, modifiers: KeyModifiers) -> Option<Actions> {
+        keymap.lookup(KeyEvent::new(code, modifiers))
+    }
+
+    #[test]
+    fn test_default_keys_ignore_shift_on_characters() {
+        let keymap = Keymap::default();
+        assert_eq!(press(&keymap, KeyCode::Char('h'), KeyModifiers::NONE), Some(Actions::MoveLeft));
+        assert_eq!(press(&keymap, KeyCode::Char('G'), KeyModifiers::SHIFT), Some(Actions::GotoLastLine));
+        assert_eq!(
+            press(&keymap, KeyCode::Char('S'), KeyModifiers::CONTROL | KeyModifiers::SHIFT),
+            Some(Actions::PromptSaveAs)
+        );
+        assert_eq!(press(&keymap, KeyCode::Char('h'), KeyModifiers::ALT), None);
+    }
+
+    #[test]
+    fn test_bind_moves_an_action_to_a_new_key() {
+        let mut keymap = Keymap::default();
+        keymap.bind("move_left", "<C-h>").unwrap();
+        assert_eq!(press(&keymap, KeyCode::Char('h'), KeyModifiers::NONE), None);
+        assert_eq!(press(&keymap, KeyCode::Char('h'), KeyModifiers::CONTROL), Some(Actions::MoveLeft));
+
+        keymap.bind("save", "l").unwrap();
+        assert_eq!(press(&keymap, KeyCode::Char('l'), KeyModifiers::NONE), Some(Actions::Save));
+        assert!(keymap.bind("fly", "f").is_err());
+        assert!(keymap.bind("save", "<Nope>").is_err());
+    }
+
+    #[test]
+    fn test_key_name_reverses_parse_key() {
+        for text in ["x", "<C-s>", "<Space>", "<Tab>", "<C-Tab>", "<F1>", "<Esc>"] {
+            assert_eq!(key_name(parse_key(text).unwrap()), text);
+        }
+    }
+
+    #[test]
+    fn test_bound_keys_follow_rebinding() {
+        let mut keymap = Keymap::default();
+        assert_eq!(keymap.bound_keys()[0], ("move_left", (KeyCode::Char('h'), KeyModifiers::NONE)));
+        keymap.bind("save", "h").unwrap();
+        let bound = keymap.bound_keys();
+        assert!(!bound.iter().any(|(name, _)| *name == "move_left"));
+        assert!(bound.contains(&("save", (KeyCode::Char('h'), KeyModifiers::NONE))));
+    }
+
+    #[test]
+    fn test_parse_key() {
+        assert_eq!(parse_key("x"), Some((KeyCode::Char('x'), KeyModifiers::NONE)));
+        assert_eq!(parse_key("<C-s>"), Some((KeyCode::Char('s'), KeyModifiers::CONTROL)));
+        assert_eq!(parse_key("<Space>"), Some((KeyCode::Char(' '), KeyModifiers::NONE)));
+        assert_eq!(parse_key("<C-Tab>"), Some((KeyCode::Tab, KeyModifiers::CONTROL)));
+        assert_eq!(parse_key("<F1>"), Some((KeyCode::F(1), KeyModifiers::NONE)));
+        assert_eq!(parse_key(""), None);
+        assert_eq!(parse_key("ab"), None);
+    }
+}
@@ -1,33 +1,51 @@
 use std::fs::{self, File, OpenOptions};
-use std::path::PathBuf;
-use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::io::{BufWriter, Write};
+use std::sync::Mutex;
 use log::{Level, LevelFilter, Metadata, Record};
 use chrono::Local;
 use anyhow::Result;
 
+/// Environment variable holding the log level: `error`, `warn`, `info`,
+/// `debug`, `trace` or `off`.
+const LEVEL_ENV: &str = "VIX_LOG";
+
+/// The level named by `VIX_LOG`, or `Info` when it is unset or not a level.
+fn level_from_env(value: Option<&str>) -> LevelFilter {
+    value
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(LevelFilter::Info)
+}
+
 pub struct FileLogger {
-    log_file: File,
+    /// One buffered handle for the whole run, flushed on `flush()` and after
+    /// every warning or error.
+    log_file: Mutex<BufWriter<File>>,
+    level: LevelFilter,
 }
 
 impl FileLogger {
-    pub fn init(log_path: impl Into<PathBuf>) -> Result<()> {
-        let path = log_path.into();
-        
+    fn new(path: &Path, level: LevelFilter) -> Result<Self> {
         // Create log directory if it doesn't exist
         if let Some(parent) = path.parent() {
             fs::create_dir_all(parent)?;
         }
 
-        let logger = FileLogger {
-            log_file: OpenOptions::new()
-                .create(true)
-                .write(true)
-                .append(true)
-                .open(&path)?,
-        };
+        Ok(FileLogger {
+            log_file: Mutex::new(BufWriter::new(
+                OpenOptions::new().create(true).append(true).open(path)?,
+            )),
+            level,
+        })
+    }
+
+    pub fn init(log_path: impl Into<PathBuf>) -> Result<()> {
+        let path = log_path.into();
+        let level = level_from_env(std::env::var(LEVEL_ENV).ok().as_deref());
+        let logger = FileLogger::new(&path, level)?;
 
         log::set_boxed_logger(Box::new(logger))?;
-        log::set_max_level(LevelFilter::Debug);
+        log::set_max_level(level);
         Ok(())
     }
 
@@ -46,20 +64,25 @@ impl FileLogger {
 
 impl log::Log for FileLogger {
     fn enabled(&self, metadata: &Metadata) -> bool {
-        metadata.level() <= Level::Debug
+        metadata.level() <= self.level
     }
 
     fn log(&self, record: &Record) {
         if self.enabled(record.metadata()) {
             let log_entry = self.format_record(record);
-            let _ = self.log_file.try_clone().and_then(|mut file| {
-                file.write_all(log_entry.as_bytes())
-            });
+            if let Ok(mut file) = self.log_file.lock() {
+                let _ = file.write_all(log_entry.as_bytes());
+                if record.level() <= Level::Warn {
+                    let _ = file.flush();
+                }
+            }
         }
     }
 
     fn flush(&self) {
-        let _ = self.log_file.try_clone().and_then(|mut file| file.flush());
+        if let Ok(mut file) = self.log_file.lock() {
+            let _ = file.flush();
+        }
     }
 }
 
@@ -83,4 +106,54 @@ mod tests {
         assert!(log_contents.contains("Test log message"));
         assert!(log_contents.contains("Test warning message"));
     }
+
+    #[test]
+    fn test_many_lines_are_written_in_order() {
+        use log::Log;
+        let temp_dir = tempdir().unwrap();
+        let log_path = temp_dir.path().join("burst.log");
+        let logger = FileLogger::new(&log_path, LevelFilter::Debug).unwrap();
+
+        for i in 0..1000 {
+            logger.log(&Record::builder().level(Level::Debug).args(format_args!("line {}", i)).build());
+        }
+        logger.flush();
+
+        let log_contents = fs::read_to_string(&log_path).unwrap();
+        let numbers: Vec<usize> = log_contents
+            .lines()
+            .map(|line| line.rsplit(' ').next().unwrap().parse().unwrap())
+            .collect();
+        assert_eq!(numbers, (0..1000).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_level_from_env() {
+        assert_eq!(level_from_env(None), LevelFilter::Info);
+        assert_eq!(level_from_env(Some("warn")), LevelFilter::Warn);
+        assert_eq!(level_from_env(Some("TRACE")), LevelFilter::Trace);
+        assert_eq!(level_from_env(Some("loud")), LevelFilter::Info);
+    }
+
+    #[test]
+    fn test_messages_below_level_are_not_written() {
+        use log::Log;
+        let temp_dir = tempdir().unwrap();
+        let log_path = temp_dir.path().join("warn.log");
+        let logger = FileLogger::new(&log_path, LevelFilter::Warn).unwrap();
+
+        for (level, message) in [(log::Level::Info, "quiet info"), (log::Level::Warn, "loud warning")] {
+            logger.log(
+                &Record::builder()
+                    .level(level)
+                    .args(format_args!("{}", message))
+                    .build(),
+            );
+        }
+        logger.flush();
+
+        let log_contents = fs::read_to_string(&log_path).unwrap();
+        assert!(!log_contents.contains("quiet info"));
+        assert!(log_contents.contains("loud warning"));
+    }
 }
@@ -1,19 +1,31 @@
 use std::io::{self, stdout};
-use std::panic;
+use std::panic::{self, AssertUnwindSafe};
 use std::process;
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
 use anyhow::Result;
-use crossterm::event::{read, Event, KeyCode};
+use crossterm::event::{
+    poll, read, DisableBracketedPaste, DisableMouseCapture, EnableBracketedPaste, EnableMouseCapture, Event,
+};
 use crossterm::{terminal, ExecutableCommand};
 use log::{debug, error, info, warn};
 use dirs::home_dir;
 
 mod editor;
-use editor::{Editor, Mode};
+use editor::Editor;
 
 mod buffer;
+mod clipboard;
+mod completion;
+mod config;
+mod keymap;
 mod logger;
+mod positions;
+mod screen;
+mod swap;
+mod syntax;
+mod undo;
 
 static PANIC_CLEANUP: AtomicBool = AtomicBool::new(false);
 
@@ -21,33 +33,216 @@ fn cleanup() -> io::Result<()> {
     if !PANIC_CLEANUP.swap(true, Ordering::SeqCst) {
         debug!("Performing terminal cleanup");
         terminal::disable_raw_mode()?;
+        stdout().execute(DisableMouseCapture)?;
+        stdout().execute(DisableBracketedPaste)?;
         stdout().execute(terminal::LeaveAlternateScreen)?;
         info!("Terminal cleanup completed");
+        log::logger().flush();
     } else {
         warn!("Cleanup already performed, skipping");
     }
     Ok(())
 }
 
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Args {
+    /// Files to open, each in its own buffer; the first one is shown. `-`
+    /// reads stdin into an unnamed buffer.
+    files: Vec<String>,
+    scratch: bool,
+    /// `-R`: browse without being able to change the file.
+    read_only: bool,
+    /// `|`-separated commands to run without a terminal before exiting.
+    exec: Option<String>,
+    /// Line to put the cursor on in the first file, from `+42` or `file:42`.
+    line: Option<usize>,
+    /// `--help`: print the keys and commands instead of editing.
+    help: bool,
+    /// `--insert`: start in Insert mode, for editing without thinking in modes.
+    insert: bool,
+}
+
+/// A 1-based line number given on the command line. Anything that isn't one
+/// is reported and treated as line 1.
+fn parse_line_number(text: &str) -> usize {
+    match text.parse() {
+        Ok(line) if line > 0 => line,
+        _ => {
+            warn!("Invalid line number {:?}, using line 1", text);
+            1
+        }
+    }
+}
+
+/// Splits `file:42` into the file and the line. A suffix that isn't all
+/// digits is part of the file name.
+fn split_file_line(arg: &str) -> (String, Option<usize>) {
+    match arg.rsplit_once(':') {
+        Some((file, line)) if !file.is_empty() && !line.is_empty() && line.bytes().all(|b| b.is_ascii_digit()) => {
+            (file.to_string(), Some(parse_line_number(line)))
+        }
+        _ => (arg.to_string(), None),
+    }
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args> {
+    let mut parsed = Args::default();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--scratch" => parsed.scratch = true,
+            "-R" => parsed.read_only = true,
+            "--insert" => parsed.insert = true,
+            "-h" | "--help" => parsed.help = true,
+            "--exec" => {
+                let commands = args
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("--exec requires a command string"))?;
+                parsed.exec = Some(commands);
+            }
+            _ if arg.starts_with('+') => parsed.line = Some(parse_line_number(&arg[1..])),
+            // stdin can only be read once
+            "-" if parsed.files.iter().any(|file| file == "-") => anyhow::bail!("- (stdin) can only be given once"),
+            _ => {
+                let (file, line) = split_file_line(&arg);
+                if parsed.files.is_empty() {
+                    parsed.line = line.or(parsed.line);
+                }
+                parsed.files.push(file);
+            }
+        }
+    }
+    Ok(parsed)
+}
+
+fn open_buffer(file: &str) -> Result<buffer::Buffer> {
+    if file == "-" {
+        info!("Reading buffer from stdin");
+        return Ok(buffer::Buffer::from_reader(io::stdin().lock(), None)?);
+    }
+    Ok(buffer::Buffer::from_file(Some(file.to_string()))?)
+}
+
+fn open_editor(args: &Args) -> Result<Editor> {
+    debug!("Opening files: {:?}", args.files);
+    if args.scratch {
+        return Ok(Editor::with_buffer(buffer::Buffer::scratch()));
+    }
+    let Some((first, rest)) = args.files.split_first() else {
+        return Ok(Editor::new());
+    };
+    let mut editor = Editor::with_buffer(open_buffer(first)?);
+    for file in rest {
+        editor.add_buffer(open_buffer(file)?);
+    }
+    Ok(editor)
+}
+
+/// Runs `f` against the editor. If it panics, any unsaved changes are written
+/// to a recovery file before the process exits.
+fn with_recovery<T>(editor: &mut Editor, f: impl FnOnce(&mut Editor) -> T) -> T {
+    match panic::catch_unwind(AssertUnwindSafe(|| f(editor))) {
+        Ok(value) => value,
+        Err(_) => {
+            editor.try_save_recovery();
+            log::logger().flush();
+            process::exit(1);
+        }
+    }
+}
+
+/// How long to wait for an event before doing periodic work.
+const TICK: Duration = Duration::from_millis(100);
+
+fn run(editor: &mut Editor) -> Result<()> {
+    let mut stdout = stdout();
+    editor.render(&mut stdout)?;
+
+    loop {
+        let mut changed = false;
+        if poll(TICK)? {
+            let ev = read()?;
+            match ev {
+                Event::Key(_) | Event::Mouse(_) | Event::Paste(_) => {
+                    if let Event::Key(key) = ev {
+                        debug!("Key event received: {:?}", key);
+                    }
+                    if editor.process_event(ev) {
+                        if editor.quit {
+                            info!("Quit command received, exiting editor");
+                            return Ok(());
+                        }
+                        changed = true;
+                    }
+                }
+                Event::Resize(w, h) => {
+                    debug!("Terminal resized to {}x{}", w, h);
+                    editor.force_redraw = true;
+                    changed = true;
+                }
+                _ => {
+                    debug!("Non-key event received: {:?}", ev);
+                }
+            }
+        }
+        changed |= editor.tick(Instant::now());
+        if changed {
+            editor.render(&mut stdout)?;
+        }
+    }
+}
+
 fn main() -> Result<()> {
     // Initialize logger with log file in user's home directory
-    let log_path = home_dir()
+    let vix_dir = home_dir()
         .ok_or_else(|| anyhow::anyhow!("Could not find home directory"))?
-        .join(".vix")
-        .join("vix.log");
-    
-    logger::FileLogger::init(log_path)?;
+        .join(".vix");
+
+    logger::FileLogger::init(vix_dir.join("vix.log"))?;
     info!("Starting vix editor");
-    
-    let mut stdout = stdout();
+
+    let args = parse_args(std::env::args().skip(1))?;
+    if args.help {
+        println!("usage: vix [-R] [--insert] [--scratch] [--exec COMMANDS] [+LINE] [FILE[:LINE] | -]...\n");
+        println!("With --insert, typing edits straight away; press Esc, then :q to quit.\n");
+        for line in editor::help_lines(&keymap::Keymap::default()) {
+            println!("{}", line);
+        }
+        return Ok(());
+    }
+    let mut editor = open_editor(&args)?;
+    if args.read_only {
+        editor.make_all_read_only();
+    }
+    editor.positions = Some(positions::Positions::load(&vix_dir.join("positions.json")));
+    editor.unnamed_autosave = Some(vix_dir.join(format!("unnamed-{}.swp", std::process::id())));
+    match args.line {
+        Some(line) => editor.goto_line(line),
+        None => editor.restore_positions(),
+    }
+    config::Config::load(&vix_dir.join("config.toml")).apply(&mut editor);
+    editor.run_startup_commands(&config::load_rc(&vix_dir.join(".vixrc")));
+    if args.insert {
+        editor.apply_action(editor::Actions::EnterMode(editor::Mode::Insert));
+    }
+    editor.clipboard = clipboard::system();
+
+    if let Some(commands) = &args.exec {
+        info!("Running commands non-interactively: {}", commands);
+        let result = with_recovery(&mut editor, |editor| editor.run_commands(commands));
+        log::logger().flush();
+        return result.map_err(|message| anyhow::anyhow!(message));
+    }
+
+    editor.claim_swap();
+
     debug!("Initializing terminal in raw mode");
     terminal::enable_raw_mode()?;
-    stdout.execute(terminal::EnterAlternateScreen)?;
+    stdout().execute(terminal::EnterAlternateScreen)?;
+    stdout().execute(EnableBracketedPaste)?;
+    if editor.mouse {
+        stdout().execute(EnableMouseCapture)?;
+    }
 
-    let file = std::env::args().nth(1);
-    debug!("Opening file: {:?}", file);
-    let buffer = buffer::Buffer::from_file(file)?;
-    let mut editor = Editor::with_buffer(buffer);
     let original_hook = panic::take_hook();
     panic::set_hook(Box::new(move |panic_info| {
         error!("Panic occurred: {}", panic_info);
@@ -55,35 +250,120 @@ fn main() -> Result<()> {
             error!("Error during cleanup: {}", e);
         }
         original_hook(panic_info);
-        process::exit(1);
     }));
 
-    editor.render(&mut stdout)?;
+    // the hook runs while the editor is still borrowed by the loop, so the
+    // recovery file is written once the panic has unwound back to here
+    with_recovery(&mut editor, run)?;
+    editor.remember_positions();
+    editor.release_swaps();
 
-    'outer: loop {
-        let ev = read()?;
-        match ev {
-            Event::Key(key) => {
-                debug!("Key event received: {:?}", key);
-                if editor.mode == Mode::Normal {
-                    if let KeyCode::Char('q') = key.code {
-                        info!("Quit command received, exiting editor");
-                        break 'outer;
-                    }
-                }
+    cleanup()?;
+    Ok(())
+}
 
-                if let Some(action) = editor.handle_event(ev) {
-                    debug!("Applying editor action");
-                    editor.apply_action(action);
-                    editor.render(&mut stdout)?;
-                }
-            }
-            _ => {
-                debug!("Non-key event received: {:?}", ev);
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Result<Args> {
+        parse_args(list.iter().map(|s| s.to_string()))
+    }
+
+    #[test]
+    fn test_parse_exec_flag() {
+        let parsed = args(&["--exec", "stripws | w | q", "notes.txt"]).unwrap();
+        assert_eq!(parsed.exec.as_deref(), Some("stripws | w | q"));
+        assert_eq!(parsed.files, vec!["notes.txt"]);
+        assert!(!parsed.scratch);
+    }
+
+    /// Set in the child process spawned by `test_panic_writes_recovery_file`.
+    const PANIC_CHILD_ENV: &str = "VIX_TEST_PANIC_FILE";
+
+    #[test]
+    fn test_panic_writes_recovery_file() {
+        if let Ok(path) = std::env::var(PANIC_CHILD_ENV) {
+            let mut editor = Editor::with_buffer(buffer::Buffer::from_file(Some(path)).unwrap());
+            editor.buffer.insert_char(0, 0, '!').unwrap();
+            with_recovery::<()>(&mut editor, |_| panic!("simulated crash"));
+            return;
         }
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.txt");
+        std::fs::write(&path, "saved").unwrap();
+        let output = std::process::Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "tests::test_panic_writes_recovery_file"])
+            .env(PANIC_CHILD_ENV, &path)
+            .output()
+            .unwrap();
+
+        assert!(!output.status.success());
+        let recovered = std::fs::read_to_string(dir.path().join("notes.txt.recovery")).unwrap();
+        assert_eq!(recovered, "!saved");
     }
 
-    cleanup()?;
-    Ok(())
+    #[test]
+    fn test_parse_plus_line_argument() {
+        let parsed = args(&["+42", "src/main.rs"]).unwrap();
+        assert_eq!(parsed.files, vec!["src/main.rs"]);
+        assert_eq!(parsed.line, Some(42));
+        assert_eq!(args(&["notes.txt", "+7"]).unwrap().line, Some(7));
+        assert_eq!(args(&["+x", "notes.txt"]).unwrap().line, Some(1));
+        assert_eq!(args(&["+0", "notes.txt"]).unwrap().line, Some(1));
+    }
+
+    #[test]
+    fn test_parse_file_colon_line_argument() {
+        let parsed = args(&["src/main.rs:42", "lib.rs:7"]).unwrap();
+        assert_eq!(parsed.files, vec!["src/main.rs", "lib.rs"]);
+        assert_eq!(parsed.line, Some(42));
+        assert_eq!(args(&["notes.txt:0"]).unwrap().line, Some(1));
+
+        assert_eq!(args(&["-", "notes.txt"]).unwrap().files, vec!["-", "notes.txt"]);
+        assert!(args(&["-", "notes.txt", "-"]).is_err());
+
+        let parsed = args(&["notes:draft"]).unwrap();
+        assert_eq!(parsed.files, vec!["notes:draft"]);
+        assert_eq!(parsed.line, None);
+        assert_eq!(args(&["notes.txt:"]).unwrap().files, vec!["notes.txt:"]);
+    }
+
+    #[test]
+    fn test_parse_read_only_flag() {
+        let parsed = args(&["-R", "notes.txt"]).unwrap();
+        assert!(parsed.read_only);
+        assert_eq!(parsed.files, vec!["notes.txt"]);
+        assert!(!args(&["notes.txt"]).unwrap().read_only);
+    }
+
+    #[test]
+    fn test_parse_insert_flag() {
+        let parsed = args(&["--insert", "notes.txt"]).unwrap();
+        assert!(parsed.insert);
+        assert_eq!(parsed.files, vec!["notes.txt"]);
+        assert!(!args(&["notes.txt"]).unwrap().insert);
+    }
+
+    #[test]
+    fn test_parse_args_errors() {
+        assert!(args(&["--exec"]).is_err());
+        assert!(args(&["--help"]).unwrap().help);
+        assert_eq!(args(&[]).unwrap(), Args::default());
+    }
+
+    #[test]
+    fn test_every_file_argument_gets_a_buffer() {
+        let dir = tempfile::tempdir().unwrap();
+        let one = dir.path().join("one.txt");
+        let two = dir.path().join("two.txt");
+        std::fs::write(&one, "first").unwrap();
+        std::fs::write(&two, "second").unwrap();
+        let files = vec![one.to_string_lossy().into_owned(), two.to_string_lossy().into_owned()];
+        let mut editor = open_editor(&Args { files: files.clone(), ..Default::default() }).unwrap();
+        assert_eq!(editor.buffer.display_name(), files[0]);
+        editor.apply_action(editor::Actions::NextBuffer);
+        assert_eq!(editor.buffer.lines, vec!["second"]);
+    }
 }
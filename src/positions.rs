@@ -0,0 +1,135 @@
+//! Where the cursor was left in each file, kept in `~/.vix/positions.json`
+//! as a list of `{"file", "line", "col"}` entries, most recent first.
+
+use std::path::{Path, PathBuf};
+
+use log::{debug, warn};
+use serde_json::{json, Value};
+
+/// Files remembered before the least recently used ones are dropped.
+const MAX_ENTRIES: usize = 200;
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Positions {
+    path: PathBuf,
+    /// Absolute file path and 0-based cursor line and column.
+    entries: Vec<(String, usize, usize)>,
+}
+
+/// The key a file is remembered under: its absolute path.
+fn key(file: &str) -> String {
+    std::fs::canonicalize(file)
+        .or_else(|_| std::path::absolute(file))
+        .map(|path| path.to_string_lossy().into_owned())
+        .unwrap_or_else(|_| file.to_string())
+}
+
+impl Positions {
+    /// Reads the state file. A missing one starts empty; an unreadable or
+    /// corrupt one is logged and also starts empty.
+    pub fn load(path: &Path) -> Self {
+        let mut positions = Self { path: path.to_path_buf(), entries: Vec::new() };
+        if !path.exists() {
+            debug!("No positions file at {:?}", path);
+            return positions;
+        }
+        let parsed = std::fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| serde_json::from_str::<Value>(&text).map_err(|e| e.to_string()));
+        match parsed {
+            Ok(Value::Array(entries)) => {
+                positions.entries = entries
+                    .iter()
+                    .filter_map(|entry| {
+                        let file = entry.get("file")?.as_str()?;
+                        let line = entry.get("line")?.as_u64()?;
+                        let col = entry.get("col")?.as_u64()?;
+                        Some((file.to_string(), line as usize, col as usize))
+                    })
+                    .take(MAX_ENTRIES)
+                    .collect();
+            }
+            Ok(_) => warn!("Ignoring positions file {:?}: not a list", path),
+            Err(e) => warn!("Ignoring positions file {:?}: {}", path, e),
+        }
+        positions
+    }
+
+    /// The remembered `(line, col)` for a file.
+    pub fn get(&self, file: &str) -> Option<(usize, usize)> {
+        let file = key(file);
+        self.entries.iter().find(|(f, _, _)| *f == file).map(|&(_, line, col)| (line, col))
+    }
+
+    /// Remembers a position, making the file the most recent entry.
+    pub fn set(&mut self, file: &str, (line, col): (usize, usize)) {
+        let file = key(file);
+        self.entries.retain(|(f, _, _)| *f != file);
+        self.entries.insert(0, (file, line, col));
+        self.entries.truncate(MAX_ENTRIES);
+    }
+
+    pub fn save(&self) {
+        let entries: Vec<Value> = self
+            .entries
+            .iter()
+            .map(|(file, line, col)| json!({ "file": file, "line": line, "col": col }))
+            .collect();
+        let written = self
+            .path
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|_| std::fs::write(&self.path, Value::Array(entries).to_string()));
+        match written {
+            Ok(()) => debug!("Saved {} positions to {:?}", self.entries.len(), self.path),
+            Err(e) => warn!("Could not save positions to {:?}: {}", self.path, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_positions_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("notes.txt");
+        std::fs::write(&file, "text").unwrap();
+        let file = file.to_string_lossy().into_owned();
+        let state = dir.path().join("state").join("positions.json");
+
+        let mut positions = Positions::load(&state);
+        assert_eq!(positions.get(&file), None);
+        positions.set(&file, (3, 4));
+        positions.save();
+
+        let loaded = Positions::load(&state);
+        assert_eq!(loaded.get(&file), Some((3, 4)));
+        assert_eq!(loaded, positions);
+    }
+
+    #[test]
+    fn test_oldest_entries_are_dropped() {
+        let mut positions = Positions::default();
+        for n in 0..MAX_ENTRIES + 5 {
+            positions.set(&format!("/tmp/file{}", n), (n, 0));
+        }
+        positions.set("/tmp/file10", (1, 1));
+        assert_eq!(positions.entries.len(), MAX_ENTRIES);
+        assert_eq!(positions.entries[0].0, "/tmp/file10");
+        assert_eq!(positions.get("/tmp/file4"), None);
+        assert_eq!(positions.get("/tmp/file5"), Some((5, 0)));
+    }
+
+    #[test]
+    fn test_corrupt_file_is_ignored() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = dir.path().join("positions.json");
+        std::fs::write(&state, "{not json").unwrap();
+        assert!(Positions::load(&state).entries.is_empty());
+
+        std::fs::write(&state, r#"[{"file": "/a", "line": 1, "col": 2}, {"file": 3}]"#).unwrap();
+        assert_eq!(Positions::load(&state).get("/a"), Some((1, 2)));
+    }
+}
@@ -0,0 +1,233 @@
+use crossterm::cursor::MoveTo;
+use crossterm::style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor};
+use crossterm::QueueableCommand;
+use std::io::{self, Write};
+
+use crate::buffer::char_width;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cell {
+    pub ch: char,
+    pub fg: Option<Color>,
+    pub bg: Option<Color>,
+}
+
+/// Stands in the cell covered by the right half of a wide character. It is
+/// never printed: the terminal fills it when the wide character is drawn.
+pub const WIDE_CONTINUATION: char = '\0';
+
+impl Default for Cell {
+    fn default() -> Self {
+        Self { ch: ' ', fg: None, bg: None }
+    }
+}
+
+/// An in-memory grid holding one fully composed screen.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+    width: u16,
+    height: u16,
+    cells: Vec<Cell>,
+}
+
+impl Frame {
+    pub fn new(width: u16, height: u16) -> Self {
+        Self {
+            width,
+            height,
+            cells: vec![Cell::default(); width as usize * height as usize],
+        }
+    }
+
+    pub fn width(&self) -> u16 {
+        self.width
+    }
+
+    pub fn height(&self) -> u16 {
+        self.height
+    }
+
+    pub fn get(&self, x: u16, y: u16) -> Option<&Cell> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        self.cells.get(y as usize * self.width as usize + x as usize)
+    }
+
+    pub fn set(&mut self, x: u16, y: u16, cell: Cell) {
+        if x < self.width && y < self.height {
+            self.cells[y as usize * self.width as usize + x as usize] = cell;
+        }
+    }
+
+    /// Writes `text` starting at `(x, y)`, clipped at the right edge. A wide
+    /// character takes two cells; one that would straddle the edge is
+    /// replaced by a space. Returns the column just past the last cell written.
+    pub fn put_str(&mut self, x: u16, y: u16, text: &str, fg: Option<Color>, bg: Option<Color>) -> u16 {
+        let mut col = x;
+        for ch in text.chars() {
+            if col >= self.width {
+                break;
+            }
+            let ch = if char_width(ch) > 1 && col + 1 >= self.width { ' ' } else { ch };
+            let width = char_width(ch) as u16;
+            self.split_wide_at(col, y);
+            self.split_wide_at(col + width, y);
+            self.set(col, y, Cell { ch, fg, bg });
+            if width > 1 {
+                self.set(col + 1, y, Cell { ch: WIDE_CONTINUATION, fg, bg });
+            }
+            col += width;
+        }
+        col
+    }
+
+    /// Blanks whatever wide character straddles the boundary just before `x`,
+    /// so no half of one is left behind when a neighbouring cell is rewritten.
+    fn split_wide_at(&mut self, x: u16, y: u16) {
+        if x == 0 || self.get(x, y).is_none_or(|cell| cell.ch != WIDE_CONTINUATION) {
+            return;
+        }
+        for col in [x - 1, x] {
+            if let Some(&cell) = self.get(col, y) {
+                self.set(col, y, Cell { ch: ' ', ..cell });
+            }
+        }
+    }
+
+    pub fn fill_row(&mut self, y: u16, cell: Cell) {
+        for x in 0..self.width {
+            self.set(x, y, cell);
+        }
+    }
+
+    /// The characters of row `y`, for asserting on composed frames.
+    #[cfg(test)]
+    pub fn row_text(&self, y: u16) -> String {
+        (0..self.width)
+            .filter_map(|x| self.get(x, y).map(|c| c.ch))
+            .filter(|&ch| ch != WIDE_CONTINUATION)
+            .collect()
+    }
+
+    /// Cells that differ from `previous`, in row-major order. Without a
+    /// comparable previous frame (none yet, or a different size) every cell
+    /// counts as changed.
+    pub fn diff(&self, previous: Option<&Frame>) -> Vec<(u16, u16, Cell)> {
+        let previous = previous.filter(|p| p.width == self.width && p.height == self.height);
+        let mut changes = Vec::new();
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let cell = self.cells[y as usize * self.width as usize + x as usize];
+                if previous.and_then(|p| p.get(x, y)) != Some(&cell) {
+                    changes.push((x, y, cell));
+                }
+            }
+        }
+        changes
+    }
+}
+
+/// Emits the given changed cells, batching runs of adjacent cells that share
+/// colours into a single print.
+pub fn write_changes(out: &mut impl Write, changes: &[(u16, u16, Cell)]) -> io::Result<()> {
+    let mut cursor: Option<(u16, u16)> = None;
+    let mut pen: (Option<Color>, Option<Color>) = (None, None);
+    let mut run = String::new();
+
+    for &(x, y, cell) in changes {
+        let contiguous = cursor == Some((x, y));
+        if cell.ch == WIDE_CONTINUATION {
+            // already drawn along with the wide character to its left
+            if contiguous {
+                cursor = Some((x + 1, y));
+            }
+            continue;
+        }
+        let style = (cell.fg, cell.bg);
+        if !contiguous || style != pen {
+            if !run.is_empty() {
+                out.queue(Print(&run))?;
+                run.clear();
+            }
+            if !contiguous {
+                out.queue(MoveTo(x, y))?;
+            }
+            if style != pen {
+                out.queue(ResetColor)?;
+                if let Some(fg) = cell.fg {
+                    out.queue(SetForegroundColor(fg))?;
+                }
+                if let Some(bg) = cell.bg {
+                    out.queue(SetBackgroundColor(bg))?;
+                }
+                pen = style;
+            }
+        }
+        run.push(cell.ch);
+        cursor = Some((x + 1, y));
+    }
+    if !run.is_empty() {
+        out.queue(Print(&run))?;
+    }
+    if pen != (None, None) {
+        out.queue(ResetColor)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_put_str_clips_at_width() {
+        let mut frame = Frame::new(4, 1);
+        assert_eq!(frame.put_str(1, 0, "abcdef", None, None), 4);
+        assert_eq!(frame.row_text(0), " abc");
+    }
+
+    #[test]
+    fn test_put_str_gives_wide_characters_two_cells() {
+        let mut frame = Frame::new(6, 1);
+        assert_eq!(frame.put_str(0, 0, "a日本", None, None), 5);
+        assert_eq!(frame.get(2, 0).unwrap().ch, WIDE_CONTINUATION);
+        assert_eq!(frame.row_text(0), "a日本 ");
+        assert_eq!(frame.put_str(2, 0, "x", None, None), 3);
+        assert_eq!(frame.row_text(0), "a x本 ");
+        assert_eq!(frame.put_str(5, 0, "日", None, None), 6);
+        assert_eq!(frame.row_text(0), "a x本 ");
+
+        let mut out = Vec::new();
+        write_changes(&mut out, &frame.diff(None)).unwrap();
+        let written = String::from_utf8(out).unwrap();
+        assert!(written.contains("a x本 "));
+        assert!(!written.contains('\0'));
+    }
+
+    #[test]
+    fn test_diff_only_reports_changed_cells() {
+        let mut before = Frame::new(5, 2);
+        before.put_str(0, 0, "hello", None, None);
+        let mut after = before.clone();
+        after.put_str(1, 1, "x", Some(Color::Red), None);
+
+        let changes = after.diff(Some(&before));
+        assert_eq!(changes, vec![(1, 1, Cell { ch: 'x', fg: Some(Color::Red), bg: None })]);
+        assert_eq!(after.diff(None).len(), 10);
+        assert_eq!(after.diff(Some(&Frame::new(4, 2))).len(), 10);
+    }
+
+    #[test]
+    fn test_write_changes_batches_adjacent_cells() {
+        let mut frame = Frame::new(6, 1);
+        frame.put_str(0, 0, "ab", None, None);
+        frame.put_str(4, 0, "cd", None, None);
+        let mut out = Vec::new();
+        write_changes(&mut out, &frame.diff(Some(&Frame::new(6, 1)))).unwrap();
+        let written = String::from_utf8(out).unwrap();
+        assert!(written.contains("ab"));
+        assert!(written.contains("cd"));
+        assert!(!written.contains("ab  cd"));
+    }
+}
@@ -0,0 +1,105 @@
+//! Swap files that mark a file as open in an editor: `.<name>.swp` next to
+//! the file, holding the editing process's pid and when it last wrote it.
+
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::{debug, warn};
+
+/// What to do about a swap file left by another process, as answered at
+/// the prompt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwapChoice {
+    ReadOnly,
+    EditAnyway,
+    /// Only offered when the process that wrote it is gone.
+    Delete,
+    Quit,
+}
+
+/// The process named in an existing swap file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SwapOwner {
+    pub pid: u32,
+    /// Whether that process is still running; a dead one left a stale swap.
+    pub alive: bool,
+}
+
+pub fn swap_path(file: &str) -> PathBuf {
+    let path = Path::new(file);
+    let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    path.with_file_name(format!(".{}.swp", name))
+}
+
+/// Who holds the swap file for `file`, if there is one. A swap file that
+/// can't be read or names no pid is treated as stale.
+pub fn owner(file: &str) -> Option<SwapOwner> {
+    let text = std::fs::read_to_string(swap_path(file)).ok()?;
+    let pid = text.lines().next().and_then(|line| line.trim().parse().ok());
+    Some(match pid {
+        Some(pid) => SwapOwner { pid, alive: is_running(pid) },
+        None => SwapOwner { pid: 0, alive: false },
+    })
+}
+
+#[cfg(target_os = "linux")]
+fn is_running(pid: u32) -> bool {
+    Path::new("/proc").join(pid.to_string()).exists()
+}
+
+/// Without `/proc` there is no cheap check, so every owner counts as live.
+#[cfg(not(target_os = "linux"))]
+fn is_running(_pid: u32) -> bool {
+    true
+}
+
+/// Writes this process's swap file for `file`, or refreshes its timestamp.
+pub fn write(file: &str) {
+    let path = swap_path(file);
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+    match std::fs::write(&path, format!("{}\n{}\n", std::process::id(), now)) {
+        Ok(()) => debug!("Wrote swap file {:?}", path),
+        Err(e) => warn!("Could not write swap file {:?}: {}", path, e),
+    }
+}
+
+pub fn remove(file: &str) {
+    let path = swap_path(file);
+    match std::fs::remove_file(&path) {
+        Ok(()) => debug!("Removed swap file {:?}", path),
+        Err(e) => warn!("Could not remove swap file {:?}: {}", path, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_swap_file_is_written_and_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("notes.txt").display().to_string();
+        assert_eq!(swap_path(&file), dir.path().join(".notes.txt.swp"));
+        assert_eq!(owner(&file), None);
+
+        write(&file);
+        assert_eq!(owner(&file), Some(SwapOwner { pid: std::process::id(), alive: true }));
+        remove(&file);
+        assert!(!swap_path(&file).exists());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_swap_from_dead_process_is_stale() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("notes.txt").display().to_string();
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        let pid = child.id();
+        child.wait().unwrap();
+
+        std::fs::write(swap_path(&file), format!("{}\n0\n", pid)).unwrap();
+        assert_eq!(owner(&file), Some(SwapOwner { pid, alive: false }));
+        std::fs::write(swap_path(&file), "garbage").unwrap();
+        assert_eq!(owner(&file), Some(SwapOwner { pid: 0, alive: false }));
+    }
+}
@@ -0,0 +1,225 @@
+//! Line-based syntax highlighting.
+//!
+//! Each line is tokenized on its own, so constructs that span lines (block
+//! comments, multi-line strings) are only coloured on the line they start.
+
+use crossterm::style::Color;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TokenKind {
+    Keyword,
+    String,
+    Comment,
+    Number,
+}
+
+impl TokenKind {
+    pub fn color(self) -> Color {
+        match self {
+            TokenKind::Keyword => Color::Blue,
+            TokenKind::String => Color::Green,
+            TokenKind::Comment => Color::DarkGrey,
+            TokenKind::Number => Color::Yellow,
+        }
+    }
+}
+
+/// A highlighted run of chars, `start..end` in char columns.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub kind: TokenKind,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Language {
+    Rust,
+    /// Strings and numbers only, for files we have no rules for.
+    Generic,
+}
+
+const RUST_KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub",
+    "ref", "return", "self", "Self", "static", "struct", "super", "trait", "true", "type", "unsafe",
+    "use", "where", "while",
+];
+
+impl Language {
+    pub fn from_path(path: Option<&str>) -> Self {
+        match path.and_then(|p| std::path::Path::new(p).extension()).and_then(|e| e.to_str()) {
+            Some("rs") => Language::Rust,
+            _ => Language::Generic,
+        }
+    }
+
+    /// The file type's name, for the status bar; `None` when unknown.
+    pub fn name(self) -> Option<&'static str> {
+        match self {
+            Language::Rust => Some("rust"),
+            Language::Generic => None,
+        }
+    }
+
+    fn keywords(self) -> &'static [&'static str] {
+        match self {
+            Language::Rust => RUST_KEYWORDS,
+            Language::Generic => &[],
+        }
+    }
+
+    fn line_comment(self) -> Option<&'static str> {
+        match self {
+            Language::Rust => Some("//"),
+            Language::Generic => None,
+        }
+    }
+}
+
+/// How files of a type are conventionally indented.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IndentStyle {
+    pub tab_width: usize,
+    /// Indent with spaces rather than tab characters.
+    pub expand_tab: bool,
+}
+
+/// The indentation usual for the file at `path`, judged by its extension.
+/// `None` for unknown types, which keep the editor's defaults.
+pub fn indent_style(path: Option<&str>) -> Option<IndentStyle> {
+    let path = std::path::Path::new(path?);
+    let extension = path.extension().and_then(|e| e.to_str());
+    let spaces = |tab_width| Some(IndentStyle { tab_width, expand_tab: true });
+    let tabs = |tab_width| Some(IndentStyle { tab_width, expand_tab: false });
+    match extension {
+        Some("rs" | "py" | "c" | "h" | "cpp" | "hpp" | "java" | "toml" | "md") => spaces(4),
+        Some("js" | "jsx" | "ts" | "tsx" | "json" | "yaml" | "yml" | "html" | "css" | "rb" | "lua") => spaces(2),
+        Some("go") => tabs(4),
+        _ if path.file_name().is_some_and(|name| name == "Makefile") => tabs(8),
+        _ => None,
+    }
+}
+
+fn starts_with_at(chars: &[char], at: usize, prefix: &str) -> bool {
+    prefix.chars().enumerate().all(|(i, p)| chars.get(at + i) == Some(&p))
+}
+
+/// Splits `line` into highlighted spans, in order and without overlaps.
+pub fn highlight_line(line: &str, language: Language) -> Vec<Span> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut spans = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        if language.line_comment().is_some_and(|prefix| starts_with_at(&chars, i, prefix)) {
+            spans.push(Span { start, end: chars.len(), kind: TokenKind::Comment });
+            break;
+        }
+        if c == '"' || (c == '\'' && language == Language::Generic) || is_char_literal(&chars, i, language) {
+            i += 1;
+            while i < chars.len() && chars[i] != c {
+                i += if chars[i] == '\\' { 2 } else { 1 };
+            }
+            i = (i + 1).min(chars.len());
+            spans.push(Span { start, end: i, kind: TokenKind::String });
+        } else if c.is_alphanumeric() || c == '_' {
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            let word: String = chars[start..i].iter().collect();
+            if c.is_ascii_digit() {
+                spans.push(Span { start, end: i, kind: TokenKind::Number });
+            } else if language.keywords().contains(&word.as_str()) {
+                spans.push(Span { start, end: i, kind: TokenKind::Keyword });
+            }
+        } else {
+            i += 1;
+        }
+    }
+    spans
+}
+
+/// In Rust a `'` starts a char literal (`'a'`, `'\n'`) but also a lifetime
+/// (`'a`); only the former is highlighted as a string.
+fn is_char_literal(chars: &[char], at: usize, language: Language) -> bool {
+    if language != Language::Rust || chars[at] != '\'' {
+        return false;
+    }
+    match chars.get(at + 1) {
+        Some('\\') => true,
+        Some(_) => chars.get(at + 2) == Some(&'\''),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(line: &str, language: Language) -> Vec<(String, TokenKind)> {
+        let chars: Vec<char> = line.chars().collect();
+        highlight_line(line, language)
+            .into_iter()
+            .map(|s| (chars[s.start..s.end].iter().collect(), s.kind))
+            .collect()
+    }
+
+    #[test]
+    fn test_indent_style_from_extension() {
+        let style = |path| indent_style(Some(path)).map(|s: IndentStyle| (s.tab_width, s.expand_tab));
+        assert_eq!(style("src/main.rs"), Some((4, true)));
+        assert_eq!(style("package.json"), Some((2, true)));
+        assert_eq!(style("ci.yaml"), Some((2, true)));
+        assert_eq!(style("main.go"), Some((4, false)));
+        assert_eq!(style("build/Makefile"), Some((8, false)));
+        assert_eq!(style("notes.txt"), None);
+        assert_eq!(style("README"), None);
+        assert_eq!(indent_style(None), None);
+    }
+
+    #[test]
+    fn test_rust_line() {
+        let spans = kinds(r#"let s = "a \"b\""; // 42 fn"#, Language::Rust);
+        assert_eq!(
+            spans,
+            vec![
+                ("let".to_string(), TokenKind::Keyword),
+                (r#""a \"b\"""#.to_string(), TokenKind::String),
+                ("// 42 fn".to_string(), TokenKind::Comment),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rust_char_literals_and_lifetimes() {
+        let spans = kinds("fn f<'a>(c: char) { '\\n'; 'x'; 10u8 }", Language::Rust);
+        assert_eq!(
+            spans,
+            vec![
+                ("fn".to_string(), TokenKind::Keyword),
+                ("'\\n'".to_string(), TokenKind::String),
+                ("'x'".to_string(), TokenKind::String),
+                ("10u8".to_string(), TokenKind::Number),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_generic_language_has_no_keywords_or_comments() {
+        let spans = kinds("let x = 'hi' // 3", Language::Generic);
+        assert_eq!(
+            spans,
+            vec![("'hi'".to_string(), TokenKind::String), ("3".to_string(), TokenKind::Number)]
+        );
+        assert_eq!(kinds("\"unterminated", Language::Generic).len(), 1);
+    }
+
+    #[test]
+    fn test_language_from_path() {
+        assert_eq!(Language::from_path(Some("src/main.rs")), Language::Rust);
+        assert_eq!(Language::from_path(Some("notes.txt")), Language::Generic);
+        assert_eq!(Language::from_path(None), Language::Generic);
+    }
+}
@@ -0,0 +1,283 @@
+use log::debug;
+use std::time::{Duration, Instant};
+
+/// Most changes kept before the oldest ones are dropped.
+const HISTORY_LIMIT: usize = 1000;
+
+/// One edit to a buffer's lines, as the buffer notes it: `removed` were the
+/// lines at `start`, and `inserted` lines took their place.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Splice {
+    pub start: usize,
+    pub removed: Vec<String>,
+    pub inserted: usize,
+}
+
+impl Splice {
+    /// Takes the edit back out of `lines`, returning the splice that puts it
+    /// in again.
+    fn revert(self, lines: &mut Vec<String>) -> Splice {
+        let inserted = self.removed.len();
+        let removed = lines.splice(self.start..self.start + self.inserted, self.removed).collect();
+        Splice { start: self.start, removed, inserted }
+    }
+}
+
+/// How far `:earlier` and `:later` go: a number of changes, or a length of
+/// time from when the current state was made.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UndoSpan {
+    Changes(usize),
+    Time(Duration),
+}
+
+/// One undoable change: the edits made while it was open, oldest first.
+#[derive(Debug)]
+struct Change {
+    /// Tells changes apart, so the one the file was saved after is known.
+    id: u64,
+    edits: Vec<Splice>,
+    cursor_before: (usize, usize),
+    cursor_after: (usize, usize),
+    /// When the change was committed.
+    time: Instant,
+}
+
+impl Change {
+    /// Reverts every edit, newest first, leaving the change holding the
+    /// edits that take it the other way.
+    fn revert(&mut self, lines: &mut Vec<String>) {
+        let edits = std::mem::take(&mut self.edits);
+        self.edits = edits.into_iter().rev().map(|edit| edit.revert(lines)).collect();
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct UndoHistory {
+    undo: Vec<Change>,
+    redo: Vec<Change>,
+    /// The cursor when the open change started, kept until it is committed
+    /// so a whole insert session can become a single undo step.
+    pending: Option<(usize, usize)>,
+    /// The last id handed to a change.
+    last_id: u64,
+    /// The id of the newest applied change when the file was last saved; 0
+    /// before any change.
+    saved: u64,
+}
+
+impl UndoHistory {
+    /// Opens a change at `cursor`, unless one is already open.
+    pub fn begin(&mut self, cursor: (usize, usize)) {
+        self.pending.get_or_insert(cursor);
+    }
+
+    /// Closes the open change as one undo step made of `edits`, the ones the
+    /// buffer noted since the last commit, made at `now`.
+    pub fn commit(&mut self, edits: Vec<Splice>, cursor: (usize, usize), now: Instant) {
+        let cursor_before = self.pending.take().unwrap_or(cursor);
+        if edits.is_empty() {
+            return;
+        }
+        self.last_id += 1;
+        debug!("Recorded change {} of {} edits", self.last_id, edits.len());
+        self.undo.push(Change { id: self.last_id, edits, cursor_before, cursor_after: cursor, time: now });
+        if self.undo.len() > HISTORY_LIMIT {
+            self.undo.remove(0);
+        }
+        self.redo.clear();
+    }
+
+    /// Reverts the most recent change, returning the cursor to restore.
+    pub fn undo(&mut self, lines: &mut Vec<String>) -> Option<(usize, usize)> {
+        let mut change = self.undo.pop()?;
+        change.revert(lines);
+        let cursor = change.cursor_before;
+        self.redo.push(change);
+        Some(cursor)
+    }
+
+    /// Re-applies the most recently undone change, returning the cursor to restore.
+    pub fn redo(&mut self, lines: &mut Vec<String>) -> Option<(usize, usize)> {
+        let mut change = self.redo.pop()?;
+        change.revert(lines);
+        let cursor = change.cursor_after;
+        self.undo.push(change);
+        Some(cursor)
+    }
+
+    /// Undoes changes back through `span`, returning how many were undone
+    /// and the cursor to restore, or `None` when there were none.
+    pub fn earlier(&mut self, span: UndoSpan, lines: &mut Vec<String>) -> Option<(usize, (usize, usize))> {
+        let count = match span {
+            UndoSpan::Changes(count) => count,
+            UndoSpan::Time(time) => {
+                let target = self.undo.last()?.time.checked_sub(time);
+                self.undo.iter().rev().take_while(|change| target.is_none_or(|t| change.time > t)).count()
+            }
+        };
+        let cursors: Vec<_> = (0..count).map_while(|_| self.undo(lines)).collect();
+        Some((cursors.len(), *cursors.last()?))
+    }
+
+    /// Redoes changes forward through `span`, returning how many were redone
+    /// and the cursor to restore, or `None` when there were none. Before any
+    /// change, time counts from the first one undone.
+    pub fn later(&mut self, span: UndoSpan, lines: &mut Vec<String>) -> Option<(usize, (usize, usize))> {
+        let count = match span {
+            UndoSpan::Changes(count) => count,
+            UndoSpan::Time(time) => {
+                let target = self.undo.last().or(self.redo.last())?.time.checked_add(time);
+                self.redo.iter().rev().take_while(|change| target.is_none_or(|t| change.time <= t)).count()
+            }
+        };
+        let cursors: Vec<_> = (0..count).map_while(|_| self.redo(lines)).collect();
+        Some((cursors.len(), *cursors.last()?))
+    }
+
+    /// Notes that the file now holds the buffer as it is. Saved halfway
+    /// through an open change, that is a state no undo step returns to.
+    pub fn mark_saved(&mut self) {
+        self.saved = if self.pending.is_some() {
+            self.last_id += 1;
+            self.last_id
+        } else {
+            self.current()
+        };
+    }
+
+    /// Whether undo or redo has brought the buffer back to what was saved.
+    pub fn is_saved(&self) -> bool {
+        self.current() == self.saved
+    }
+
+    /// The id of the newest applied change, or 0.
+    fn current(&self) -> u64 {
+        self.undo.last().map_or(0, |change| change.id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|s| s.to_string()).collect()
+    }
+
+    /// Replaces line `index` of `text` with `line`, returning the splice a
+    /// buffer would note for it.
+    fn set_line(text: &mut [String], index: usize, line: &str) -> Splice {
+        let removed = vec![std::mem::replace(&mut text[index], line.to_string())];
+        Splice { start: index, removed, inserted: 1 }
+    }
+
+    #[test]
+    fn test_undo_and_redo_restore_lines_and_cursor() {
+        let mut history = UndoHistory::default();
+        let mut text = lines(&["a", "b", "c"]);
+        history.begin((0, 1));
+        let changed = set_line(&mut text, 1, "B");
+        text.insert(2, "new".to_string());
+        let added = Splice { start: 2, removed: Vec::new(), inserted: 1 };
+        history.commit(vec![changed, added], (0, 2), Instant::now());
+
+        assert_eq!(history.undo(&mut text), Some((0, 1)));
+        assert_eq!(text, lines(&["a", "b", "c"]));
+        assert_eq!(history.redo(&mut text), Some((0, 2)));
+        assert_eq!(text, lines(&["a", "B", "new", "c"]));
+    }
+
+    #[test]
+    fn test_unchanged_commit_records_nothing() {
+        let mut history = UndoHistory::default();
+        let mut text = lines(&["same"]);
+        history.begin((0, 0));
+        history.commit(Vec::new(), (0, 0), Instant::now());
+        assert_eq!(history.undo(&mut text), None);
+    }
+
+    #[test]
+    fn test_begin_keeps_the_cursor_of_the_open_change() {
+        let mut history = UndoHistory::default();
+        let mut text = lines(&[""]);
+        history.begin((0, 0));
+        let first = set_line(&mut text, 0, "a");
+        history.begin((0, 1));
+        let second = set_line(&mut text, 0, "ab");
+        history.commit(vec![first, second], (0, 2), Instant::now());
+
+        assert_eq!(history.undo(&mut text), Some((0, 0)));
+        assert_eq!(text, lines(&[""]));
+    }
+
+    #[test]
+    fn test_new_change_clears_redo() {
+        let mut history = UndoHistory::default();
+        let mut text = lines(&["x"]);
+        history.begin((0, 0));
+        let edit = set_line(&mut text, 0, "y");
+        history.commit(vec![edit], (0, 0), Instant::now());
+        history.undo(&mut text);
+
+        history.begin((0, 0));
+        let edit = set_line(&mut text, 0, "z");
+        history.commit(vec![edit], (0, 0), Instant::now());
+        assert_eq!(history.redo(&mut text), None);
+    }
+
+    #[test]
+    fn test_saved_state_is_found_again_by_undo_and_redo() {
+        let mut history = UndoHistory::default();
+        let mut text = lines(&["x"]);
+        assert!(history.is_saved());
+        let edit = set_line(&mut text, 0, "y");
+        history.commit(vec![edit], (0, 0), Instant::now());
+        assert!(!history.is_saved());
+        history.mark_saved();
+
+        history.undo(&mut text);
+        assert!(!history.is_saved());
+        history.redo(&mut text);
+        assert!(history.is_saved());
+
+        // saved partway through a change that then went on
+        history.begin((0, 0));
+        let first = set_line(&mut text, 0, "z");
+        history.mark_saved();
+        let second = set_line(&mut text, 0, "zz");
+        history.commit(vec![first, second], (0, 0), Instant::now());
+        assert!(!history.is_saved());
+        history.undo(&mut text);
+        assert!(!history.is_saved());
+        history.redo(&mut text);
+        assert!(!history.is_saved());
+    }
+
+    #[test]
+    fn test_earlier_and_later_go_by_when_changes_were_made() {
+        let mut history = UndoHistory::default();
+        let mut text = lines(&["0"]);
+        let start = Instant::now();
+        for (i, secs) in [0, 10, 60, 65].into_iter().enumerate() {
+            let edit = set_line(&mut text, 0, &(i + 1).to_string());
+            history.commit(vec![edit], (0, i), start + Duration::from_secs(secs));
+        }
+
+        // back from the change made at 65s to before 60s
+        assert_eq!(history.earlier(UndoSpan::Time(Duration::from_secs(30)), &mut text), Some((2, (0, 2))));
+        assert_eq!(text, lines(&["2"]));
+        assert_eq!(history.later(UndoSpan::Time(Duration::from_secs(50)), &mut text), Some((1, (0, 2))));
+        assert_eq!(text, lines(&["3"]));
+        assert_eq!(history.earlier(UndoSpan::Time(Duration::from_secs(3600)), &mut text), Some((3, (0, 0))));
+        assert_eq!(text, lines(&["0"]));
+        assert_eq!(history.earlier(UndoSpan::Time(Duration::from_secs(1)), &mut text), None);
+
+        // before any change, time counts from the first one
+        assert_eq!(history.later(UndoSpan::Time(Duration::from_secs(10)), &mut text), Some((2, (0, 1))));
+        assert_eq!(text, lines(&["2"]));
+        assert_eq!(history.later(UndoSpan::Changes(5), &mut text), Some((2, (0, 3))));
+        assert_eq!(text, lines(&["4"]));
+        assert_eq!(history.later(UndoSpan::Changes(1), &mut text), None);
+    }
+}